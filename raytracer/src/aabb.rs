@@ -19,24 +19,41 @@ impl AABB {
             maximum: b,
         }
     }
+    /// A box around nothing, which any box grows from.
+    pub fn empty() -> Self {
+        Self {
+            minimum: Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            maximum: Vec3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
     pub fn min(&self) -> point3 {
         self.minimum
     }
     pub fn max(&self) -> point3 {
         self.maximum
     }
-    pub fn hit(&self, ray: &Ray, mut tmin: f64, mut tmax: f64) -> bool {
+    pub fn centroid(&self) -> point3 {
+        (self.minimum + self.maximum) * 0.5
+    }
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        let (x, y, z) = (d.x.max(0.0), d.y.max(0.0), d.z.max(0.0));
+        2.0 * (x * y + y * z + z * x)
+    }
+    pub fn hit(&self, ray: &Ray, tmin: f64, tmax: f64) -> bool {
+        let inv_dir = Vec3::new(1.0 / ray.dir.x, 1.0 / ray.dir.y, 1.0 / ray.dir.z);
+        self.hit_inv(&ray.orig, &inv_dir, tmin, tmax)
+    }
+
+    /// Slab test with the reciprocal of the ray direction worked out by
+    /// the caller, so it is shared by every box the ray is tested against.
+    pub fn hit_inv(&self, orig: &point3, inv_dir: &Vec3, mut tmin: f64, mut tmax: f64) -> bool {
         for idx in 0..3 {
-            let t0 = Vec3::fmin(
-                (self.minimum.get(idx) - ray.orig.get(idx)) / ray.dir.get(idx),
-                (self.maximum.get(idx) - ray.orig.get(idx)) / ray.dir.get(idx),
-            );
-            let t1 = Vec3::fmax(
-                (self.minimum.get(idx) - ray.orig.get(idx)) / ray.dir.get(idx),
-                (self.maximum.get(idx) - ray.orig.get(idx)) / ray.dir.get(idx),
-            );
-            tmin = Vec3::fmax(t0, tmin);
-            tmax = Vec3::fmin(t1, tmax);
+            let inv_d = inv_dir.get(idx);
+            let near = (self.minimum.get(idx) - orig.get(idx)) * inv_d;
+            let far = (self.maximum.get(idx) - orig.get(idx)) * inv_d;
+            tmin = Vec3::fmax(Vec3::fmin(near, far), tmin);
+            tmax = Vec3::fmin(Vec3::fmax(near, far), tmax);
             if tmax <= tmin {
                 return false;
             }
@@ -1,7 +1,7 @@
 use crate::aabb::*;
-use crate::aarect::*;
 use crate::hittable::*;
 use crate::material::*;
+use crate::quad::*;
 use crate::ray::*;
 
 use std::sync::Arc;
@@ -13,64 +13,44 @@ pub struct Box6 {
 }
 
 impl Box6 {
+    /// The axis-aligned box with opposite corners `p0` and `p1`.
     pub fn new(p0: &point3, p1: &point3, ptr: Arc<dyn Material>) -> Self {
-        let mut tmp = Self {
-            box_min: *p0,
-            box_max: *p1,
-            sides: HittableList::new(),
-        };
-        tmp.sides.add(Arc::new(XyRect::new(
-            p0.x,
-            p1.x,
-            p0.y,
-            p1.y,
-            p1.z,
-            ptr.clone(),
-        )));
-        tmp.sides.add(Arc::new(XyRect::new(
-            p0.x,
-            p1.x,
-            p0.y,
-            p1.y,
-            p0.z,
-            ptr.clone(),
-        )));
-
-        tmp.sides.add(Arc::new(XzRect::new(
-            p0.x,
-            p1.x,
-            p0.z,
-            p1.z,
-            p1.y,
-            ptr.clone(),
-        )));
-        tmp.sides.add(Arc::new(XzRect::new(
-            p0.x,
-            p1.x,
-            p0.z,
-            p1.z,
-            p0.y,
-            ptr.clone(),
-        )));
+        let min = point3::new(p0.x.min(p1.x), p0.y.min(p1.y), p0.z.min(p1.z));
+        let max = point3::new(p0.x.max(p1.x), p0.y.max(p1.y), p0.z.max(p1.z));
+        let d = max - min;
+        Self::oriented(
+            &min,
+            Vec3::new(d.x, 0.0, 0.0),
+            Vec3::new(0.0, d.y, 0.0),
+            Vec3::new(0.0, 0.0, d.z),
+            ptr,
+        )
+    }
 
-        tmp.sides.add(Arc::new(YzRect::new(
-            p0.y,
-            p1.y,
-            p0.z,
-            p1.z,
-            p1.x,
-            ptr.clone(),
-        )));
-        tmp.sides.add(Arc::new(YzRect::new(
-            p0.y,
-            p1.y,
-            p0.z,
-            p1.z,
-            p0.x,
-            ptr.clone(),
-        )));
+    /// The parallelepiped spanned by edges `a`, `b` and `c` from `corner`,
+    /// with every face normal pointing out.
+    pub fn oriented(corner: &point3, a: Vec3, b: Vec3, c: Vec3, ptr: Arc<dyn Material>) -> Self {
+        let (b, c) = if Vec3::cross(a, b) * c < 0.0 {
+            (c, b)
+        } else {
+            (b, c)
+        };
+        let q = *corner;
+        let mut sides = HittableList::new();
+        sides.add(Arc::new(Quad::new(q + c, a, b, ptr.clone())));
+        sides.add(Arc::new(Quad::new(q, b, a, ptr.clone())));
+        sides.add(Arc::new(Quad::new(q + b, c, a, ptr.clone())));
+        sides.add(Arc::new(Quad::new(q, a, c, ptr.clone())));
+        sides.add(Arc::new(Quad::new(q + a, b, c, ptr.clone())));
+        sides.add(Arc::new(Quad::new(q, c, b, ptr)));
 
-        tmp
+        let mut bounds = AABB::new0();
+        sides.bounding_box(0.0, 1.0, &mut bounds);
+        Self {
+            box_min: bounds.min(),
+            box_max: bounds.max(),
+            sides,
+        }
     }
 }
 
@@ -78,6 +58,9 @@ impl Hittable for Box6 {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         self.sides.hit(r, t_min, t_max, rec)
     }
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.sides.occluded(r, t_min, t_max)
+    }
     fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
         *output_box = AABB::new(self.box_min, self.box_max);
         true
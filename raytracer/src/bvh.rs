@@ -1,91 +1,299 @@
 use crate::aabb::*;
 use crate::hittable::*;
 use crate::ray::*;
-use crate::vec3::*;
-use std::ops::Deref;
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
+use threadpool::ThreadPool;
+
+/// Most primitives the builder puts in one leaf.
+pub const MAX_LEAF_SIZE: usize = 4;
+/// Candidate split planes per axis are the edges between this many bins.
+const SAH_BINS: usize = 16;
+/// Cost of visiting a node, relative to intersecting one primitive.
+pub const TRAVERSAL_COST: f64 = 0.5;
+/// Builds of at least this many primitives are spread over a pool with a
+/// worker per CPU: the top levels bin and partition in parallel, and
+/// subtrees smaller than this are built whole, one per worker.
+const PARALLEL_MIN: usize = 16384;
+
+pub enum BvhContent {
+    /// `left` holds the primitives with smaller centroids along `axis`.
+    Interior {
+        left: Box<BvhNode>,
+        right: Box<BvhNode>,
+        axis: usize,
+    },
+    Leaf(Vec<Arc<dyn Hittable>>),
+}
+
+/// Bounding volume hierarchy built with the binned surface area
+/// heuristic: at each node the primitives' centroids are sorted into bins
+/// along each axis, and the split between bins with the lowest expected
+/// cost is taken, or a leaf is made if splitting would not pay off.
 pub struct BvhNode {
-    pub left: Arc<dyn Hittable>,
-    pub right: Arc<dyn Hittable>,
     pub bvh_box: AABB,
+    pub content: BvhContent,
 }
-impl BvhNode {
-    /*pub fn new0()->Self{
-        Self{
-           left:Arc::new(HitRecord::new0()),
-           right:Arc::new(HitRecord::new0()),
-           bvh_box: AABB::new0(),
+
+struct BuildPrim {
+    object: Arc<dyn Hittable>,
+    bbox: AABB,
+    centroid: point3,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bbox: AABB,
+    count: usize,
+}
+
+/// Bins along each axis.
+type Bins = [[Bin; SAH_BINS]; 3];
+
+type SubtreeResult = (BvhNode, Vec<BuildPrim>);
+
+/// The top of a parallel build, waiting on the workers for the rest.
+enum Pending {
+    Interior {
+        bbox: AABB,
+        axis: usize,
+        left: Box<Pending>,
+        right: Box<Pending>,
+    },
+    Subtree(Receiver<SubtreeResult>),
+}
+
+impl Pending {
+    /// Waits for every subtree, appending their primitives to `order` in
+    /// leaf order.
+    fn finish(self, order: &mut Vec<BuildPrim>) -> BvhNode {
+        match self {
+            Pending::Interior {
+                bbox,
+                axis,
+                left,
+                right,
+            } => {
+                let left = left.finish(order);
+                let right = right.finish(order);
+                BvhNode {
+                    bvh_box: bbox,
+                    content: BvhContent::Interior {
+                        left: Box::new(left),
+                        right: Box::new(right),
+                        axis,
+                    },
+                }
+            }
+            Pending::Subtree(rx) => {
+                let (node, prims) = rx.recv().expect("BVH build worker failed");
+                order.extend(prims);
+                node
+            }
         }
-    }*/
+    }
+}
+
+impl BvhNode {
     pub fn from(list: &mut HittableList, time0: f64, time1: f64) -> Self {
-        let len = &mut list.objects.len();
-        Self::fromvec(&mut list.objects, 0, *len, time0, time1)
+        let len = list.objects.len();
+        Self::fromvec(&mut list.objects, 0, len, time0, time1)
     }
 
+    /// Builds over `src_objects[start..end]` and leaves that range in the
+    /// order the leaves hold it.
     pub fn fromvec(
-        src_objects: &mut Vec<Arc<dyn Hittable>>,
+        src_objects: &mut [Arc<dyn Hittable>],
         start: usize,
         end: usize,
         time0: f64,
         time1: f64,
     ) -> Self {
-        let objects = src_objects;
-        let axis = Vec3::random_int(0, 2);
+        let objects = &mut src_objects[start..end];
+        let mut prims: Vec<BuildPrim> = objects
+            .iter()
+            .map(|object| {
+                let mut bbox = AABB::new0();
+                if !object.bounding_box(time0, time1, &mut bbox) {
+                    eprintln!("No bounding box in bvh_node constructor");
+                }
+                BuildPrim {
+                    object: object.clone(),
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+        // With one CPU the pool would only add overhead.
+        let pool = if prims.len() >= PARALLEL_MIN {
+            Some(threadpool::Builder::new().build()).filter(|pool| pool.max_count() > 1)
+        } else {
+            None
+        };
+        let (node, order) = match pool {
+            Some(pool) => Self::build_parallel(prims, &pool, PARALLEL_MIN),
+            None => {
+                let node = Self::build(&mut prims);
+                (node, prims)
+            }
+        };
+        for (slot, prim) in objects.iter_mut().zip(order) {
+            *slot = prim.object;
+        }
+        node
+    }
 
-        let mut left: Arc<dyn Hittable> = objects[start].clone();
-        let mut right: Arc<dyn Hittable> = objects[start].clone();
+    fn build(prims: &mut [BuildPrim]) -> Self {
+        let (bbox, centroids) = bounds(prims);
+        let n = prims.len();
+        if n <= 1 {
+            return Self::leaf(bbox, prims);
+        }
 
-        let comparator = if axis == 0 {
-            BvhNode::box_x_compare
-        } else if axis == 1 {
-            BvhNode::box_y_compare
+        let extent = centroids.max() - centroids.min();
+        let axis = largest_axis(&extent);
+        let split = if extent.get(axis as i32) > 0.0 {
+            best_split(&bin_prims(prims, &centroids), &bbox)
         } else {
-            BvhNode::box_z_compare
+            // Every centroid is in the same place, so no plane separates
+            // them.
+            None
         };
-        let object_span = end - start;
-
-        if object_span == 1 {
-        } else if object_span == 2 {
-            if comparator(objects[start].deref(), objects[start + 1].deref()) {
-                left = objects[start].clone();
-                right = objects[start + 1].clone();
-            } else {
-                right = objects[start].clone();
-                left = objects[start + 1].clone();
+        let (axis, mid) = match split {
+            Some((_, _, cost)) if n <= MAX_LEAF_SIZE && n as f64 <= cost => {
+                return Self::leaf(bbox, prims);
             }
-        } else {
-            objects[start..end].sort_unstable_by(|a, b| {
-                if comparator(&**a, &**b) {
-                    std::cmp::Ordering::Greater
-                } else {
-                    std::cmp::Ordering::Less
-                }
-            });
+            Some((axis, bin, _)) => {
+                let (lo, size) = (centroids.min().get(axis as i32), extent.get(axis as i32));
+                let mid = partition(prims, |p| {
+                    bin_index(p.centroid.get(axis as i32), lo, size) <= bin
+                });
+                (axis, mid)
+            }
+            None if n <= MAX_LEAF_SIZE => return Self::leaf(bbox, prims),
+            None => {
+                // Too many to share a leaf: split by count instead.
+                let mid = n / 2;
+                prims.select_nth_unstable_by(mid, |a, b| {
+                    BvhNode::box_compare(&*a.object, &*b.object, axis)
+                });
+                (axis, mid)
+            }
+        };
 
-            let mid = start + object_span / 2;
-            left =
-                Arc::new(BvhNode::fromvec(objects, start, mid, time0, time1)) as Arc<dyn Hittable>;
-            right =
-                Arc::new(BvhNode::fromvec(objects, mid, end, time0, time1)) as Arc<dyn Hittable>;
+        let (left, right) = prims.split_at_mut(mid);
+        Self {
+            bvh_box: bbox,
+            content: BvhContent::Interior {
+                left: Box::new(Self::build(left)),
+                right: Box::new(Self::build(right)),
+                axis,
+            },
         }
-        let mut box_left = AABB::new0();
-        let mut box_right = AABB::new0();
+    }
 
-        if !left.bounding_box(time0, time1, &mut box_left)
-            || !right.bounding_box(time0, time1, &mut box_right)
-        {
-            eprintln!("No bounding box in bvh_node constructor");
+    fn leaf(bbox: AABB, prims: &[BuildPrim]) -> Self {
+        Self {
+            bvh_box: bbox,
+            content: BvhContent::Leaf(prims.iter().map(|p| p.object.clone()).collect()),
         }
+    }
 
-        let boxx = AABB::surrounding_box(&box_left, &box_right);
+    /// Builds on `pool`, handing out subtrees smaller than `min_size`
+    /// whole.
+    fn build_parallel(
+        prims: Vec<BuildPrim>,
+        pool: &ThreadPool,
+        min_size: usize,
+    ) -> (Self, Vec<BuildPrim>) {
+        let n = prims.len();
+        let mut subtrees = vec![];
+        let pending = Self::build_top(pool, prims, min_size, &mut subtrees);
+        // The top levels are done, so every worker is free for the
+        // subtrees. The largest go first to even out the load.
+        subtrees.sort_by_key(|(prims, _)| Reverse(prims.len()));
+        for (mut prims, tx) in subtrees {
+            pool.execute(move || {
+                let node = Self::build(&mut prims);
+                tx.send((node, prims)).expect("BVH build was abandoned");
+            });
+        }
+        let mut order = Vec::with_capacity(n);
+        let node = pending.finish(&mut order);
+        (node, order)
+    }
 
-        Self {
-            left: left.clone(),
-            right: right.clone(),
-            bvh_box: boxx,
+    /// Splits `prims` as `build` does, but with the binning and
+    /// partitioning spread over `pool`, down to subtrees smaller than
+    /// `min_size`, which are left in `subtrees` for the workers.
+    fn build_top(
+        pool: &ThreadPool,
+        prims: Vec<BuildPrim>,
+        min_size: usize,
+        subtrees: &mut Vec<(Vec<BuildPrim>, Sender<SubtreeResult>)>,
+    ) -> Pending {
+        if prims.len() < min_size {
+            let (tx, rx) = channel();
+            subtrees.push((prims, tx));
+            return Pending::Subtree(rx);
+        }
+
+        let n = prims.len();
+        let prims = Arc::new(prims);
+        let (bbox, centroids) = par_map(pool, &prims, bounds)
+            .into_iter()
+            .fold((AABB::empty(), AABB::empty()), |(a, b), (c, d)| {
+                (AABB::surrounding_box(&a, &c), AABB::surrounding_box(&b, &d))
+            });
+        let extent = centroids.max() - centroids.min();
+        let axis = largest_axis(&extent);
+        let split = if extent.get(axis as i32) > 0.0 {
+            let bins = par_map(pool, &prims, move |p| bin_prims(p, &centroids))
+                .into_iter()
+                .fold(empty_bins(), |mut all, b| {
+                    merge_bins(&mut all, &b);
+                    all
+                });
+            best_split(&bins, &bbox)
+        } else {
+            None
+        };
+        let mut prims = match Arc::try_unwrap(prims) {
+            Ok(prims) => prims,
+            Err(_) => panic!("BVH build workers still hold the primitives"),
+        };
+
+        // Far more than a leaf holds, so always split.
+        let (axis, left, right) = match split {
+            Some((axis, bin, _)) => {
+                let (lo, size) = (centroids.min().get(axis as i32), extent.get(axis as i32));
+                let (left, right) = par_partition(pool, prims, move |p| {
+                    bin_index(p.centroid.get(axis as i32), lo, size) <= bin
+                });
+                (axis, left, right)
+            }
+            None => {
+                let mid = n / 2;
+                prims.select_nth_unstable_by(mid, |a, b| {
+                    BvhNode::box_compare(&*a.object, &*b.object, axis)
+                });
+                let right = prims.split_off(mid);
+                (axis, prims, right)
+            }
+        };
+        Pending::Interior {
+            bbox,
+            axis,
+            left: Box::new(Self::build_top(pool, left, min_size, subtrees)),
+            right: Box::new(Self::build_top(pool, right, min_size, subtrees)),
         }
     }
-    pub fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> bool {
+
+    /// Orders by the low edge of the boxes along `axis`.
+    pub fn box_compare(a: &dyn Hittable, b: &dyn Hittable, axis: usize) -> Ordering {
         let mut box_a = AABB::new0();
         let mut box_b = AABB::new0();
 
@@ -93,17 +301,279 @@ impl BvhNode {
             eprintln!("No bounding box in BvhNode constructor");
         }
         let idx = axis as i32;
-        box_a.min().get(idx) < box_b.min().get(idx)
+        box_a
+            .min()
+            .get(idx)
+            .partial_cmp(&box_b.min().get(idx))
+            .unwrap_or(Ordering::Equal)
+    }
+
+    /// Size, depth and expected cost of the tree.
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            interior_nodes: 0,
+            leaves: 0,
+            primitives: 0,
+            max_depth: 0,
+            min_leaf_size: usize::MAX,
+            max_leaf_size: 0,
+            sah_cost: 0.0,
+        };
+        let root_area = self.bvh_box.surface_area();
+        let inv_root_area = if root_area > 0.0 {
+            1.0 / root_area
+        } else {
+            0.0
+        };
+        self.gather_stats(1, inv_root_area, &mut stats);
+        stats
+    }
+
+    fn gather_stats(&self, depth: usize, inv_root_area: f64, stats: &mut BvhStats) {
+        // The chance a ray through the root also passes through this box.
+        let p = self.bvh_box.surface_area() * inv_root_area;
+        stats.max_depth = stats.max_depth.max(depth);
+        match &self.content {
+            BvhContent::Interior { left, right, .. } => {
+                stats.interior_nodes += 1;
+                stats.sah_cost += TRAVERSAL_COST * p;
+                left.gather_stats(depth + 1, inv_root_area, stats);
+                right.gather_stats(depth + 1, inv_root_area, stats);
+            }
+            BvhContent::Leaf(objects) => {
+                stats.leaves += 1;
+                stats.primitives += objects.len();
+                stats.min_leaf_size = stats.min_leaf_size.min(objects.len());
+                stats.max_leaf_size = stats.max_leaf_size.max(objects.len());
+                stats.sah_cost += objects.len() as f64 * p;
+            }
+        }
+    }
+}
+
+/// Boxes around the primitives and around their centroids.
+fn bounds(prims: &[BuildPrim]) -> (AABB, AABB) {
+    prims
+        .iter()
+        .fold((AABB::empty(), AABB::empty()), |(b, c), p| {
+            (
+                AABB::surrounding_box(&b, &p.bbox),
+                AABB::surrounding_box(&c, &AABB::new(p.centroid, p.centroid)),
+            )
+        })
+}
+
+fn largest_axis(extent: &Vec3) -> usize {
+    if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn empty_bins() -> Bins {
+    [[Bin {
+        bbox: AABB::empty(),
+        count: 0,
+    }; SAH_BINS]; 3]
+}
+
+/// Sorts the primitives into bins along each axis of `centroids`.
+fn bin_prims(prims: &[BuildPrim], centroids: &AABB) -> Bins {
+    let mut bins = empty_bins();
+    for (axis, axis_bins) in bins.iter_mut().enumerate() {
+        let lo = centroids.min().get(axis as i32);
+        let size = centroids.max().get(axis as i32) - lo;
+        if size <= 0.0 {
+            continue;
+        }
+        for p in prims {
+            let b = &mut axis_bins[bin_index(p.centroid.get(axis as i32), lo, size)];
+            b.count += 1;
+            b.bbox = AABB::surrounding_box(&b.bbox, &p.bbox);
+        }
+    }
+    bins
+}
+
+fn merge_bins(all: &mut Bins, other: &Bins) {
+    for (a, b) in all.iter_mut().flatten().zip(other.iter().flatten()) {
+        a.count += b.count;
+        a.bbox = AABB::surrounding_box(&a.bbox, &b.bbox);
+    }
+}
+
+/// Cheapest split over all axes as `(axis, last bin on the left, cost)`,
+/// with the cost in units of one primitive test.
+fn best_split(bins: &Bins, bbox: &AABB) -> Option<(usize, usize, f64)> {
+    let area = bbox.surface_area();
+    let inv_area = if area > 0.0 { 1.0 / area } else { 0.0 };
+    let mut best: Option<(usize, usize, f64)> = None;
+    let mut best_cost = INFINITY;
+    for (axis, bins) in bins.iter().enumerate() {
+        // Sweep from the right to get the area and count to the right of
+        // each plane, then from the left to price each one.
+        let mut right_area = [0.0; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut acc = AABB::empty();
+        let mut count = 0;
+        for i in (1..SAH_BINS).rev() {
+            acc = AABB::surrounding_box(&acc, &bins[i].bbox);
+            count += bins[i].count;
+            right_area[i] = acc.surface_area();
+            right_count[i] = count;
+        }
+        let mut acc = AABB::empty();
+        let mut count = 0;
+        for i in 0..SAH_BINS - 1 {
+            acc = AABB::surrounding_box(&acc, &bins[i].bbox);
+            count += bins[i].count;
+            if count == 0 || right_count[i + 1] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + (count as f64 * acc.surface_area()
+                    + right_count[i + 1] as f64 * right_area[i + 1])
+                    * inv_area;
+            if cost < best_cost {
+                best_cost = cost;
+                best = Some((axis, i, cost));
+            }
+        }
     }
+    best
+}
+
+/// Runs `f` over `prims` in one chunk per worker, and returns the results
+/// in chunk order.
+fn par_map<T, F>(pool: &ThreadPool, prims: &Arc<Vec<BuildPrim>>, f: F) -> Vec<T>
+where
+    T: Send + 'static,
+    F: Fn(&[BuildPrim]) -> T + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    let chunk = chunk_size(pool, prims.len());
+    let (tx, rx) = channel();
+    let mut jobs = 0;
+    for start in (0..prims.len()).step_by(chunk) {
+        let (tx, prims, f) = (tx.clone(), prims.clone(), f.clone());
+        pool.execute(move || {
+            let end = (start + chunk).min(prims.len());
+            let result = f(&prims[start..end]);
+            // Let go before reporting, so the caller can take the
+            // primitives back as soon as every chunk is in.
+            drop(prims);
+            tx.send((start, result)).expect("BVH build was abandoned");
+        });
+        jobs += 1;
+    }
+    let mut results: Vec<(usize, T)> = rx.iter().take(jobs).collect();
+    assert_eq!(results.len(), jobs, "BVH build worker failed");
+    results.sort_by_key(|(start, _)| *start);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Splits `prims` into those matching `pred` and the rest, a chunk per
+/// worker, keeping their order.
+fn par_partition<F>(
+    pool: &ThreadPool,
+    mut prims: Vec<BuildPrim>,
+    pred: F,
+) -> (Vec<BuildPrim>, Vec<BuildPrim>)
+where
+    F: Fn(&BuildPrim) -> bool + Send + Copy + 'static,
+{
+    let n = prims.len();
+    let chunk = chunk_size(pool, n);
+    // Cut from the back so each cut only moves the piece cut off.
+    let mut chunks = vec![];
+    while prims.len() > chunk {
+        let at = prims.len() - chunk;
+        chunks.push(prims.split_off(at));
+    }
+    chunks.push(prims);
+    chunks.reverse();
+
+    let (tx, rx) = channel();
+    let jobs = chunks.len();
+    for (i, c) in chunks.into_iter().enumerate() {
+        let tx = tx.clone();
+        pool.execute(move || {
+            let (left, right): (Vec<_>, Vec<_>) = c.into_iter().partition(|p| pred(p));
+            tx.send((i, left, right)).expect("BVH build was abandoned");
+        });
+    }
+    let mut parts: Vec<_> = rx.iter().take(jobs).collect();
+    assert_eq!(parts.len(), jobs, "BVH build worker failed");
+    parts.sort_by_key(|(i, _, _)| *i);
+    let mut left = Vec::with_capacity(n);
+    let mut right = Vec::with_capacity(n);
+    for (_, l, r) in parts {
+        left.extend(l);
+        right.extend(r);
+    }
+    (left, right)
+}
+
+/// At most one chunk per worker, never empty.
+fn chunk_size(pool: &ThreadPool, n: usize) -> usize {
+    n / pool.max_count() + 1
+}
+
+/// Which of the bins over `lo..lo + size` the value falls in.
+fn bin_index(x: f64, lo: f64, size: f64) -> usize {
+    (((x - lo) / size * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
 
-    pub fn box_x_compare(a: &dyn Hittable, b: &dyn Hittable) -> bool {
-        BvhNode::box_compare(a, b, 0)
+/// Moves the items matching `pred` to the front and returns how many there
+/// are.
+fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
     }
-    pub fn box_y_compare(a: &dyn Hittable, b: &dyn Hittable) -> bool {
-        BvhNode::box_compare(a, b, 1)
+    first
+}
+
+/// Tree quality as reported by `BvhNode::stats`.
+#[derive(Clone, Copy, Debug)]
+pub struct BvhStats {
+    pub interior_nodes: usize,
+    pub leaves: usize,
+    pub primitives: usize,
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    /// Expected cost of tracing a ray that hits the root box, in units of
+    /// one primitive test.
+    pub sah_cost: f64,
+}
+
+impl BvhStats {
+    pub fn mean_leaf_size(&self) -> f64 {
+        self.primitives as f64 / self.leaves.max(1) as f64
     }
-    pub fn box_z_compare(a: &dyn Hittable, b: &dyn Hittable) -> bool {
-        BvhNode::box_compare(a, b, 2)
+}
+
+impl fmt::Display for BvhStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} primitives, {} nodes, {} leaves of {}-{} (mean {:.2}), depth {}, SAH cost {:.2}",
+            self.primitives,
+            self.interior_nodes + self.leaves,
+            self.leaves,
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.mean_leaf_size(),
+            self.max_depth,
+            self.sah_cost
+        )
     }
 }
 
@@ -116,23 +586,120 @@ impl Hittable for BvhNode {
     }
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         if !self.bvh_box.hit(&ray, t_min, t_max) {
-            false
-        } else {
-            let hit_left = self.left.hit(ray, t_min, t_max, rec);
-            let hit_right = self.right.hit(
-                ray,
-                t_min,
-                {
-                    if hit_left {
-                        rec.t
-                    } else {
-                        t_max
+            return false;
+        }
+        match &self.content {
+            BvhContent::Interior { left, right, axis } => {
+                // Visit the nearer child first so the far one is more
+                // often culled by the closer hit.
+                let (first, second) = if ray.direction().get(*axis as i32) < 0.0 {
+                    (right, left)
+                } else {
+                    (left, right)
+                };
+                let hit_first = first.hit(ray, t_min, t_max, rec);
+                let hit_second = second.hit(ray, t_min, if hit_first { rec.t } else { t_max }, rec);
+                hit_first || hit_second
+            }
+            BvhContent::Leaf(objects) => {
+                let mut closest_so_far = t_max;
+                let mut hit_anything = false;
+                for object in objects {
+                    if object.hit(ray, t_min, closest_so_far, rec) {
+                        hit_anything = true;
+                        closest_so_far = rec.t;
                     }
-                },
-                rec,
-            );
+                }
+                hit_anything
+            }
+        }
+    }
+    fn occluded(&self, ray: Ray, t_min: f64, t_max: f64) -> bool {
+        if !self.bvh_box.hit(&ray, t_min, t_max) {
+            return false;
+        }
+        match &self.content {
+            BvhContent::Interior { left, right, .. } => {
+                left.occluded(ray, t_min, t_max) || right.occluded(ray, t_min, t_max)
+            }
+            BvhContent::Leaf(objects) => objects.iter().any(|o| o.occluded(ray, t_min, t_max)),
+        }
+    }
+}
 
-            hit_left || hit_right
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    /// Appends the leaves' primitives to `out` in tree order.
+    fn leaf_order(node: &BvhNode, out: &mut Vec<Arc<dyn Hittable>>) {
+        match &node.content {
+            BvhContent::Leaf(objects) => out.extend(objects.iter().cloned()),
+            BvhContent::Interior { left, right, .. } => {
+                leaf_order(left, out);
+                leaf_order(right, out);
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_build_matches_list() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        for _i in 0..5000 {
+            let radius = random_double2(0.05, 0.3);
+            list.add(Arc::new(Sphere::new(
+                Vec3::random2(-20.0, 20.0),
+                radius,
+                mat.clone(),
+            )));
+        }
+        // A stack no plane can split.
+        for _i in 0..600 {
+            list.add(Arc::new(Sphere::new(
+                Vec3::new(1.0, 2.0, 3.0),
+                0.5,
+                mat.clone(),
+            )));
+        }
+        let prims: Vec<BuildPrim> = list
+            .objects
+            .iter()
+            .map(|object| {
+                let mut bbox = AABB::new0();
+                assert!(object.bounding_box(0.0, 1.0, &mut bbox));
+                BuildPrim {
+                    object: object.clone(),
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+
+        // A small threshold takes the parallel path several levels down.
+        let pool = ThreadPool::new(4);
+        let (bvh, order) = BvhNode::build_parallel(prims, &pool, 256);
+        assert_eq!(order.len(), list.objects.len());
+        let mut leaves = vec![];
+        leaf_order(&bvh, &mut leaves);
+        assert_eq!(leaves.len(), order.len());
+        for (a, b) in leaves.iter().zip(&order) {
+            assert!(Arc::ptr_eq(a, &b.object));
+        }
+        let stats = bvh.stats();
+        assert_eq!(stats.primitives, list.objects.len());
+        assert!(stats.max_leaf_size <= MAX_LEAF_SIZE);
+
+        for _i in 0..5000 {
+            let r = Ray::new(Vec3::random2(-25.0, 25.0), Vec3::random2(-1.0, 1.0), 0.0);
+            let mut expected = HitRecord::new0();
+            let mut rec = HitRecord::new0();
+            let hit = list.hit(r, 0.001, INFINITY, &mut expected);
+            assert_eq!(bvh.hit(r, 0.001, INFINITY, &mut rec), hit);
+            if hit {
+                assert_eq!(rec.t, expected.t);
+            }
         }
     }
 }
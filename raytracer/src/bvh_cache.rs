@@ -0,0 +1,335 @@
+use crate::hittable::*;
+use crate::linear_bvh::*;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+const MAGIC: &[u8; 8] = b"RTBVH\0\0\0";
+/// Goes up whenever the file layout or the builder changes, so files from
+/// older versions are rebuilt instead of used.
+pub const BVH_CACHE_VERSION: u32 = 1;
+
+/// Hash of what the builder sees: the number of primitives, the time span
+/// and every primitive's box, in order. Equal hashes give the same tree.
+pub fn geometry_hash(objects: &[Arc<dyn Hittable>], time0: f64, time1: f64) -> u64 {
+    // FNV-1a.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let mut add = |x: u64| {
+        for byte in x.to_le_bytes().iter() {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    add(objects.len() as u64);
+    add(time0.to_bits());
+    add(time1.to_bits());
+    for object in objects {
+        let mut b = AABB::new0();
+        add(object.bounding_box(time0, time1, &mut b) as u64);
+        for x in [b.min(), b.max()].iter() {
+            add(x.x.to_bits());
+            add(x.y.to_bits());
+            add(x.z.to_bits());
+        }
+    }
+    hash
+}
+
+/// For each of `primitives`, where it was in `originals`.
+fn primitive_order(originals: &[Arc<dyn Hittable>], primitives: &[Arc<dyn Hittable>]) -> Vec<u32> {
+    let key = |o: &Arc<dyn Hittable>| Arc::as_ptr(o) as *const u8 as usize;
+    // The same object can be in the list more than once.
+    let mut slots: HashMap<usize, Vec<u32>> = HashMap::new();
+    for (i, o) in originals.iter().enumerate().rev() {
+        slots.entry(key(o)).or_default().push(i as u32);
+    }
+    primitives
+        .iter()
+        .map(|p| slots.get_mut(&key(p)).and_then(|s| s.pop()).unwrap())
+        .collect()
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads little-endian values from the front of a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err(invalid("file is truncated"));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+impl LinearBvh {
+    /// The tree for `list`, loaded from `dir` if it was built before, or
+    /// else built and saved there. Files are named after the geometry
+    /// hash. A missing or bad file only costs a rebuild.
+    pub fn cached(list: &mut HittableList, time0: f64, time1: f64, dir: &str) -> Self {
+        let hash = geometry_hash(&list.objects, time0, time1);
+        let path = Path::new(dir).join(format!("{:016x}.bvh", hash));
+        if path.exists() {
+            match Self::load(&path, hash, &list.objects) {
+                Ok(bvh) => return bvh,
+                Err(e) => eprintln!("ignoring BVH cache {}: {}", path.display(), e),
+            }
+        }
+        let originals = list.objects.clone();
+        let bvh = Self::from(list, time0, time1);
+        let order = primitive_order(&originals, &bvh.primitives);
+        if let Err(e) = fs::create_dir_all(dir).and_then(|_| bvh.save(&path, hash, &order)) {
+            eprintln!("could not write BVH cache {}: {}", path.display(), e);
+        }
+        bvh
+    }
+
+    /// Writes the nodes and `order`, the index in the source list of each
+    /// primitive in leaf order. The file is written beside `path` and then
+    /// moved over it, so readers never see half of one.
+    pub fn save(&self, path: &Path, hash: u64, order: &[u32]) -> io::Result<()> {
+        let mut data = Vec::with_capacity(40 + order.len() * 4 + self.nodes.len() * 32);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&BVH_CACHE_VERSION.to_le_bytes());
+        data.extend_from_slice(&hash.to_le_bytes());
+        data.extend_from_slice(&(order.len() as u64).to_le_bytes());
+        data.extend_from_slice(&(self.nodes.len() as u64).to_le_bytes());
+        for i in order {
+            data.extend_from_slice(&i.to_le_bytes());
+        }
+        for node in &self.nodes {
+            data.extend_from_slice(&node.to_bytes());
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &data)?;
+        fs::rename(&tmp, path)
+    }
+
+    /// Reads a tree saved for `objects` with geometry hash `hash`.
+    pub fn load(path: &Path, hash: u64, objects: &[Arc<dyn Hittable>]) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let mut r = Reader { data: &data };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a BVH cache file"));
+        }
+        if r.u32()? != BVH_CACHE_VERSION {
+            return Err(invalid("written by another version"));
+        }
+        if r.u64()? != hash {
+            return Err(invalid("built for other geometry"));
+        }
+        let count = r.u64()? as usize;
+        let node_count = r.u64()? as usize;
+        if count != objects.len() {
+            return Err(invalid("wrong number of primitives"));
+        }
+        let length = node_count
+            .checked_mul(32)
+            .and_then(|n| n.checked_add(count * 4));
+        if length != Some(r.data.len()) {
+            return Err(invalid("wrong length"));
+        }
+
+        let mut seen = vec![false; count];
+        let mut primitives = Vec::with_capacity(count);
+        for _ in 0..count {
+            let i = r.u32()? as usize;
+            if i >= count || seen[i] {
+                return Err(invalid("primitive order is not a permutation"));
+            }
+            seen[i] = true;
+            primitives.push(objects[i].clone());
+        }
+        let mut nodes = Vec::with_capacity(node_count);
+        for _ in 0..node_count {
+            nodes.push(LinearBvhNode::from_bytes(r.take(32)?.try_into().unwrap()));
+        }
+        // Walking the tree left child first must meet every node once, in
+        // array order, and the leaves' primitives back to back. Then each
+        // node but the root has exactly one parent, links only point
+        // forwards, and the depth measured from the links is the real one,
+        // which the traversal stack is sized by.
+        let mut next_node = 0;
+        let mut next_primitive = 0;
+        let mut stack = if node_count > 0 { vec![0] } else { vec![] };
+        while let Some(i) = stack.pop() {
+            if i != next_node {
+                return Err(invalid("nodes are not in depth-first order"));
+            }
+            next_node += 1;
+            let node = &nodes[i];
+            if node.axis > 2 {
+                return Err(invalid("bad split axis"));
+            }
+            if node.is_leaf() {
+                if node.offset as usize != next_primitive {
+                    return Err(invalid("leaves are not in primitive order"));
+                }
+                next_primitive += node.count as usize;
+            } else {
+                let second = node.offset as usize;
+                if second <= i + 1 || second >= node_count {
+                    return Err(invalid("node links out of range"));
+                }
+                stack.push(second);
+                stack.push(i + 1);
+            }
+        }
+        if next_node != node_count || next_primitive != count {
+            return Err(invalid("tree does not cover the file"));
+        }
+        Ok(Self::from_parts(nodes, primitives))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+    use std::path::PathBuf;
+
+    /// Header bytes before the primitive order.
+    const HEADER: usize = 8 + 4 + 8 + 8 + 8;
+
+    fn spheres(n: usize) -> HittableList {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        for _i in 0..n {
+            list.add(Arc::new(Sphere::new(
+                Vec3::random2(-10.0, 10.0),
+                0.3,
+                mat.clone(),
+            )));
+        }
+        list
+    }
+
+    /// An empty directory of its own for each test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raytracer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Saves a tree over `list` and returns the file's path and hash.
+    fn save(list: &HittableList, dir: &Path) -> (PathBuf, u64) {
+        let hash = geometry_hash(&list.objects, 0.0, 1.0);
+        let bvh = LinearBvh::from(&mut list.clone(), 0.0, 1.0);
+        let order = primitive_order(&list.objects, &bvh.primitives);
+        let path = dir.join("tree.bvh");
+        bvh.save(&path, hash, &order).unwrap();
+        (path, hash)
+    }
+
+    fn load_with(
+        path: &Path,
+        hash: u64,
+        list: &HittableList,
+        edit: impl Fn(&mut Vec<u8>),
+    ) -> io::Result<LinearBvh> {
+        let mut data = fs::read(path).unwrap();
+        edit(&mut data);
+        let edited = path.with_extension("edited");
+        fs::write(&edited, &data).unwrap();
+        LinearBvh::load(&edited, hash, &list.objects)
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("bvh-round-trip");
+        let list = spheres(500);
+        let built = LinearBvh::from(&mut list.clone(), 0.0, 1.0);
+        let (path, hash) = save(&list, &dir);
+        let loaded = LinearBvh::load(&path, hash, &list.objects).unwrap();
+
+        assert_eq!(loaded.depth, built.depth);
+        assert_eq!(loaded.nodes.len(), built.nodes.len());
+        for (a, b) in loaded.nodes.iter().zip(&built.nodes) {
+            assert_eq!(a.to_bytes()[..], b.to_bytes()[..]);
+        }
+        for (a, b) in loaded.primitives.iter().zip(&built.primitives) {
+            assert!(Arc::ptr_eq(a, b));
+        }
+
+        // `cached` builds and saves the first time and loads the second.
+        let cache = dir.join("cache");
+        let cache = cache.to_str().unwrap();
+        let first = LinearBvh::cached(&mut list.clone(), 0.0, 1.0, cache);
+        assert_eq!(fs::read_dir(cache).unwrap().count(), 1);
+        let second = LinearBvh::cached(&mut list.clone(), 0.0, 1.0, cache);
+        assert_eq!(first.nodes.len(), second.nodes.len());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let dir = temp_dir("bvh-bad-files");
+        let list = spheres(200);
+        let (path, hash) = save(&list, &dir);
+        let len = fs::metadata(&path).unwrap().len() as usize;
+        let node = |i: usize| HEADER + list.objects.len() * 4 + i * 32;
+
+        assert!(load_with(&path, hash, &list, |_| ()).is_ok());
+        assert!(LinearBvh::load(&path, hash ^ 1, &list.objects).is_err());
+        assert!(LinearBvh::load(&path, hash, &spheres(199).objects).is_err());
+        for cut in [4, HEADER, len - 1].iter() {
+            assert!(load_with(&path, hash, &list, |d| d.truncate(*cut)).is_err());
+        }
+        assert!(load_with(&path, hash, &list, |d| d.push(0)).is_err());
+        let version = (BVH_CACHE_VERSION + 1).to_le_bytes();
+        assert!(load_with(&path, hash, &list, |d| d[8..12].copy_from_slice(&version)).is_err());
+        // A node count so large the length overflows.
+        assert!(load_with(&path, hash, &list, |d| d[28..36]
+            .copy_from_slice(&[0xff; 8]))
+        .is_err());
+        // Two primitives with the same place in the list.
+        assert!(load_with(&path, hash, &list, |d| {
+            let first = d[HEADER..HEADER + 4].to_vec();
+            d[HEADER + 4..HEADER + 8].copy_from_slice(&first)
+        })
+        .is_err());
+
+        // The root's second child pointed at the one its first child
+        // already has, so that node gets two parents.
+        let loaded = LinearBvh::load(&path, hash, &list.objects).unwrap();
+        let shared = loaded.nodes[1].offset;
+        assert!(!loaded.nodes[1].is_leaf());
+        assert!(load_with(&path, hash, &list, |d| {
+            d[node(0) + 24..node(0) + 28].copy_from_slice(&shared.to_le_bytes())
+        })
+        .is_err());
+        // Two leaves swapping primitive ranges.
+        let leaves: Vec<usize> = (0..loaded.nodes.len())
+            .filter(|i| loaded.nodes[*i].is_leaf())
+            .take(2)
+            .collect();
+        assert!(load_with(&path, hash, &list, |d| {
+            let (a, b) = (node(leaves[0]) + 24, node(leaves[1]) + 24);
+            let first = d[a..a + 6].to_vec();
+            let second = d[b..b + 6].to_vec();
+            d[a..a + 6].copy_from_slice(&second);
+            d[b..b + 6].copy_from_slice(&first);
+        })
+        .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
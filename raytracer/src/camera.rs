@@ -1,8 +1,82 @@
+pub use crate::hittable::{HitRecord, Hittable};
 pub use crate::ray::Ray;
 pub use crate::rtweekend::*;
+pub use crate::shutter::Shutter;
+pub use crate::texture::Texture;
 pub use crate::vec3::Vec3;
+use std::sync::Arc;
 pub use Vec3 as point3;
 pub use Vec3 as color;
+
+/// Cells across the grid the cat's-eye cut of a non-circular aperture is
+/// measured on.
+const APERTURE_GRID: usize = 32;
+
+/// Shape of the lens opening, which is the shape out-of-focus highlights take.
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon with `blades` sides, turned by `rotation` degrees.
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    /// Mask read from `[0,1]^2` of the texture, brightness is transmission.
+    Texture(Arc<dyn Texture>),
+}
+
+impl ApertureShape {
+    /// How much light passes at `p` in `[-1,1]^2`.
+    fn transmission(&self, p: &Vec3) -> f64 {
+        match self {
+            ApertureShape::Circle => (p.squared_length() <= 1.0) as u8 as f64,
+            ApertureShape::Polygon { blades, rotation } => {
+                let n = (*blades).max(3) as f64;
+                let sector = 2.0 * PI / n;
+                let a = p.y.atan2(p.x) - degrees_to_radians(*rotation);
+                let mid = degrees_to_radians(*rotation) + ((a / sector).floor() + 0.5) * sector;
+                // Inside when no further out than the edge's apothem.
+                (p.x * mid.cos() + p.y * mid.sin() <= (PI / n).cos()) as u8 as f64
+            }
+            ApertureShape::Texture(mask) => {
+                let c = mask.value((p.x + 1.0) / 2.0, (p.y + 1.0) / 2.0, p);
+                (0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+/// A real lens, given in millimetres like on a camera body.
+#[derive(Clone, Copy)]
+pub struct Lens {
+    pub focal_length: f64,
+    pub sensor_width: f64,
+    pub f_number: f64,
+    /// How many scene units one millimetre is.
+    pub units_per_mm: f64,
+}
+
+impl Lens {
+    pub fn new(focal_length: f64, sensor_width: f64, f_number: f64, units_per_mm: f64) -> Self {
+        Self {
+            focal_length,
+            sensor_width,
+            f_number,
+            units_per_mm,
+        }
+    }
+
+    pub fn vfov(&self, aspect_ratio: f64) -> f64 {
+        let sensor_height = self.sensor_width / aspect_ratio;
+        2.0 * (sensor_height / (2.0 * self.focal_length)).atan() * 180.0 / PI
+    }
+
+    /// Diameter of the entrance pupil in scene units.
+    pub fn aperture(&self) -> f64 {
+        self.focal_length / self.f_number * self.units_per_mm
+    }
+}
+
 #[derive(Clone)]
 pub struct Camera {
     origin: point3,
@@ -12,9 +86,16 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    viewport_width: f64,
+    viewport_height: f64,
+    focus_dist: f64,
     lens_radius: f64,
-    time0: f64,
-    time1: f64,
+    aperture_shape: ApertureShape,
+    /// `aperture_shape` sampled at the centres of an `APERTURE_GRID`
+    /// square grid, or empty for a circle, whose cut is found exactly.
+    aperture_grid: Vec<f64>,
+    cats_eye: f64,
+    shutter: Shutter,
 }
 
 impl Camera {
@@ -36,45 +117,194 @@ impl Camera {
         let uu = (Vec3::cross(vup, ww)).unit();
         let vv = Vec3::cross(ww, uu);
 
-        let origin2 = lookfrom;
-        let horizontal2 = uu * viewport_width * focus_dist;
-        let vertical2 = vv * viewport_height * focus_dist;
-        Self {
+        let mut cam = Self {
             origin: lookfrom,
-            horizontal: horizontal2,
-            vertical: vertical2,
-            lower_left_corner: origin2 - horizontal2 / 2.0 - vertical2 / 2.0 - ww * focus_dist,
+            horizontal: Vec3::zero(),
+            vertical: Vec3::zero(),
+            lower_left_corner: lookfrom,
             u: uu,
             v: vv,
             w: ww,
+            viewport_width,
+            viewport_height,
+            focus_dist,
             lens_radius: aperture / 2.0,
-            time0: 0.0,
-            time1: 0.0,
+            aperture_shape: ApertureShape::Circle,
+            aperture_grid: vec![],
+            cats_eye: 0.0,
+            shutter: Shutter::new(0.0, 0.0),
+        };
+        cam.set_focus_dist(focus_dist);
+        cam
+    }
+
+    pub fn from_lens(
+        lookfrom: point3,
+        lookat: point3,
+        vup: Vec3,
+        aspect_ratio: f64,
+        lens: &Lens,
+        focus_dist: f64,
+    ) -> Self {
+        Self::new(
+            lookfrom,
+            lookat,
+            vup,
+            lens.vfov(aspect_ratio),
+            aspect_ratio,
+            lens.aperture(),
+            focus_dist,
+        )
+    }
+
+    pub fn new2(&self, time0: f64, time1: f64) -> Self {
+        let mut cam = self.clone();
+        cam.shutter = Shutter::new(time0, time1);
+        cam
+    }
+
+    pub fn set_shutter(&mut self, shutter: Shutter) {
+        self.shutter = shutter;
+    }
+
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    pub fn set_focus_dist(&mut self, focus_dist: f64) {
+        self.focus_dist = focus_dist;
+        self.horizontal = self.u * self.viewport_width * focus_dist;
+        self.vertical = self.v * self.viewport_height * focus_dist;
+        self.lower_left_corner =
+            self.origin - self.horizontal / 2.0 - self.vertical / 2.0 - self.w * focus_dist;
+    }
+
+    pub fn set_aperture_shape(&mut self, shape: ApertureShape) {
+        self.aperture_grid = match shape {
+            ApertureShape::Circle => vec![],
+            _ => (0..APERTURE_GRID * APERTURE_GRID)
+                .map(|i| shape.transmission(&Self::grid_point(i)))
+                .collect(),
+        };
+        self.aperture_shape = shape;
+    }
+
+    /// Centre of cell `i` of the aperture grid.
+    fn grid_point(i: usize) -> Vec3 {
+        let cell = 2.0 / APERTURE_GRID as f64;
+        Vec3::new(
+            -1.0 + ((i % APERTURE_GRID) as f64 + 0.5) * cell,
+            -1.0 + ((i / APERTURE_GRID) as f64 + 0.5) * cell,
+            0.0,
+        )
+    }
+
+    /// Shifts the rear pupil towards the frame centre by `strength` aperture
+    /// radii at the corners, giving the cat's-eye bokeh of fast lenses.
+    pub fn set_cats_eye(&mut self, strength: f64) {
+        self.cats_eye = strength;
+    }
+
+    /// Focuses on whatever the ray through the middle of the frame hits.
+    /// Keeps the old distance and returns false if nothing is there.
+    pub fn autofocus(&mut self, world: &dyn Hittable) -> bool {
+        let r = Ray::new(self.origin, -self.w, self.shutter.mid());
+        let mut rec = HitRecord::new0();
+        if !world.hit(r, 0.001, INFINITY, &mut rec) {
+            return false;
         }
+        self.set_focus_dist(rec.t);
+        true
     }
-    pub fn new2(&self, _time0: f64, _time1: f64) -> Self {
-        Self {
-            origin: self.origin,
-            horizontal: self.horizontal,
-            vertical: self.vertical,
-            lower_left_corner: self.lower_left_corner,
-            u: self.u,
-            v: self.v,
-            w: self.w,
-            lens_radius: self.lens_radius,
-            time0: _time0,
-            time1: _time1,
+
+    /// Fraction of the aperture left open at film position (s, t) once the
+    /// cat's-eye cut is applied. For a circle that is the overlap of two
+    /// unit disks; other shapes are measured on their grid.
+    pub fn vignetting(&self, s: f64, t: f64) -> f64 {
+        if self.cats_eye <= 0.0 || self.lens_radius <= 0.0 {
+            return 1.0;
+        }
+        let shift = self.pupil_shift(s, t);
+        let d = shift.length();
+        if d >= 2.0 {
+            return 0.0;
+        }
+        if self.aperture_grid.is_empty() {
+            return (2.0 * (d / 2.0).acos() - d / 2.0 * (4.0 - d * d).sqrt()) / PI;
+        }
+        let mut open = 0.0;
+        let mut total = 0.0;
+        for (i, w) in self.aperture_grid.iter().enumerate() {
+            total += w;
+            if (Self::grid_point(i) - shift).squared_length() <= 1.0 {
+                open += w;
+            }
+        }
+        if total > 0.0 {
+            open / total
+        } else {
+            1.0
+        }
+    }
+
+    fn pupil_shift(&self, s: f64, t: f64) -> Vec3 {
+        Vec3::new(2.0 * s - 1.0, 2.0 * t - 1.0, 0.0) * self.cats_eye
+    }
+
+    fn sample_shape(&self) -> Vec3 {
+        match &self.aperture_shape {
+            ApertureShape::Circle => Vec3::random_in_unit_disk(),
+            ApertureShape::Polygon { blades, rotation } => {
+                let n = (*blades).max(3) as f64;
+                let k = (random_double1() * n).floor();
+                let a0 = degrees_to_radians(*rotation) + 2.0 * PI * k / n;
+                let a1 = a0 + 2.0 * PI / n;
+                let mut r1 = random_double1();
+                let mut r2 = random_double1();
+                if r1 + r2 > 1.0 {
+                    r1 = 1.0 - r1;
+                    r2 = 1.0 - r2;
+                }
+                Vec3::new(a0.cos(), a0.sin(), 0.0) * r1 + Vec3::new(a1.cos(), a1.sin(), 0.0) * r2
+            }
+            ApertureShape::Texture(mask) => {
+                for _i in 0..64 {
+                    let p = Vec3::new(random_double2(-1.0, 1.0), random_double2(-1.0, 1.0), 0.0);
+                    let c = mask.value((p.x + 1.0) / 2.0, (p.y + 1.0) / 2.0, &p);
+                    let lum = 0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z;
+                    if random_double1() < lum {
+                        return p;
+                    }
+                }
+                Vec3::zero()
+            }
+        }
+    }
+
+    fn sample_aperture(&self, s: f64, t: f64) -> Vec3 {
+        if self.cats_eye <= 0.0 {
+            return self.sample_shape();
+        }
+        let shift = self.pupil_shift(s, t);
+        if shift.length() >= 2.0 {
+            return Vec3::zero();
+        }
+        for _i in 0..64 {
+            let p = self.sample_shape();
+            if (p - shift).squared_length() <= 1.0 {
+                return p;
+            }
         }
+        shift / 2.0
     }
 
     pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
+        let rd = self.sample_aperture(s, t) * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
-        let _x = self.w;
         Ray::new(
             self.origin + offset,
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-            random_double2(self.time0, self.time1),
+            self.shutter.sample(t),
         )
     }
 }
@@ -28,19 +28,17 @@ impl ConstantMedium {
             phase_function: Arc::new(Isotropic::new_from_color(c)),
         }
     }
-}
 
-impl Hittable for ConstantMedium {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut rec1 = HitRecord::new(Arc::new(Lambertian::new_by_color(color::new(
-            0.0, 0.0, 0.0,
-        ))));
-        let mut rec2 = rec1.clone();
-        if !self.boundary.hit(r, -INFINITY, INFINITY, &mut rec1) {
-            return false;
+    /// Where a ray through the medium scatters in `t_min..t_max`, if it
+    /// does. The distance is random, so two calls can disagree.
+    fn scatter_t(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let mut rec1 = HitRecord::scratch();
+        let mut rec2 = HitRecord::scratch();
+        if !self.boundary.hit(*r, -INFINITY, INFINITY, &mut rec1) {
+            return None;
         }
-        if !self.boundary.hit(r, rec1.t + 0.0001, INFINITY, &mut rec2) {
-            return false;
+        if !self.boundary.hit(*r, rec1.t + 0.0001, INFINITY, &mut rec2) {
+            return None;
         }
         if rec1.t < t_min {
             rec1.t = t_min;
@@ -49,7 +47,7 @@ impl Hittable for ConstantMedium {
             rec2.t = t_max;
         }
         if rec1.t > rec2.t {
-            return false;
+            return None;
         }
         if rec1.t < 0.0 {
             rec1.t = 0.0;
@@ -58,17 +56,33 @@ impl Hittable for ConstantMedium {
         let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
         let hit_distance = self.neg_inv_density * random_double2(0.0, 1.0).log10();
         if hit_distance > distance_inside_boundary {
-            return false;
+            return None;
         }
+        Some(rec1.t + hit_distance / ray_length)
+    }
+}
 
-        rec.t = rec1.t + hit_distance / ray_length;
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        rec.t = match self.scatter_t(&r, t_min, t_max) {
+            Some(t) => t,
+            None => return false,
+        };
         rec.p = r.at(rec.t);
         rec.normal = Vec3::new(1.0, 0.0, 0.0);
+        rec.geometric_normal = rec.normal;
+        rec.dpdu = Vec3::zero();
+        rec.dpdv = Vec3::zero();
+        rec.vertex_color = None;
         rec.front_face = true;
         rec.mat_ptr = self.phase_function.clone();
         true
     }
 
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.scatter_t(&r, t_min, t_max).is_some()
+    }
+
     fn bounding_box(&self, t0: f64, t1: f64, output_box: &mut AABB) -> bool {
         self.boundary.bounding_box(t0, t1, output_box)
     }
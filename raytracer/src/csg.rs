@@ -0,0 +1,157 @@
+use crate::hittable::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CsgOp {
+    Union,
+    Intersection,
+    /// `left` with `right` cut away.
+    Difference,
+}
+
+impl CsgOp {
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOp::Union => in_left || in_right,
+            CsgOp::Intersection => in_left && in_right,
+            CsgOp::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Boolean combination of two closed objects. A ray is followed through
+/// each operand's surface crossings in order, and the first crossing that
+/// changes whether it is inside the result is the hit. The material comes
+/// from the operand that was crossed, and surfaces cut by `Difference`
+/// have their front and back swapped, so the result can be nested and
+/// refracts correctly.
+pub struct Csg {
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
+    pub op: CsgOp,
+}
+
+impl Csg {
+    pub fn new(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>, op: CsgOp) -> Self {
+        Self { left, right, op }
+    }
+
+    pub fn union(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        Self::new(left, right, CsgOp::Union)
+    }
+
+    pub fn intersection(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        Self::new(left, right, CsgOp::Intersection)
+    }
+
+    pub fn difference(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        Self::new(left, right, CsgOp::Difference)
+    }
+}
+
+/// Next surface crossing of `object` after `t`, if any.
+fn next_crossing(object: &dyn Hittable, r: Ray, t: f64, rec: &HitRecord) -> Option<HitRecord> {
+    let mut tmp = rec.clone();
+    // Step past the crossing at `t` itself.
+    let t_min = t + 1e-6 * t.abs().max(1.0);
+    if object.hit(r, t_min, INFINITY, &mut tmp) {
+        Some(tmp)
+    } else {
+        None
+    }
+}
+
+impl Csg {
+    /// The first crossing in `t_min..t_max` that changes whether the ray
+    /// is inside the result, and whether it was of `left`.
+    fn first_change(&self, r: Ray, t_min: f64, t_max: f64) -> Option<(HitRecord, bool)> {
+        let rec = HitRecord::scratch();
+        let mut left = next_crossing(&*self.left, r, t_min, &rec);
+        let mut right = next_crossing(&*self.right, r, t_min, &rec);
+        // Leaving through a back face means the ray started inside.
+        let mut in_left = left.as_ref().map(|h| !h.front_face).unwrap_or(false);
+        let mut in_right = right.as_ref().map(|h| !h.front_face).unwrap_or(false);
+
+        loop {
+            let from_left = match (&left, &right) {
+                (Some(a), Some(b)) => a.t <= b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => return None,
+            };
+            let crossing = if from_left { &mut left } else { &mut right };
+            let t = crossing.as_ref().map(|h| h.t).unwrap_or(INFINITY);
+            if t > t_max {
+                return None;
+            }
+
+            let was_inside = self.op.inside(in_left, in_right);
+            if from_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+            if was_inside != self.op.inside(in_left, in_right) {
+                return crossing.take().map(|h| (h, from_left));
+            }
+
+            if from_left {
+                left = next_crossing(&*self.left, r, t, &rec);
+            } else {
+                right = next_crossing(&*self.right, r, t, &rec);
+            }
+        }
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.first_change(r, t_min, t_max) {
+            Some((crossing, from_left)) => {
+                *rec = crossing;
+                if !from_left && self.op == CsgOp::Difference {
+                    // The normal already faces the ray, only the side
+                    // the outward normal points to changes.
+                    rec.front_face = !rec.front_face;
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.first_change(r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        let mut left = AABB::new0();
+        let mut right = AABB::new0();
+        if !self.left.bounding_box(time0, time1, &mut left) {
+            return false;
+        }
+        if self.op == CsgOp::Difference {
+            *output_box = left;
+            return true;
+        }
+        if !self.right.bounding_box(time0, time1, &mut right) {
+            return false;
+        }
+        *output_box = match self.op {
+            CsgOp::Union => AABB::surrounding_box(&left, &right),
+            _ => {
+                let (a, b) = (left.min(), right.min());
+                let (c, d) = (left.max(), right.max());
+                let min = point3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+                let max = point3::new(c.x.min(d.x), c.y.min(d.y), c.z.min(d.z));
+                // Disjoint operands still need a valid, if empty, box.
+                AABB::new(
+                    min,
+                    point3::new(max.x.max(min.x), max.y.max(min.y), max.z.max(min.z)),
+                )
+            }
+        };
+        true
+    }
+}
@@ -0,0 +1,287 @@
+use crate::hittable::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveType {
+    /// A ribbon that always turns to face the ray, for thin fibres.
+    Flat,
+    /// A round tube, shaded with the normal of the tube.
+    Cylinder,
+}
+
+/// A cubic Bezier curve with width changing linearly from `widths.0` at
+/// the start to `widths.1` at the end, intersected directly by splitting
+/// it until the pieces are close to straight, as in pbrt. Texture `u` runs
+/// along the curve and `v` across it.
+pub struct Curve {
+    pub cp: [point3; 4],
+    pub widths: (f64, f64),
+    pub kind: CurveType,
+    pub mp: Arc<dyn Material>,
+}
+
+impl Curve {
+    pub fn new(
+        cp: [point3; 4],
+        widths: (f64, f64),
+        kind: CurveType,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            cp,
+            widths,
+            kind,
+            mp: mat,
+        }
+    }
+
+    /// The Bezier pieces of the uniform cubic B-spline through `points`.
+    pub fn bspline_to_bezier(points: &[point3]) -> Vec<[point3; 4]> {
+        points
+            .windows(4)
+            .map(|p| {
+                [
+                    (p[0] + p[1] * 4.0 + p[2]) / 6.0,
+                    (p[1] * 2.0 + p[2]) / 3.0,
+                    (p[1] + p[2] * 2.0) / 3.0,
+                    (p[1] + p[2] * 4.0 + p[3]) / 6.0,
+                ]
+            })
+            .collect()
+    }
+
+    fn width_at(&self, u: f64) -> f64 {
+        self.widths.0 * (1.0 - u) + self.widths.1 * u
+    }
+
+    fn max_width(&self) -> f64 {
+        self.widths.0.max(self.widths.1)
+    }
+
+    /// Closest hit found so far, as `(z, u, v)` in the ray's frame. With
+    /// `any_hit` the search stops at the first.
+    #[allow(clippy::too_many_arguments)]
+    fn recursive_intersect(
+        &self,
+        cp: &[Vec3; 4],
+        u0: f64,
+        u1: f64,
+        depth: usize,
+        z_min: f64,
+        z_max: &mut f64,
+        any_hit: bool,
+        found: &mut Option<(f64, f64, f64)>,
+    ) {
+        if any_hit && found.is_some() {
+            return;
+        }
+        if depth > 0 {
+            let halves = split_bezier(cp);
+            let u_mid = 0.5 * (u0 + u1);
+            for (half, a, b) in [(&halves.0, u0, u_mid), (&halves.1, u_mid, u1)].iter() {
+                let half_width = 0.5 * self.width_at(*a).max(self.width_at(*b));
+                let (lo, hi) = bounds(half);
+                if lo.x - half_width > 0.0
+                    || hi.x + half_width < 0.0
+                    || lo.y - half_width > 0.0
+                    || hi.y + half_width < 0.0
+                    || hi.z + half_width < z_min
+                    || lo.z - half_width > *z_max
+                {
+                    continue;
+                }
+                self.recursive_intersect(half, *a, *b, depth - 1, z_min, z_max, any_hit, found);
+            }
+            return;
+        }
+
+        // The ray, at the origin, must lie between the planes at the two
+        // ends that are perpendicular to the curve.
+        let edge = (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x);
+        if edge < 0.0 {
+            return;
+        }
+        let edge = (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x);
+        if edge < 0.0 {
+            return;
+        }
+        // Treat the piece as straight to find the nearest point.
+        let (sx, sy) = (cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = sx * sx + sy * sy;
+        if denom == 0.0 {
+            return;
+        }
+        let w = ((-cp[0].x * sx - cp[0].y * sy) / denom).clamp(0.0, 1.0);
+        let u = (u0 * (1.0 - w) + u1 * w).clamp(u0, u1);
+        let width = self.width_at(u);
+        let (pc, dpcdw) = eval_bezier(cp, w);
+        let dist2 = pc.x * pc.x + pc.y * pc.y;
+        if dist2 > width * width * 0.25 || pc.z < z_min || pc.z > *z_max {
+            return;
+        }
+        let dist = dist2.sqrt();
+        let side = dpcdw.x * -pc.y + pc.x * dpcdw.y;
+        let v = if side > 0.0 {
+            0.5 + dist / width
+        } else {
+            0.5 - dist / width
+        };
+        *z_max = pc.z;
+        *found = Some((pc.z, u, v));
+    }
+
+    /// The nearest hit in `t_min..t_max` as `(t, u, v)`, or with `any_hit`
+    /// the first one found.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64, any_hit: bool) -> Option<(f64, f64, f64)> {
+        let speed = r.direction().length();
+        if speed == 0.0 {
+            return None;
+        }
+        // A frame with the ray along +z from the origin.
+        let dz = r.direction() / speed;
+        let a = if dz.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let dx = Vec3::cross(a, dz).unit();
+        let dy = Vec3::cross(dz, dx);
+        let to_ray = |p: &point3| {
+            let q = *p - r.orign();
+            Vec3::new(q * dx, q * dy, q * dz)
+        };
+        let cp = [
+            to_ray(&self.cp[0]),
+            to_ray(&self.cp[1]),
+            to_ray(&self.cp[2]),
+            to_ray(&self.cp[3]),
+        ];
+
+        // Split until each piece is within a twentieth of the width of a
+        // straight line.
+        let mut l0: f64 = 0.0;
+        for i in 0..2 {
+            let d = cp[i] - cp[i + 1] * 2.0 + cp[i + 2];
+            l0 = l0.max(d.x.abs()).max(d.y.abs()).max(d.z.abs());
+        }
+        let eps = self.max_width() * 0.05;
+        let ratio = std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps);
+        let depth = if ratio > 1.0 {
+            ((ratio.log2() as usize) / 2).min(10)
+        } else {
+            0
+        };
+
+        let mut z_max = t_max * speed;
+        let mut found = None;
+        let (lo, hi) = bounds(&cp);
+        let half_width = 0.5 * self.max_width();
+        if lo.x - half_width > 0.0
+            || hi.x + half_width < 0.0
+            || lo.y - half_width > 0.0
+            || hi.y + half_width < 0.0
+        {
+            return None;
+        }
+        self.recursive_intersect(
+            &cp,
+            0.0,
+            1.0,
+            depth,
+            t_min * speed,
+            &mut z_max,
+            any_hit,
+            &mut found,
+        );
+        found.map(|(z, u, v)| (z / speed, u, v))
+    }
+}
+
+/// De Casteljau at the middle.
+fn split_bezier(cp: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let m01 = (cp[0] + cp[1]) * 0.5;
+    let m12 = (cp[1] + cp[2]) * 0.5;
+    let m23 = (cp[2] + cp[3]) * 0.5;
+    let a = (m01 + m12) * 0.5;
+    let b = (m12 + m23) * 0.5;
+    let mid = (a + b) * 0.5;
+    ([cp[0], m01, a, mid], [mid, b, m23, cp[3]])
+}
+
+/// Point and derivative at `t`.
+fn eval_bezier(cp: &[Vec3; 4], t: f64) -> (Vec3, Vec3) {
+    let lerp = |a: Vec3, b: Vec3| a * (1.0 - t) + b * t;
+    let cp1 = [lerp(cp[0], cp[1]), lerp(cp[1], cp[2]), lerp(cp[2], cp[3])];
+    let cp2 = [lerp(cp1[0], cp1[1]), lerp(cp1[1], cp1[2])];
+    let d = cp2[1] - cp2[0];
+    let d = if d.squared_length() > 0.0 {
+        d * 3.0
+    } else {
+        // Coincident control points at an end.
+        cp[3] - cp[0]
+    };
+    (lerp(cp2[0], cp2[1]), d)
+}
+
+fn bounds(cp: &[Vec3; 4]) -> (Vec3, Vec3) {
+    cp.iter().fold(
+        (
+            Vec3::new(INFINITY, INFINITY, INFINITY),
+            Vec3::new(-INFINITY, -INFINITY, -INFINITY),
+        ),
+        |(lo, hi), p| {
+            (
+                Vec3::new(lo.x.min(p.x), lo.y.min(p.y), lo.z.min(p.z)),
+                Vec3::new(hi.x.max(p.x), hi.y.max(p.y), hi.z.max(p.z)),
+            )
+        },
+    )
+}
+
+impl Hittable for Curve {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, u, v) = match self.intersect(&r, t_min, t_max, false) {
+            Some(f) => f,
+            None => return false,
+        };
+
+        let dz = r.direction().unit();
+        let (_, dpdu) = eval_bezier(&self.cp, u);
+        let tangent = dpdu.unit();
+        let across = Vec3::cross(dz, tangent);
+        if across.squared_length() == 0.0 {
+            return false;
+        }
+        let across = across.unit();
+        let facing = Vec3::cross(across, tangent);
+        let outward_normal = match self.kind {
+            CurveType::Flat => facing,
+            CurveType::Cylinder => {
+                let h = (2.0 * v - 1.0).clamp(-1.0, 1.0);
+                across * h + facing * (1.0 - h * h).sqrt()
+            }
+        };
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = u;
+        rec.v = v;
+        rec.set_face_normal(&r, &outward_normal);
+        rec.dpdu = dpdu;
+        rec.dpdv = Vec3::cross(tangent, outward_normal).unit() * self.width_at(u);
+        rec.mat_ptr = self.mp.clone();
+        true
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max, true).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let (lo, hi) = bounds(&self.cp);
+        let pad = Vec3::ones() * (0.5 * self.max_width());
+        *output_box = AABB::new(lo - pad, hi + pad);
+        true
+    }
+}
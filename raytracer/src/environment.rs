@@ -0,0 +1,454 @@
+pub use crate::rtweekend::*;
+pub use crate::texture::Texture;
+pub use crate::vec3::Vec3;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+pub use Vec3 as color;
+
+/// Light arriving from infinitely far away, looked up by direction.
+pub trait Environment: Sync + Send {
+    /// Radiance coming from unit direction `dir`.
+    fn value(&self, dir: &Vec3) -> color;
+
+    /// What a camera ray that misses everything sees.
+    fn camera_value(&self, dir: &Vec3) -> color {
+        self.value(dir)
+    }
+
+    /// Whether the environment should be sampled as a light source.
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    /// Solid angle density `random` picks `dir` with.
+    fn pdf_value(&self, _dir: &Vec3) -> f64 {
+        0.0
+    }
+
+    fn random(&self) -> Vec3 {
+        Vec3::new(0.0, 1.0, 0.0)
+    }
+}
+
+pub struct ConstantEnvironment {
+    pub color_value: color,
+}
+
+impl ConstantEnvironment {
+    pub fn new(c: color) -> Self {
+        Self { color_value: c }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn value(&self, _dir: &Vec3) -> color {
+        self.color_value
+    }
+}
+
+/// The sky of the first book, blends from `bottom` straight down to `top`
+/// straight up.
+pub struct GradientEnvironment {
+    pub bottom: color,
+    pub top: color,
+}
+
+impl GradientEnvironment {
+    pub fn new(bottom: color, top: color) -> Self {
+        Self { bottom, top }
+    }
+
+    pub fn sky() -> Self {
+        Self::new(color::new(1.0, 1.0, 1.0), color::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn value(&self, dir: &Vec3) -> color {
+        let t = 0.5 * (dir.y + 1.0);
+        self.bottom * (1.0 - t) + self.top * t
+    }
+}
+
+/// Any texture wrapped around the scene with the lat-long mapping of
+/// `EnvironmentMap`, `p` is the direction.
+pub struct TextureEnvironment {
+    pub texture: Arc<dyn Texture>,
+    pub intensity: f64,
+}
+
+impl TextureEnvironment {
+    pub fn new(texture: Arc<dyn Texture>, intensity: f64) -> Self {
+        Self { texture, intensity }
+    }
+}
+
+impl Environment for TextureEnvironment {
+    fn value(&self, dir: &Vec3) -> color {
+        let (u, v) = lat_long(dir);
+        self.texture.value(u, 1.0 - v, dir) * self.intensity
+    }
+}
+
+/// Looks black to the camera but still shows in reflections and refractions
+/// and still lights the scene.
+pub struct ReflectionsOnly {
+    pub inner: Arc<dyn Environment>,
+}
+
+impl ReflectionsOnly {
+    pub fn new(inner: Arc<dyn Environment>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Environment for ReflectionsOnly {
+    fn value(&self, dir: &Vec3) -> color {
+        self.inner.value(dir)
+    }
+
+    fn camera_value(&self, _dir: &Vec3) -> color {
+        color::zero()
+    }
+
+    fn is_light(&self) -> bool {
+        self.inner.is_light()
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        self.inner.pdf_value(dir)
+    }
+
+    fn random(&self) -> Vec3 {
+        self.inner.random()
+    }
+}
+
+/// Distant lights seen as small disks in front of `inner`, such as the
+/// directional lights of a glTF scene.
+pub struct SunEnvironment {
+    pub inner: Arc<dyn Environment>,
+    /// Direction towards each light and its radiance.
+    pub suns: Vec<(Vec3, color)>,
+    cos_max: f64,
+}
+
+impl SunEnvironment {
+    /// Every disk spans `angular_radius` degrees.
+    pub fn new(inner: Arc<dyn Environment>, angular_radius: f64) -> Self {
+        Self {
+            inner,
+            suns: vec![],
+            cos_max: degrees_to_radians(angular_radius).cos(),
+        }
+    }
+
+    /// A light towards `dir` that puts `irradiance` on a surface facing it.
+    pub fn add_sun(&mut self, dir: Vec3, irradiance: color) {
+        let solid_angle = 2.0 * PI * (1.0 - self.cos_max);
+        self.suns.push((dir.unit(), irradiance / solid_angle));
+    }
+
+    fn inner_probability(&self) -> f64 {
+        if self.suns.is_empty() {
+            1.0
+        } else if self.inner.is_light() {
+            0.5
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Environment for SunEnvironment {
+    fn value(&self, dir: &Vec3) -> color {
+        let mut c = self.inner.value(dir);
+        for (w, radiance) in self.suns.iter() {
+            if *dir * *w >= self.cos_max {
+                c += *radiance;
+            }
+        }
+        c
+    }
+
+    fn camera_value(&self, dir: &Vec3) -> color {
+        self.inner.camera_value(dir)
+    }
+
+    fn is_light(&self) -> bool {
+        !self.suns.is_empty() || self.inner.is_light()
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        let p_inner = self.inner_probability();
+        let mut pdf = 0.0;
+        if p_inner > 0.0 {
+            pdf += p_inner * self.inner.pdf_value(dir);
+        }
+        let cone_pdf = 1.0 / (2.0 * PI * (1.0 - self.cos_max));
+        for (w, _) in self.suns.iter() {
+            if *dir * *w >= self.cos_max {
+                pdf += (1.0 - p_inner) / self.suns.len() as f64 * cone_pdf;
+            }
+        }
+        pdf
+    }
+
+    fn random(&self) -> Vec3 {
+        if random_double1() < self.inner_probability() {
+            return self.inner.random();
+        }
+        let i = ((random_double1() * self.suns.len() as f64) as usize).min(self.suns.len() - 1);
+        random_in_cone(&self.suns[i].0, self.cos_max)
+    }
+}
+
+/// Uniform direction within the cone of cosine `cos_max` around unit `w`.
+pub fn random_in_cone(w: &Vec3, cos_max: f64) -> Vec3 {
+    let cos_theta = 1.0 - random_double1() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * random_double1();
+    let a = if w.x.abs() > 0.9 {
+        Vec3::new(0.0, 1.0, 0.0)
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let v = Vec3::cross(*w, a).unit();
+    let u = Vec3::cross(*w, v);
+    u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + *w * cos_theta
+}
+
+/// (u, v) of a unit direction, `v` is 0 straight up.
+pub fn lat_long(d: &Vec3) -> (f64, f64) {
+    let theta = clamp(d.y, -1.0, 1.0).acos();
+    let phi = (-d.z).atan2(d.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
+pub fn luminance(c: &color) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+/// Piecewise constant density over [0, 1).
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(f: &[f64]) -> Self {
+        let n = f.len();
+        let mut func: Vec<f64> = f.iter().map(|x| x.abs()).collect();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let mut integral = cdf[n];
+        if integral <= 0.0 {
+            for (i, x) in func.iter_mut().enumerate() {
+                *x = 1.0;
+                cdf[i + 1] = (i + 1) as f64 / n as f64;
+            }
+            integral = 1.0;
+        } else {
+            for c in cdf.iter_mut() {
+                *c /= integral;
+            }
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Returns the sampled position, its density and the cell it fell in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let n = self.count();
+        let mut lo = 0;
+        let mut hi = n;
+        while lo + 1 < hi {
+            let mid = (lo + hi) / 2;
+            if self.cdf[mid] <= u {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        let width = self.cdf[lo + 1] - self.cdf[lo];
+        let du = if width > 0.0 {
+            (u - self.cdf[lo]) / width
+        } else {
+            0.0
+        };
+        let pdf = self.func[lo] / self.integral;
+        ((lo as f64 + du) / n as f64, pdf, lo)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let n = self.count();
+        let i = ((x * n as f64) as usize).min(n - 1);
+        self.func[i] / self.integral
+    }
+}
+
+/// Piecewise constant density over [0, 1)^2, rows picked first.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `f` holds `height` rows of `width` values.
+    pub fn new(f: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height)
+            .map(|j| Distribution1D::new(&f[j * width..(j + 1) * width]))
+            .collect();
+        let row_weights: Vec<f64> = conditional.iter().map(|d| d.integral).collect();
+        Self {
+            conditional,
+            marginal: Distribution1D::new(&row_weights),
+        }
+    }
+
+    /// Returns (u, v) and their joint density.
+    pub fn sample(&self, u0: f64, u1: f64) -> (f64, f64, f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.conditional[row].sample(u0);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let rows = self.conditional.len();
+        let row = ((v * rows as f64) as usize).min(rows - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+/// Equirectangular (lat-long) HDR image around the scene. The top row is
+/// straight up, `u` runs around the y axis.
+pub struct EnvironmentMap {
+    pub data: Vec<color>,
+    pub width: usize,
+    pub height: usize,
+    pub intensity: f64,
+    sin_theta: f64,
+    cos_theta: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance `.hdr` file, turned by `rotation` degrees about +y
+    /// and scaled by `intensity`.
+    pub fn new(filename: &str, rotation: f64, intensity: f64) -> io::Result<Self> {
+        let bad = |e: image::ImageError| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename, e))
+        };
+        let file = BufReader::new(File::open(filename)?);
+        let decoder = image::codecs::hdr::HdrDecoder::new(file).map_err(bad)?;
+        let meta = decoder.metadata();
+        let data = decoder
+            .read_image_hdr()
+            .map_err(bad)?
+            .iter()
+            .map(|p| color::new(p.0[0] as f64, p.0[1] as f64, p.0[2] as f64))
+            .collect();
+        Ok(Self::from_pixels(
+            meta.width as usize,
+            meta.height as usize,
+            data,
+            rotation,
+            intensity,
+        ))
+    }
+
+    pub fn from_pixels(
+        width: usize,
+        height: usize,
+        data: Vec<color>,
+        rotation: f64,
+        intensity: f64,
+    ) -> Self {
+        let mut weights = vec![0.0; width * height];
+        for j in 0..height {
+            let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+            for i in 0..width {
+                weights[j * width + i] = luminance(&data[j * width + i]) * sin_theta;
+            }
+        }
+        let radians = degrees_to_radians(rotation);
+        Self {
+            distribution: Distribution2D::new(&weights, width, height),
+            data,
+            width,
+            height,
+            intensity,
+            sin_theta: radians.sin(),
+            cos_theta: radians.cos(),
+        }
+    }
+
+    fn to_local(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * d.x - self.sin_theta * d.z,
+            d.y,
+            self.sin_theta * d.x + self.cos_theta * d.z,
+        )
+    }
+
+    fn to_world(&self, d: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * d.x + self.sin_theta * d.z,
+            d.y,
+            -self.sin_theta * d.x + self.cos_theta * d.z,
+        )
+    }
+
+    fn uv(&self, dir: &Vec3) -> (f64, f64) {
+        lat_long(&self.to_local(dir))
+    }
+
+    fn texel(&self, u: f64, v: f64) -> color {
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.data[j * self.width + i]
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn value(&self, dir: &Vec3) -> color {
+        let (u, v) = self.uv(dir);
+        self.texel(u, v) * self.intensity
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        let (u, v) = self.uv(dir);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random(&self) -> Vec3 {
+        let (u, v, _) = self.distribution.sample(random_double1(), random_double1());
+        let theta = v * PI;
+        let phi = u * 2.0 * PI - PI;
+        let local = Vec3::new(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            -theta.sin() * phi.sin(),
+        );
+        self.to_world(&local)
+    }
+}
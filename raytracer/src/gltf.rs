@@ -0,0 +1,978 @@
+use crate::camera::Camera;
+use crate::environment::*;
+use crate::hittable::*;
+use crate::instance::Instance;
+use crate::json::*;
+use crate::linear_bvh::*;
+use crate::material::*;
+use crate::sphere::Sphere;
+use crate::transform::Transform;
+use crate::triangle::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Radius of the spheres that stand in for point and spot lights.
+pub const GLTF_LIGHT_RADIUS: f64 = 0.05;
+/// Angular radius in degrees of the disks that stand in for directional lights.
+pub const GLTF_SUN_ANGULAR_RADIUS: f64 = 0.27;
+
+/// A perspective camera placed by its node.
+#[derive(Clone)]
+pub struct GltfCamera {
+    pub name: String,
+    pub lookfrom: point3,
+    pub lookat: point3,
+    pub vup: Vec3,
+    /// Vertical field of view in degrees.
+    pub vfov: f64,
+    pub aspect_ratio: Option<f64>,
+}
+
+impl GltfCamera {
+    /// A pinhole camera, `aspect_ratio` is that of the image being rendered.
+    pub fn to_camera(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            aspect_ratio,
+            0.0,
+            1.0,
+        )
+    }
+}
+
+/// Everything of a glTF 2.0 scene the renderer can show. Each mesh is
+/// built once and placed by an `Instance` per node that uses it; point and
+/// spot lights become small emitting spheres and directional lights are
+/// kept for `environment`.
+pub struct GltfScene {
+    pub objects: HittableList,
+    pub cameras: Vec<GltfCamera>,
+    /// Direction towards each directional light and the irradiance it gives.
+    pub suns: Vec<(Vec3, color)>,
+}
+
+impl GltfScene {
+    /// Reads a `.gltf` (with its buffers and images) or a `.glb` file.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut file = GltfFile::open(path)?;
+        let mut scene = Self {
+            objects: HittableList::new(),
+            cameras: vec![],
+            suns: vec![],
+        };
+
+        let doc = file.doc.clone();
+        let roots: Vec<usize> =
+            match doc["scenes"][doc["scene"].as_usize().unwrap_or(0)].get("nodes") {
+                Some(nodes) => nodes
+                    .members()
+                    .iter()
+                    .filter_map(|n| n.as_usize())
+                    .collect(),
+                None => {
+                    let count = doc["nodes"].members().len();
+                    let mut is_child = vec![false; count];
+                    for node in doc["nodes"].members() {
+                        for c in node["children"].members() {
+                            if let Some(c) = c.as_usize().filter(|&c| c < count) {
+                                is_child[c] = true;
+                            }
+                        }
+                    }
+                    (0..count).filter(|&i| !is_child[i]).collect()
+                }
+            };
+
+        let node_count = doc["nodes"].members().len();
+        let mut stack: Vec<(usize, Transform, usize)> = roots
+            .iter()
+            .map(|&n| (n, Transform::identity(), 0))
+            .collect();
+        while let Some((index, parent, depth)) = stack.pop() {
+            if index >= node_count || depth > node_count {
+                return Err(invalid("glTF node hierarchy is broken"));
+            }
+            let node = &doc["nodes"][index];
+            // A node scaled to nothing hides its whole subtree.
+            let world = match local_transform(node) {
+                Some(local) => parent * local,
+                None => continue,
+            };
+
+            if let Some(mesh) = node["mesh"].as_usize() {
+                if let Some(prototype) = file.mesh(mesh)? {
+                    scene.objects.add(Arc::new(Instance::new(prototype, world)));
+                }
+            }
+            if let Some(camera) = node["camera"].as_usize() {
+                let c = &doc["cameras"][camera];
+                if c["type"].as_str() == Some("perspective") {
+                    let p = &c["perspective"];
+                    let lookfrom = world.point(&Vec3::zero());
+                    let forward = world.vector(&Vec3::new(0.0, 0.0, -1.0));
+                    scene.cameras.push(GltfCamera {
+                        name: c["name"].as_str().unwrap_or("").to_string(),
+                        lookfrom,
+                        lookat: lookfrom + forward.unit(),
+                        vup: world.vector(&Vec3::new(0.0, 1.0, 0.0)),
+                        vfov: p["yfov"].as_f64().unwrap_or(0.8) * 180.0 / PI,
+                        aspect_ratio: p["aspectRatio"].as_f64(),
+                    });
+                }
+            }
+            if let Some(light) = node["extensions"]["KHR_lights_punctual"]["light"].as_usize() {
+                let l = &doc["extensions"]["KHR_lights_punctual"]["lights"][light];
+                let c = l["color"]
+                    .as_f64_vec()
+                    .filter(|c| c.len() == 3)
+                    .unwrap_or_else(|| vec![1.0; 3]);
+                let intensity =
+                    color::new(c[0], c[1], c[2]) * l["intensity"].as_f64().unwrap_or(1.0);
+                match l["type"].as_str() {
+                    Some("directional") => {
+                        let dir = world.vector(&Vec3::new(0.0, 0.0, -1.0));
+                        scene.suns.push((-dir.unit(), intensity));
+                    }
+                    Some(kind) => {
+                        // A sphere shines in every direction. A spot light
+                        // keeps the power it sends into its cone.
+                        let share = if kind == "spot" {
+                            let outer = l["spot"]["outerConeAngle"].as_f64().unwrap_or(PI / 4.0);
+                            (1.0 - outer.cos()) / 2.0
+                        } else {
+                            1.0
+                        };
+                        let r = GLTF_LIGHT_RADIUS;
+                        let radiance = intensity * (share / (PI * r * r));
+                        scene.objects.add(Arc::new(Sphere::new(
+                            world.point(&Vec3::zero()),
+                            r,
+                            Arc::new(DiffuseLight::new_by_color(radiance)),
+                        )));
+                    }
+                    None => {}
+                }
+            }
+            for c in node["children"].members() {
+                if let Some(c) = c.as_usize() {
+                    stack.push((c, world, depth + 1));
+                }
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Top-level BVH over the instances and lights.
+    pub fn to_bvh(&self, time0: f64, time1: f64) -> LinearBvh {
+        LinearBvh::from(&mut self.objects.clone(), time0, time1)
+    }
+
+    /// The scene's first camera.
+    pub fn camera(&self, aspect_ratio: f64) -> Option<Camera> {
+        self.cameras.first().map(|c| c.to_camera(aspect_ratio))
+    }
+
+    /// `background` with the scene's directional lights in it.
+    pub fn environment(&self, background: Arc<dyn Environment>) -> Arc<dyn Environment> {
+        if self.suns.is_empty() {
+            return background;
+        }
+        let mut env = SunEnvironment::new(background, GLTF_SUN_ANGULAR_RADIUS);
+        for (dir, irradiance) in self.suns.iter() {
+            env.add_sun(*dir, *irradiance);
+        }
+        Arc::new(env)
+    }
+}
+
+/// The JSON document and its loaded buffers.
+struct GltfFile {
+    doc: Json,
+    dir: PathBuf,
+    buffers: Vec<Vec<u8>>,
+    images: Vec<Option<Arc<ImageTexture>>>,
+    materials: Vec<Option<Arc<dyn Material>>>,
+    /// Prototype of each mesh once built, `None` inside if it has no triangles.
+    meshes: Vec<Option<Option<Arc<dyn Hittable>>>>,
+}
+
+impl GltfFile {
+    fn open(path: &str) -> io::Result<Self> {
+        let dir = Path::new(path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf();
+        Self::from_bytes(fs::read(path)?, dir)
+    }
+
+    /// A `.gltf` or `.glb` already read, with relative URIs under `dir`.
+    fn from_bytes(bytes: Vec<u8>, dir: PathBuf) -> io::Result<Self> {
+        let (text, bin) = if bytes.starts_with(b"glTF") {
+            split_glb(&bytes)?
+        } else {
+            (
+                String::from_utf8(bytes).map_err(|_| invalid("glTF is not UTF-8"))?,
+                None,
+            )
+        };
+        let doc = Json::parse(&text)?;
+        if !matches!(doc["asset"]["version"].as_str(), Some(v) if v.starts_with('2')) {
+            return Err(invalid("only glTF 2.0 is supported"));
+        }
+
+        let mut buffers = vec![];
+        for (i, b) in doc["buffers"].members().iter().enumerate() {
+            let data = match b["uri"].as_str() {
+                Some(uri) => read_uri(&dir, uri)?,
+                None if i == 0 => bin
+                    .clone()
+                    .ok_or_else(|| invalid("glTF buffer has no data"))?,
+                None => return Err(invalid("glTF buffer has no data")),
+            };
+            if data.len() < b["byteLength"].as_usize().unwrap_or(0) {
+                return Err(invalid("glTF buffer is shorter than its byteLength"));
+            }
+            buffers.push(data);
+        }
+
+        Ok(Self {
+            images: vec![None; doc["images"].members().len()],
+            materials: vec![None; doc["materials"].members().len()],
+            meshes: vec![None; doc["meshes"].members().len()],
+            doc,
+            dir,
+            buffers,
+        })
+    }
+
+    fn buffer_view(&self, index: usize) -> io::Result<(&[u8], Option<usize>)> {
+        let view = &self.doc["bufferViews"][index];
+        let buffer = view["buffer"]
+            .as_usize()
+            .and_then(|b| self.buffers.get(b))
+            .ok_or_else(|| invalid("glTF bufferView without buffer"))?;
+        let offset = view["byteOffset"].as_usize().unwrap_or(0);
+        let length = view["byteLength"].as_usize().unwrap_or(0);
+        let data = offset
+            .checked_add(length)
+            .and_then(|end| buffer.get(offset..end))
+            .ok_or_else(|| invalid("glTF bufferView out of range"))?;
+        Ok((data, view["byteStride"].as_usize()))
+    }
+
+    /// Every element of an accessor as floats, `components` per element.
+    /// Normalized integers are mapped to [0, 1] or [-1, 1].
+    fn accessor(&self, index: usize) -> io::Result<(Vec<f64>, usize)> {
+        let a = &self.doc["accessors"][index];
+        let count = a["count"]
+            .as_usize()
+            .ok_or_else(|| invalid("glTF accessor without count"))?;
+        let components = match a["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(invalid("glTF accessor with unknown type")),
+        };
+        let kind = a["componentType"].as_usize().unwrap_or(0);
+        let size = match kind {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(invalid("glTF accessor with unknown componentType")),
+        };
+        let normalized = a["normalized"].as_bool().unwrap_or(false);
+        let len = count
+            .checked_mul(components)
+            .ok_or_else(|| invalid("glTF accessor out of range"))?;
+
+        let view = match a["bufferView"].as_usize() {
+            Some(v) => v,
+            // Only sparse values, which are not supported: all zero. Nothing
+            // in the file backs them, so they may not outgrow its buffers.
+            None => {
+                let bytes: usize = self.buffers.iter().map(|b| b.len()).sum();
+                if len > bytes {
+                    return Err(invalid("glTF accessor out of range"));
+                }
+                return Ok((vec![0.0; len], components));
+            }
+        };
+        let (data, stride) = self.buffer_view(view)?;
+        let offset = a["byteOffset"].as_usize().unwrap_or(0);
+        let element = components * size;
+        let stride = stride.unwrap_or(element);
+        if stride < element {
+            return Err(invalid("glTF byteStride smaller than its element"));
+        }
+        // Checked before allocating, the count comes straight from the file.
+        let end = match count.checked_sub(1) {
+            Some(last) => stride
+                .checked_mul(last)
+                .and_then(|n| n.checked_add(offset))
+                .and_then(|n| n.checked_add(element)),
+            None => Some(0),
+        };
+        if end.filter(|&end| end <= data.len()).is_none() {
+            return Err(invalid("glTF accessor out of range"));
+        }
+
+        let mut out = Vec::with_capacity(len);
+        for i in 0..count {
+            let at = offset + i * stride;
+            for c in 0..components {
+                let b = &data[at + c * size..at + (c + 1) * size];
+                let x = match kind {
+                    5120 => {
+                        let x = b[0] as i8 as f64;
+                        if normalized {
+                            (x / 127.0).max(-1.0)
+                        } else {
+                            x
+                        }
+                    }
+                    5121 => {
+                        let x = b[0] as f64;
+                        if normalized {
+                            x / 255.0
+                        } else {
+                            x
+                        }
+                    }
+                    5122 => {
+                        let x = i16::from_le_bytes([b[0], b[1]]) as f64;
+                        if normalized {
+                            (x / 32767.0).max(-1.0)
+                        } else {
+                            x
+                        }
+                    }
+                    5123 => {
+                        let x = u16::from_le_bytes([b[0], b[1]]) as f64;
+                        if normalized {
+                            x / 65535.0
+                        } else {
+                            x
+                        }
+                    }
+                    5125 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                };
+                out.push(x);
+            }
+        }
+        Ok((out, components))
+    }
+
+    fn texture(&mut self, info: &Json) -> io::Result<Option<Arc<ImageTexture>>> {
+        let image = match info["index"]
+            .as_usize()
+            .and_then(|t| self.doc["textures"][t]["source"].as_usize())
+        {
+            Some(i) if i < self.images.len() => i,
+            _ => return Ok(None),
+        };
+        if let Some(t) = &self.images[image] {
+            return Ok(Some(t.clone()));
+        }
+        let img = &self.doc["images"][image];
+        let bytes = match (img["uri"].as_str(), img["bufferView"].as_usize()) {
+            (Some(uri), _) => read_uri(&self.dir, uri)?,
+            (None, Some(view)) => self.buffer_view(view)?.0.to_vec(),
+            _ => return Ok(None),
+        };
+        let data = image::load_from_memory(&bytes)
+            .map_err(|e| invalid(&format!("glTF image {}: {}", image, e)))?
+            .to_rgb8();
+        let texture = Arc::new(ImageTexture::from_image(data));
+        self.images[image] = Some(texture.clone());
+        Ok(Some(texture))
+    }
+
+    /// Emissive materials become lights, transmissive ones glass, mostly
+    /// metallic ones `Metal` with the roughness as fuzz, the rest Lambertian.
+    /// A `metallicRoughnessTexture` scales both factors by its average.
+    fn material(
+        &mut self,
+        index: Option<usize>,
+        vertex_colors: bool,
+    ) -> io::Result<Arc<dyn Material>> {
+        let cached = index
+            .filter(|_| !vertex_colors)
+            .and_then(|i| self.materials.get(i).cloned().flatten());
+        if let Some(m) = cached {
+            return Ok(m);
+        }
+        let m = self.doc["materials"][index.unwrap_or(usize::MAX)].clone();
+        let pbr = &m["pbrMetallicRoughness"];
+        let rgb = |j: &Json, default: f64| match j.as_f64_vec() {
+            Some(c) if c.len() >= 3 => color::new(c[0], c[1], c[2]),
+            _ => color::new(default, default, default),
+        };
+
+        let strength = m["extensions"]["KHR_materials_emissive_strength"]["emissiveStrength"]
+            .as_f64()
+            .unwrap_or(1.0);
+        let emissive = rgb(&m["emissiveFactor"], 0.0) * strength;
+        let base = rgb(&pbr["baseColorFactor"], 1.0);
+        let mut metallic = pbr["metallicFactor"].as_f64().unwrap_or(1.0);
+        let mut roughness = pbr["roughnessFactor"].as_f64().unwrap_or(1.0);
+        if let Some(t) = self.texture(&pbr["metallicRoughnessTexture"])? {
+            // Roughness is in green and metalness in blue.
+            let mean = t.mean();
+            roughness *= mean.y;
+            metallic *= mean.z;
+        }
+        let transmission = m["extensions"]["KHR_materials_transmission"]["transmissionFactor"]
+            .as_f64()
+            .unwrap_or(0.0);
+        let ior = m["extensions"]["KHR_materials_ior"]["ior"]
+            .as_f64()
+            .unwrap_or(1.5);
+
+        let material: Arc<dyn Material> = if emissive.x.max(emissive.y).max(emissive.z) > 0.0 {
+            match self.texture(&m["emissiveTexture"])? {
+                Some(t) => Arc::new(DiffuseLight::new(Arc::new(TintTexture::new(t, emissive)))),
+                None => Arc::new(DiffuseLight::new_by_color(emissive)),
+            }
+        } else if transmission > 0.5 {
+            Arc::new(Dielectric::new(ior))
+        } else {
+            let texture: Arc<dyn Texture> = match self.texture(&pbr["baseColorTexture"])? {
+                Some(t) => Arc::new(TintTexture::new(t, base)),
+                None if vertex_colors => Arc::new(TintTexture::new(
+                    Arc::new(VertexColorTexture::new_by_color(color::new(1.0, 1.0, 1.0))),
+                    base,
+                )),
+                None => Arc::new(Solid::new(base)),
+            };
+            if metallic >= 0.5 {
+                Arc::new(Metal::new_by_texture(texture, roughness))
+            } else {
+                Arc::new(Lambertian::new(texture))
+            }
+        };
+        let material: Arc<dyn Material> = match self.texture(&m["normalTexture"])? {
+            Some(t) if emissive.x.max(emissive.y).max(emissive.z) <= 0.0 => {
+                let scale = m["normalTexture"]["scale"].as_f64().unwrap_or(1.0);
+                Arc::new(NormalMapped::new(material, t, scale))
+            }
+            _ => material,
+        };
+        if let Some(i) = index.filter(|&i| i < self.materials.len() && !vertex_colors) {
+            self.materials[i] = Some(material.clone());
+        }
+        Ok(material)
+    }
+
+    /// All primitives of a mesh under one BVH, in the mesh's own space.
+    fn mesh(&mut self, index: usize) -> io::Result<Option<Arc<dyn Hittable>>> {
+        if let Some(Some(cached)) = self.meshes.get(index) {
+            return Ok(cached.clone());
+        }
+        let mut list = HittableList::new();
+        for prim in self.doc["meshes"][index]["primitives"].clone().members() {
+            self.add_primitive(prim, &mut list)?;
+        }
+        let prototype: Option<Arc<dyn Hittable>> = if list.objects.is_empty() {
+            None
+        } else {
+            Some(Arc::new(LinearBvh::from(&mut list, 0.0, 1.0)))
+        };
+        if let Some(slot) = self.meshes.get_mut(index) {
+            *slot = Some(prototype.clone());
+        }
+        Ok(prototype)
+    }
+
+    fn add_primitive(&mut self, prim: &Json, list: &mut HittableList) -> io::Result<()> {
+        let mode = prim["mode"].as_usize().unwrap_or(4);
+        if !(4..=6).contains(&mode) {
+            return Ok(());
+        }
+        let attr = &prim["attributes"];
+        let position = attr["POSITION"]
+            .as_usize()
+            .ok_or_else(|| invalid("glTF primitive without POSITION"))?;
+
+        let mut mesh = TriangleMesh::new();
+        let (p, pc) = self.accessor(position)?;
+        mesh.positions = p.chunks(pc).map(|v| Vec3::new(v[0], v[1], v[2])).collect();
+        let vertex_count = mesh.positions.len();
+        if let Some(n) = attr["NORMAL"].as_usize() {
+            let (n, nc) = self.accessor(n)?;
+            // Some exporters write zero normals. Those stay zero, and the
+            // triangles using them fall back to their face normal.
+            mesh.normals = n
+                .chunks(nc)
+                .map(|v| Vec3::new(v[0], v[1], v[2]))
+                .map(|n| {
+                    if n.squared_length() > 0.0 {
+                        n.unit()
+                    } else {
+                        n
+                    }
+                })
+                .collect();
+        }
+        if let Some(t) = attr["TEXCOORD_0"].as_usize() {
+            let (t, tc) = self.accessor(t)?;
+            // glTF puts v = 0 at the top of the image.
+            mesh.uvs = t.chunks(tc).map(|v| [v[0], 1.0 - v[1]]).collect();
+        }
+        if let Some(c) = attr["COLOR_0"].as_usize() {
+            let (c, cc) = self.accessor(c)?;
+            mesh.colors = c.chunks(cc).map(|v| color::new(v[0], v[1], v[2])).collect();
+        }
+        if mesh.normals.len() != vertex_count {
+            mesh.normals.clear();
+        }
+        if mesh.uvs.len() != vertex_count {
+            mesh.uvs.clear();
+        }
+        if mesh.colors.len() != vertex_count {
+            mesh.colors.clear();
+        }
+
+        let indices: Vec<u32> = match prim["indices"].as_usize() {
+            Some(i) => self.accessor(i)?.0.iter().map(|&x| x as u32).collect(),
+            None => (0..vertex_count as u32).collect(),
+        };
+        if indices.iter().any(|&i| i as usize >= vertex_count) {
+            return Err(invalid("glTF index out of range"));
+        }
+        let mut push = |a: u32, b: u32, c: u32| {
+            if a != b && b != c && a != c {
+                mesh.position_indices.extend_from_slice(&[a, b, c]);
+            }
+        };
+        match mode {
+            4 => indices.chunks_exact(3).for_each(|t| push(t[0], t[1], t[2])),
+            5 => {
+                for k in 2..indices.len() {
+                    if k % 2 == 0 {
+                        push(indices[k - 2], indices[k - 1], indices[k]);
+                    } else {
+                        push(indices[k - 1], indices[k - 2], indices[k]);
+                    }
+                }
+            }
+            _ => {
+                for k in 2..indices.len() {
+                    push(indices[0], indices[k - 1], indices[k]);
+                }
+            }
+        }
+        if mesh.face_count() == 0 {
+            return Ok(());
+        }
+        if !mesh.normals.is_empty() {
+            mesh.normal_indices = mesh.position_indices.clone();
+        }
+        if !mesh.uvs.is_empty() {
+            mesh.uv_indices = mesh.position_indices.clone();
+        }
+
+        let material = self.material(prim["material"].as_usize(), !mesh.colors.is_empty())?;
+        let mesh = Arc::new(mesh);
+        list.objects.reserve(mesh.face_count());
+        for face in 0..mesh.face_count() {
+            list.add(Arc::new(Triangle::new(
+                mesh.clone(),
+                face,
+                material.clone(),
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The JSON text and the binary chunk of a `.glb`.
+fn split_glb(bytes: &[u8]) -> io::Result<(String, Option<Vec<u8>>)> {
+    let word = |at: usize| -> io::Result<usize> {
+        bytes
+            .get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| invalid("glb ends early"))
+    };
+    if word(4)? != 2 {
+        return Err(invalid("only glb version 2 is supported"));
+    }
+    let length = word(8)?.min(bytes.len());
+    let mut text = None;
+    let mut bin = None;
+    let mut at = 12;
+    while at + 8 <= length {
+        let chunk_length = word(at)?;
+        let kind = word(at + 4)?;
+        let data = bytes
+            .get(at + 8..at + 8 + chunk_length)
+            .ok_or_else(|| invalid("glb chunk out of range"))?;
+        match kind {
+            0x4e4f_534a => {
+                text = Some(
+                    String::from_utf8(data.to_vec())
+                        .map_err(|_| invalid("glb JSON is not UTF-8"))?,
+                )
+            }
+            0x004e_4942 => bin = Some(data.to_vec()),
+            _ => {}
+        }
+        at += 8 + chunk_length;
+    }
+    Ok((text.ok_or_else(|| invalid("glb without JSON chunk"))?, bin))
+}
+
+/// Contents of a `data:` URI or of a file next to the glTF.
+fn read_uri(dir: &Path, uri: &str) -> io::Result<Vec<u8>> {
+    if let Some(rest) = uri.strip_prefix("data:") {
+        let comma = rest.find(',').ok_or_else(|| invalid("bad data URI"))?;
+        if !rest[..comma].ends_with(";base64") {
+            return Err(invalid("data URI is not base64"));
+        }
+        return decode_base64(&rest[comma + 1..]);
+    }
+    fs::read(dir.join(percent_decode(uri)))
+}
+
+fn percent_decode(s: &str) -> String {
+    let b = s.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < b.len() {
+        let hex = b
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (b[i], hex) {
+            (b'%', Some(x)) => {
+                out.push(x);
+                i += 3;
+            }
+            (c, _) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn decode_base64(s: &str) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let x = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' | b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return Err(invalid("bad base64")),
+        };
+        acc = (acc << 6) | x as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// `matrix` (column-major) or translation * rotation * scale, `None` when
+/// it collapses space.
+fn local_transform(node: &Json) -> Option<Transform> {
+    if let Some(m) = node["matrix"].as_f64_vec() {
+        return Transform::from_column_major(&m);
+    }
+    let vec3 = |key: &str, default: f64| match node[key].as_f64_vec() {
+        Some(v) if v.len() == 3 => Vec3::new(v[0], v[1], v[2]),
+        _ => Vec3::new(default, default, default),
+    };
+    let q = node["rotation"]
+        .as_f64_vec()
+        .filter(|v| v.len() == 4)
+        .unwrap_or_else(|| vec![0.0, 0.0, 0.0, 1.0]);
+    let scale = vec3("scale", 1.0);
+    if scale.x * scale.y * scale.z == 0.0 {
+        return None;
+    }
+    Some(Transform::trs(
+        vec3("translation", 0.0),
+        Transform::quaternion(q[0], q[1], q[2], q[3]),
+        scale,
+    ))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    fn base64(data: &[u8]) -> String {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+        for chunk in data.chunks(3) {
+            let b = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+            for k in 0..4 {
+                if k <= chunk.len() {
+                    out.push(ALPHABET[(n >> (18 - 6 * k) & 63) as usize] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    fn data_uri(mime: &str, data: &[u8]) -> String {
+        format!("data:{};base64,{}", mime, base64(data))
+    }
+
+    /// A 2x2 PNG of one colour.
+    fn png(rgb: [u8; 3]) -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(2, 2, image::Rgb(rgb));
+        let mut bytes = vec![];
+        image::DynamicImage::ImageRgb8(img)
+            .write_to(&mut bytes, image::ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let mut text = json.as_bytes().to_vec();
+        text.resize(text.len() + (4 - text.len() % 4) % 4, b' ');
+        let mut bin = bin.to_vec();
+        bin.resize(bin.len() + (4 - bin.len() % 4) % 4, 0);
+        let length = 12 + 8 + text.len() + 8 + bin.len();
+        let mut out = b"glTF".to_vec();
+        for word in [2, length, text.len(), 0x4e4f_534a].iter() {
+            out.extend_from_slice(&(*word as u32).to_le_bytes());
+        }
+        out.extend_from_slice(&text);
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(&0x004e_4942u32.to_le_bytes());
+        out.extend_from_slice(&bin);
+        out
+    }
+
+    fn floats(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|x| x.to_le_bytes().to_vec())
+            .collect()
+    }
+
+    /// One triangle in z = 0 whose second normal is zero, with two
+    /// materials: 0 metallic through the default factor, 1 with a
+    /// `metallicRoughnessTexture` that has no metal in it.
+    fn triangle_glb() -> Vec<u8> {
+        let mut bin = floats(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        bin.extend(floats(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0]));
+        for i in [0u16, 1, 2].iter() {
+            bin.extend_from_slice(&i.to_le_bytes());
+        }
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": {} }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 36, "byteLength": 36 }},
+                    {{ "buffer": 0, "byteOffset": 72, "byteLength": 6 }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                    {{ "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3" }},
+                    {{ "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+                ],
+                "images": [{{ "uri": "{}" }}, {{ "uri": "{}" }}],
+                "textures": [{{ "source": 0 }}, {{ "source": 1 }}],
+                "materials": [
+                    {{ "pbrMetallicRoughness": {{
+                        "baseColorTexture": {{ "index": 0 }}, "roughnessFactor": 0.0 }} }},
+                    {{ "pbrMetallicRoughness": {{
+                        "baseColorTexture": {{ "index": 0 }},
+                        "metallicRoughnessTexture": {{ "index": 1 }} }} }}
+                ],
+                "meshes": [{{ "primitives": [{{
+                    "attributes": {{ "POSITION": 0, "NORMAL": 1 }},
+                    "indices": 2, "material": 0 }}] }}],
+                "nodes": [{{ "mesh": 0, "translation": [0, 0, -1] }}],
+                "scenes": [{{ "nodes": [0] }}]
+            }}"#,
+            bin.len(),
+            data_uri("image/png", &png([255, 0, 0])),
+            data_uri("image/png", &png([0, 255, 0])),
+        );
+        glb(&json, &bin)
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(decode_base64("SGVsbG8=").unwrap(), b"Hello");
+        assert_eq!(decode_base64("SGVs\nbG8").unwrap(), b"Hello");
+        assert_eq!(decode_base64("-_8").unwrap(), [0xfb, 0xff]);
+        assert!(decode_base64("SGV*").is_err());
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode_base64(&base64(&data)).unwrap(), data);
+    }
+
+    #[test]
+    fn decodes_uris() {
+        assert_eq!(percent_decode("a%20b%2"), "a b%2");
+        let dir = Path::new("");
+        assert_eq!(
+            read_uri(dir, "data:application/octet-stream;base64,AAEC").unwrap(),
+            [0, 1, 2]
+        );
+        assert!(read_uri(dir, "data:text/plain,hello").is_err());
+    }
+
+    #[test]
+    fn reads_interleaved_and_normalized_accessors() {
+        // Two elements of a normalized u8 pair followed by a u16, 4 bytes apart.
+        let bin = [255u8, 0, 7, 0, 0, 255, 9, 0];
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": 8, "uri": "{}" }}],
+                "bufferViews": [{{ "buffer": 0, "byteLength": 8, "byteStride": 4 }}],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5121, "normalized": true,
+                       "count": 2, "type": "VEC2" }},
+                    {{ "bufferView": 0, "byteOffset": 2, "componentType": 5123,
+                       "count": 2, "type": "SCALAR" }},
+                    {{ "bufferView": 0, "byteOffset": 4, "componentType": 5126,
+                       "count": 2, "type": "VEC3" }},
+                    {{ "componentType": 5126, "count": 2, "type": "VEC2" }}
+                ]
+            }}"#,
+            data_uri("application/octet-stream", &bin)
+        );
+        let file = GltfFile::from_bytes(json.into_bytes(), PathBuf::new()).unwrap();
+        assert_eq!(file.accessor(0).unwrap(), (vec![1.0, 0.0, 0.0, 1.0], 2));
+        assert_eq!(file.accessor(1).unwrap(), (vec![7.0, 9.0], 1));
+        assert!(file.accessor(2).is_err());
+        assert_eq!(file.accessor(3).unwrap(), (vec![0.0; 4], 2));
+    }
+
+    #[test]
+    fn rejects_accessors_beyond_their_view() {
+        let bin = [0u8; 16];
+        let json = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "buffers": [{{ "byteLength": 16, "uri": "{}" }}],
+                "bufferViews": [
+                    {{ "buffer": 0, "byteLength": 16 }},
+                    {{ "buffer": 0, "byteLength": 16, "byteStride": 4611686018427387904 }},
+                    {{ "buffer": 0, "byteLength": 16, "byteStride": 0 }},
+                    {{ "buffer": 0, "byteOffset": 8, "byteLength": 18446744073709551615 }}
+                ],
+                "accessors": [
+                    {{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "SCALAR" }},
+                    {{ "bufferView": 0, "componentType": 5126, "count": 5, "type": "SCALAR" }},
+                    {{ "bufferView": 0, "componentType": 5126, "count": 4611686018427387904,
+                       "type": "MAT4" }},
+                    {{ "bufferView": 1, "componentType": 5126, "count": 8, "type": "SCALAR" }},
+                    {{ "bufferView": 2, "componentType": 5126, "count": 1000000000000,
+                       "type": "SCALAR" }},
+                    {{ "bufferView": 3, "componentType": 5126, "count": 1, "type": "SCALAR" }},
+                    {{ "componentType": 5126, "count": 1000000000000, "type": "VEC3" }}
+                ]
+            }}"#,
+            data_uri("application/octet-stream", &bin)
+        );
+        let file = GltfFile::from_bytes(json.into_bytes(), PathBuf::new()).unwrap();
+        assert_eq!(file.accessor(0).unwrap(), (vec![0.0; 4], 1));
+        for i in 1..7 {
+            assert!(file.accessor(i).is_err(), "accessor {}", i);
+        }
+    }
+
+    #[test]
+    fn loads_glb() {
+        let path = std::env::temp_dir().join(format!("raytracer-{}.glb", std::process::id()));
+        fs::write(&path, triangle_glb()).unwrap();
+        let scene = GltfScene::load(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        let scene = scene.unwrap();
+        assert_eq!(scene.objects.size(), 1);
+
+        let bvh = scene.to_bvh(0.0, 1.0);
+        let mut rec = HitRecord::new0();
+        let r = Ray::new(point3::new(0.5, 0.2, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(bvh.hit(r, 0.001, INFINITY, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-9);
+        // The zero normal makes the face normal be used.
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        let miss = Ray::new(point3::new(0.8, 0.8, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(!bvh.hit(miss, 0.001, INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn rejects_broken_glb() {
+        let bytes = triangle_glb();
+        for end in [8, 20, bytes.len() - 40].iter() {
+            assert!(GltfFile::from_bytes(bytes[..*end].to_vec(), PathBuf::new()).is_err());
+        }
+        let mut wrong_version = bytes;
+        wrong_version[4] = 1;
+        assert!(GltfFile::from_bytes(wrong_version, PathBuf::new()).is_err());
+    }
+
+    /// Scatters a ray hitting `z = 0` at 45 degrees, returning the
+    /// attenuation and whether it left as a mirror reflection.
+    fn scatter(material: &Arc<dyn Material>) -> (color, bool) {
+        let r = Ray::new(point3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0), 0.0);
+        let mut rec = HitRecord::new0();
+        rec.t = 1.0;
+        rec.p = point3::new(0.0, 0.0, 0.0);
+        rec.u = 0.5;
+        rec.v = 0.5;
+        rec.set_face_normal(&r, &Vec3::new(0.0, 0.0, 1.0));
+        let mut attenuation = color::zero();
+        let mut scattered = Ray::new0();
+        assert!(material.scatter(r, &rec, &mut attenuation, &mut scattered));
+        let mirror = Vec3::new(0.0, -1.0, 1.0).unit();
+        let is_mirror = (scattered.direction().unit() - mirror).length() < 1e-9;
+        (attenuation, is_mirror)
+    }
+
+    #[test]
+    fn metalness_comes_from_factor_and_texture() {
+        let mut file = GltfFile::from_bytes(triangle_glb(), PathBuf::new()).unwrap();
+        let red = color::new(1.0, 0.0, 0.0);
+
+        // Default metallicFactor 1: a mirror that keeps its base colour texture.
+        let (attenuation, is_mirror) = scatter(&file.material(Some(0), false).unwrap());
+        assert!(is_mirror);
+        assert!((attenuation - red).length() < 1e-9);
+
+        // The texture's blue channel says there is no metal.
+        let (attenuation, is_mirror) = scatter(&file.material(Some(1), false).unwrap());
+        assert!(!is_mirror);
+        assert!((attenuation - red).length() < 1e-9);
+    }
+}
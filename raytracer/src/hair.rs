@@ -0,0 +1,413 @@
+use crate::curve::*;
+use crate::hittable::*;
+use crate::linear_bvh::*;
+use crate::ray::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+
+/// One hair, a chain of cubic Bezier pieces tapering from `root_width` to
+/// `tip_width`.
+#[derive(Clone, Debug)]
+pub struct Strand {
+    pub spans: Vec<[point3; 4]>,
+    pub root_width: f64,
+    pub tip_width: f64,
+}
+
+impl Strand {
+    pub fn curves(&self, kind: CurveType, mat: Arc<dyn Material>) -> Vec<Curve> {
+        let n = self.spans.len() as f64;
+        let width = |s: f64| self.root_width * (1.0 - s) + self.tip_width * s;
+        self.spans
+            .iter()
+            .enumerate()
+            .map(|(i, cp)| {
+                let widths = (width(i as f64 / n), width((i + 1) as f64 / n));
+                Curve::new(*cp, widths, kind, mat.clone())
+            })
+            .collect()
+    }
+
+    /// Every strand's curves in one list.
+    pub fn to_list(strands: &[Strand], kind: CurveType, mat: Arc<dyn Material>) -> HittableList {
+        let mut list = HittableList::new();
+        for s in strands {
+            for c in s.curves(kind, mat.clone()) {
+                list.add(Arc::new(c));
+            }
+        }
+        list
+    }
+
+    pub fn to_bvh(
+        strands: &[Strand],
+        kind: CurveType,
+        mat: Arc<dyn Material>,
+        time0: f64,
+        time1: f64,
+    ) -> LinearBvh {
+        LinearBvh::from(&mut Self::to_list(strands, kind, mat), time0, time1)
+    }
+}
+
+/// Loads hair from a text file with one strand per line:
+///
+/// ```text
+/// # comment
+/// bezier  <root width> <tip width> x y z x y z ...   (3k + 1 points)
+/// bspline <root width> <tip width> x y z x y z ...   (4 or more points)
+/// ```
+pub fn load_strands(path: &str) -> io::Result<Vec<Strand>> {
+    let input = BufReader::new(File::open(path)?);
+    let mut strands = vec![];
+    for (line_no, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", path, line_no + 1, what),
+            )
+        };
+        let mut tokens = line.split_whitespace();
+        let kind = tokens.next().unwrap_or("");
+        let numbers: Option<Vec<f64>> = tokens.map(|t| t.parse().ok()).collect();
+        let numbers = numbers.ok_or_else(|| bad("bad number"))?;
+        if numbers.len() < 2 || numbers.len() % 3 != 2 {
+            return Err(bad("expected two widths and whole points"));
+        }
+        let points: Vec<point3> = numbers[2..]
+            .chunks(3)
+            .map(|c| point3::new(c[0], c[1], c[2]))
+            .collect();
+        let spans = match kind {
+            "bezier" => {
+                if points.len() < 4 || points.len() % 3 != 1 {
+                    return Err(bad("a Bezier strand needs 3k + 1 points"));
+                }
+                (0..(points.len() - 1) / 3)
+                    .map(|i| {
+                        [
+                            points[3 * i],
+                            points[3 * i + 1],
+                            points[3 * i + 2],
+                            points[3 * i + 3],
+                        ]
+                    })
+                    .collect()
+            }
+            "bspline" => {
+                if points.len() < 4 {
+                    return Err(bad("a B-spline strand needs 4 or more points"));
+                }
+                Curve::bspline_to_bezier(&points)
+            }
+            _ => return Err(bad("expected `bezier` or `bspline`")),
+        };
+        strands.push(Strand {
+            spans,
+            root_width: numbers[0],
+            tip_width: numbers[1],
+        });
+    }
+    Ok(strands)
+}
+
+/// Hair fibre scattering after d'Eon et al. and Chiang et al., as in pbrt:
+/// a reflection lobe, two transmission lobes and a residual, each with
+/// Gaussian-like spread along the fibre and logistic spread around it.
+/// Meant for `CurveType::Flat` curves, whose `v` gives where across the
+/// fibre the ray hit.
+pub struct HairMaterial {
+    /// Absorption inside the fibre per unit of its diameter.
+    pub sigma_a: color,
+    pub eta: f64,
+    /// Longitudinal roughness, 0 to 1.
+    pub beta_m: f64,
+    /// Azimuthal roughness, 0 to 1.
+    pub beta_n: f64,
+    /// Tilt of the cuticle scales in degrees.
+    pub alpha: f64,
+    v: [f64; 4],
+    s: f64,
+    sin_2k_alpha: [f64; 3],
+    cos_2k_alpha: [f64; 3],
+}
+
+impl HairMaterial {
+    pub fn new(sigma_a: color, beta_m: f64, beta_n: f64, alpha: f64) -> Self {
+        let v0 = (0.726 * beta_m + 0.812 * beta_m.powi(2) + 3.7 * beta_m.powi(20)).powi(2);
+        let s =
+            (PI / 8.0).sqrt() * (0.265 * beta_n + 1.194 * beta_n.powi(2) + 5.372 * beta_n.powi(22));
+        let mut sin_2k_alpha = [degrees_to_radians(alpha).sin(), 0.0, 0.0];
+        let mut cos_2k_alpha = [safe_sqrt(1.0 - sin_2k_alpha[0].powi(2)), 0.0, 0.0];
+        for i in 1..3 {
+            sin_2k_alpha[i] = 2.0 * cos_2k_alpha[i - 1] * sin_2k_alpha[i - 1];
+            cos_2k_alpha[i] = cos_2k_alpha[i - 1].powi(2) - sin_2k_alpha[i - 1].powi(2);
+        }
+        Self {
+            sigma_a,
+            eta: 1.55,
+            beta_m,
+            beta_n,
+            alpha,
+            v: [v0, 0.25 * v0, 4.0 * v0, 4.0 * v0],
+            s,
+            sin_2k_alpha,
+            cos_2k_alpha,
+        }
+    }
+
+    /// Absorption from eumelanin (brown to black) and pheomelanin (red)
+    /// concentrations, about 0 to 8 for natural hair.
+    pub fn from_melanin(eumelanin: f64, pheomelanin: f64, beta_m: f64, beta_n: f64) -> Self {
+        let sigma_a =
+            color::new(0.419, 0.697, 1.37) * eumelanin + color::new(0.187, 0.4, 1.05) * pheomelanin;
+        Self::new(sigma_a, beta_m, beta_n, 2.0)
+    }
+
+    /// Absorption that gives roughly colour `c` after many bounces.
+    pub fn from_color(c: color, beta_m: f64, beta_n: f64) -> Self {
+        let d = 5.969 - 0.215 * beta_n + 2.532 * beta_n.powi(2) - 10.73 * beta_n.powi(3)
+            + 5.574 * beta_n.powi(4)
+            + 0.245 * beta_n.powi(5);
+        let f = |x: f64| (x.max(1e-4).ln() / d).powi(2);
+        Self::new(color::new(f(c.x), f(c.y), f(c.z)), beta_m, beta_n, 2.0)
+    }
+
+    /// `sin theta_o` and `cos theta_o` tilted by the cuticle scales for
+    /// lobe `p`.
+    fn tilt(&self, p: usize, sin_o: f64, cos_o: f64) -> (f64, f64) {
+        let (s, c) = match p {
+            0 => (-self.sin_2k_alpha[1], self.cos_2k_alpha[1]),
+            1 => (self.sin_2k_alpha[0], self.cos_2k_alpha[0]),
+            2 => (self.sin_2k_alpha[2], self.cos_2k_alpha[2]),
+            _ => (0.0, 1.0),
+        };
+        (sin_o * c + cos_o * s, (cos_o * c - sin_o * s).abs())
+    }
+
+    /// Attenuation of each lobe and the angle `gamma_t` inside the fibre.
+    fn attenuation(&self, sin_o: f64, cos_o: f64, h: f64) -> ([color; 4], f64) {
+        let sin_t = sin_o / self.eta;
+        let cos_t = safe_sqrt(1.0 - sin_t * sin_t);
+        let etap = (self.eta * self.eta - sin_o * sin_o).sqrt() / cos_o;
+        let sin_gamma_t = (h / etap).clamp(-1.0, 1.0);
+        let cos_gamma_t = safe_sqrt(1.0 - sin_gamma_t * sin_gamma_t);
+        let path = 2.0 * cos_gamma_t / cos_t;
+        let tr = color::new(
+            (-self.sigma_a.x * path).exp(),
+            (-self.sigma_a.y * path).exp(),
+            (-self.sigma_a.z * path).exp(),
+        );
+        let f = fr_dielectric(cos_o * safe_sqrt(1.0 - h * h), self.eta);
+        let mut ap = [color::zero(); 4];
+        ap[0] = color::ones() * f;
+        ap[1] = tr * (1.0 - f).powi(2);
+        ap[2] = Vec3::elemul(ap[1], tr) * f;
+        let tf = tr * f;
+        ap[3] = Vec3::elemul(Vec3::elemul(ap[2], tr) * f, recip(color::ones() - tf));
+        (ap, sin_gamma_t.asin())
+    }
+
+    /// The BSDF times `|cos theta_i|`, and the density `sample` draws
+    /// `wi` with, both in the fibre frame.
+    fn eval(&self, wo: &Vec3, wi: &Vec3, h: f64) -> (color, f64) {
+        let (sin_o, cos_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let (sin_i, cos_i) = (wi.x, safe_sqrt(1.0 - wi.x * wi.x));
+        let phi = wi.z.atan2(wi.y) - wo.z.atan2(wo.y);
+        let gamma_o = h.clamp(-1.0, 1.0).asin();
+        let (ap, gamma_t) = self.attenuation(sin_o, cos_o, h);
+        let weights = lobe_weights(&ap);
+
+        let mut f = color::zero();
+        let mut pdf = 0.0;
+        for p in 0..3 {
+            let (sin_op, cos_op) = self.tilt(p, sin_o, cos_o);
+            let mn =
+                mp(cos_i, cos_op, sin_i, sin_op, self.v[p]) * np(phi, p, self.s, gamma_o, gamma_t);
+            f += ap[p] * mn;
+            pdf += weights[p] * mn;
+        }
+        let m3 = mp(cos_i, cos_o, sin_i, sin_o, self.v[3]) / (2.0 * PI);
+        f += ap[3] * m3;
+        pdf += weights[3] * m3;
+        (f, pdf)
+    }
+
+    fn sample(&self, wo: &Vec3, h: f64) -> Vec3 {
+        let (sin_o, cos_o) = (wo.x, safe_sqrt(1.0 - wo.x * wo.x));
+        let gamma_o = h.clamp(-1.0, 1.0).asin();
+        let (ap, gamma_t) = self.attenuation(sin_o, cos_o, h);
+        let weights = lobe_weights(&ap);
+
+        let mut u = random_double1();
+        let mut p = 0;
+        while p < 3 && u >= weights[p] {
+            u -= weights[p];
+            p += 1;
+        }
+        let (sin_op, cos_op) = self.tilt(p, sin_o, cos_o);
+
+        // Longitudinal angle from the lobe's `mp`.
+        let u1 = random_double1().max(1e-5);
+        let cos_theta = 1.0 + self.v[p] * (u1 + (1.0 - u1) * (-2.0 / self.v[p]).exp()).ln();
+        let sin_theta = safe_sqrt(1.0 - cos_theta * cos_theta);
+        let cos_phi = (2.0 * PI * random_double1()).cos();
+        let sin_i = -cos_theta * sin_op + sin_theta * cos_phi * cos_op;
+        let cos_i = safe_sqrt(1.0 - sin_i * sin_i);
+
+        // Azimuthal angle from the lobe's `np`.
+        let dphi = if p < 3 {
+            phi_p(p, gamma_o, gamma_t) + sample_trimmed_logistic(random_double1(), self.s)
+        } else {
+            2.0 * PI * random_double1()
+        };
+        let phi_i = wo.z.atan2(wo.y) + dphi;
+        Vec3::new(sin_i, cos_i * phi_i.cos(), cos_i * phi_i.sin())
+    }
+
+    /// The fibre frame of pbrt: x along the hair, z the normal of the
+    /// ribbon facing away from the ray.
+    fn frame(ray_in: &Ray, rec: &HitRecord) -> Option<(Vec3, Vec3, Vec3)> {
+        let x = rec.dpdu.unit();
+        let across = Vec3::cross(ray_in.direction(), x);
+        if across.squared_length() == 0.0 || across.x.is_nan() {
+            return None;
+        }
+        let z = Vec3::cross(x, across.unit());
+        Some((x, Vec3::cross(z, x), z))
+    }
+}
+
+impl Material for HairMaterial {
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let (x, y, z) = match Self::frame(&ray_in, rec) {
+            Some(frame) => frame,
+            None => return false,
+        };
+        let h = 2.0 * rec.v - 1.0;
+        let d = -ray_in.direction().unit();
+        let wo = Vec3::new(d * x, d * y, d * z);
+        let wi = self.sample(&wo, h);
+        let (f, pdf) = self.eval(&wo, &wi, h);
+        if pdf.is_nan() || pdf <= 0.0 {
+            return false;
+        }
+        *attenuation = f / pdf;
+        *scattered = Ray::new(rec.p, x * wi.x + y * wi.y + z * wi.z, ray_in.time());
+        true
+    }
+}
+
+fn safe_sqrt(x: f64) -> f64 {
+    x.max(0.0).sqrt()
+}
+
+fn recip(c: color) -> color {
+    color::new(1.0 / c.x, 1.0 / c.y, 1.0 / c.z)
+}
+
+/// Unpolarized Fresnel reflectance from outside a dielectric of index `eta`.
+fn fr_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(-1.0, 1.0).abs();
+    let sin_t = safe_sqrt(1.0 - cos_i * cos_i) / eta;
+    if sin_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = safe_sqrt(1.0 - sin_t * sin_t);
+    let parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+/// Chance of sampling each lobe, by its luminance.
+fn lobe_weights(ap: &[color; 4]) -> [f64; 4] {
+    let y: Vec<f64> = ap
+        .iter()
+        .map(|c| 0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z)
+        .collect();
+    let sum: f64 = y.iter().sum();
+    if sum.is_nan() || sum <= 0.0 {
+        return [1.0, 0.0, 0.0, 0.0];
+    }
+    [y[0] / sum, y[1] / sum, y[2] / sum, y[3] / sum]
+}
+
+/// Modified Bessel function of the first kind, order zero.
+fn bessel_i0(x: f64) -> f64 {
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f64;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+/// Longitudinal scattering.
+fn mp(cos_i: f64, cos_o: f64, sin_i: f64, sin_o: f64, v: f64) -> f64 {
+    let a = cos_i * cos_o / v;
+    let b = sin_i * sin_o / v;
+    if v <= 0.1 {
+        (log_bessel_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+/// Azimuth at which lobe `p` leaves.
+fn phi_p(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+/// Azimuthal scattering, a logistic around `phi_p` cut to `-pi..pi`.
+fn np(phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    let mut dphi = phi - phi_p(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    logistic(dphi, s) / (logistic_cdf(PI, s) - logistic_cdf(-PI, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64) -> f64 {
+    let k = logistic_cdf(PI, s) - logistic_cdf(-PI, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(-PI, s)) - 1.0).ln();
+    x.clamp(-PI, PI)
+}
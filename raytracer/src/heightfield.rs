@@ -0,0 +1,307 @@
+use crate::hittable::*;
+use crate::perlin::*;
+use crate::ray::*;
+use std::io;
+use std::sync::Arc;
+
+/// Terrain from an `nx` by `nz` grid of heights between 0 and 1, spread
+/// over `size.x` by `size.z` from the origin and raised `size.y` at 1.
+/// Each cell is two triangles. Rays walk the grid cell by cell and only
+/// test cells whose height range they pass through. There is no coarser
+/// level to skip empty stretches, so a ray crossing the field visits about
+/// `nx + nz` cells and the cost grows with the square root of the number
+/// of samples.
+pub struct Heightfield {
+    pub nx: usize,
+    pub nz: usize,
+    pub heights: Vec<f32>,
+    pub size: Vec3,
+    pub mp: Arc<dyn Material>,
+    min_height: f64,
+    max_height: f64,
+}
+
+/// Where a ray meets one of a cell's two triangles.
+struct CellHit {
+    t: f64,
+    /// Samples at the triangle's corners.
+    corners: [(usize, usize); 3],
+    b1: f64,
+    b2: f64,
+    normal: Vec3,
+}
+
+impl Heightfield {
+    /// `heights` holds `nx * nz` samples, x varying fastest.
+    pub fn new(
+        nx: usize,
+        nz: usize,
+        heights: Vec<f32>,
+        size: Vec3,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        assert!(
+            nx >= 2 && nz >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), nx * nz, "wrong number of height samples");
+        let (lo, hi) = heights
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), h| {
+                (lo.min(*h), hi.max(*h))
+            });
+        Self {
+            nx,
+            nz,
+            heights,
+            size,
+            mp: mat,
+            min_height: lo as f64 * size.y,
+            max_height: hi as f64 * size.y,
+        }
+    }
+
+    /// Heights from the brightness of a grayscale or colour image, one
+    /// sample per pixel, with the top row at z = 0.
+    pub fn from_image(path: &str, size: Vec3, mat: Arc<dyn Material>) -> io::Result<Self> {
+        let img = image::open(path)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, e)))?
+            .to_luma16();
+        let (w, h) = img.dimensions();
+        if w < 2 || h < 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: heightfield image must be at least 2x2", path),
+            ));
+        }
+        let heights = img.pixels().map(|p| p[0] as f32 / 65535.0).collect();
+        Ok(Self::new(w as usize, h as usize, heights, size, mat))
+    }
+
+    /// Procedural terrain from `Perlin::turb`, with hills about
+    /// `1 / frequency` of the field across.
+    pub fn from_noise(
+        noise: &Perlin,
+        nx: usize,
+        nz: usize,
+        frequency: f64,
+        size: Vec3,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        assert!(
+            nx >= 2 && nz >= 2,
+            "a heightfield needs at least 2x2 samples"
+        );
+        let mut heights = Vec::with_capacity(nx * nz);
+        for j in 0..nz {
+            for i in 0..nx {
+                let p = point3::new(i as f64 / (nx - 1) as f64, 0.0, j as f64 / (nz - 1) as f64)
+                    * frequency;
+                heights.push(noise.turb(&p).min(1.0) as f32);
+            }
+        }
+        Self::new(nx, nz, heights, size, mat)
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.size.x / (self.nx - 1) as f64,
+            self.size.z / (self.nz - 1) as f64,
+        )
+    }
+
+    /// Height of sample `(i, j)`, clamped to the grid.
+    fn height(&self, i: isize, j: isize) -> f64 {
+        let i = i.clamp(0, self.nx as isize - 1) as usize;
+        let j = j.clamp(0, self.nz as isize - 1) as usize;
+        self.heights[j * self.nx + i] as f64 * self.size.y
+    }
+
+    fn vertex(&self, i: usize, j: usize) -> point3 {
+        let (dx, dz) = self.cell_size();
+        point3::new(
+            i as f64 * dx,
+            self.height(i as isize, j as isize),
+            j as f64 * dz,
+        )
+    }
+
+    /// Smooth normal at sample `(i, j)` from central differences.
+    fn vertex_normal(&self, i: usize, j: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        let (i, j) = (i as isize, j as isize);
+        let sx = (self.height(i + 1, j) - self.height(i - 1, j)) / (2.0 * dx);
+        let sz = (self.height(i, j + 1) - self.height(i, j - 1)) / (2.0 * dz);
+        Vec3::new(-sx, 1.0, -sz).unit()
+    }
+
+    /// The two triangles of cell `(i, j)`, closest hit before `t_max`.
+    fn intersect_cell(
+        &self,
+        r: &Ray,
+        i: usize,
+        j: usize,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<CellHit> {
+        let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+        let mut closest = t_max;
+        let mut found = None;
+        for tri in [[0, 1, 2], [0, 2, 3]].iter() {
+            let p0 = self.vertex(corners[tri[0]].0, corners[tri[0]].1);
+            let p1 = self.vertex(corners[tri[1]].0, corners[tri[1]].1);
+            let p2 = self.vertex(corners[tri[2]].0, corners[tri[2]].1);
+            let e1 = p1 - p0;
+            let e2 = p2 - p0;
+            let pvec = Vec3::cross(r.direction(), e2);
+            let det = e1 * pvec;
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let inv_det = 1.0 / det;
+            let tvec = r.orign() - p0;
+            let b1 = (tvec * pvec) * inv_det;
+            if !(0.0..=1.0).contains(&b1) {
+                continue;
+            }
+            let qvec = Vec3::cross(tvec, e1);
+            let b2 = (r.direction() * qvec) * inv_det;
+            if b2 < 0.0 || b1 + b2 > 1.0 {
+                continue;
+            }
+            let t = (e2 * qvec) * inv_det;
+            if t < t_min || t > closest {
+                continue;
+            }
+            closest = t;
+            found = Some(CellHit {
+                t,
+                corners: [corners[tri[0]], corners[tri[1]], corners[tri[2]]],
+                b1,
+                b2,
+                normal: Vec3::cross(e2, e1),
+            });
+        }
+        found
+    }
+
+    fn fill(&self, r: &Ray, h: &CellHit, rec: &mut HitRecord) {
+        let n = h.normal;
+        let p = r.at(h.t);
+        let outward_normal = if n.y < 0.0 { -n.unit() } else { n.unit() };
+        let normal = |k: usize| self.vertex_normal(h.corners[k].0, h.corners[k].1);
+        let shading = normal(0) * (1.0 - h.b1 - h.b2) + normal(1) * h.b1 + normal(2) * h.b2;
+        rec.t = h.t;
+        rec.p = p;
+        rec.u = p.x / self.size.x;
+        rec.v = p.z / self.size.z;
+        rec.set_face_normal(r, &outward_normal);
+        rec.set_shading_normal(&shading);
+        rec.dpdu = Vec3::new(self.size.x, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.size.z);
+        rec.mat_ptr = self.mp.clone();
+    }
+
+    /// Walks the cells along the ray and returns the hit in the first one
+    /// the ray meets the surface in.
+    fn first_hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<CellHit> {
+        let mut bbox = AABB::new0();
+        self.bounding_box(0.0, 1.0, &mut bbox);
+        let (o, d) = (r.orign(), r.direction());
+
+        // Clip to the box, then walk the cells the ray crosses in order.
+        let (mut t0, mut t1) = (t_min, t_max);
+        for a in 0..3 {
+            let inv_d = 1.0 / d.get(a);
+            let mut near = (bbox.min().get(a) - o.get(a)) * inv_d;
+            let mut far = (bbox.max().get(a) - o.get(a)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 < t0 {
+                return None;
+            }
+        }
+
+        let (dx, dz) = self.cell_size();
+        let start = r.at(t0);
+        let mut i = ((start.x / dx) as isize).clamp(0, self.nx as isize - 2);
+        let mut j = ((start.z / dz) as isize).clamp(0, self.nz as isize - 2);
+        let step_i: isize = if d.x > 0.0 { 1 } else { -1 };
+        let step_j: isize = if d.z > 0.0 { 1 } else { -1 };
+        let next_boundary = |cell: isize, step: isize, cell_size: f64, o: f64, d: f64| {
+            if d == 0.0 {
+                return INFINITY;
+            }
+            let edge = (cell + if step > 0 { 1 } else { 0 }) as f64 * cell_size;
+            (edge - o) / d
+        };
+        let mut t_next_x = next_boundary(i, step_i, dx, o.x, d.x);
+        let mut t_next_z = next_boundary(j, step_j, dz, o.z, d.z);
+        let t_step_x = if d.x == 0.0 { INFINITY } else { dx / d.x.abs() };
+        let t_step_z = if d.z == 0.0 { INFINITY } else { dz / d.z.abs() };
+
+        let mut t_enter = t0;
+        loop {
+            let t_exit = t_next_x.min(t_next_z).min(t1);
+            // Skip cells the ray passes entirely above or below.
+            let (y0, y1) = (o.y + t_enter * d.y, o.y + t_exit * d.y);
+            let corner_heights = [
+                self.height(i, j),
+                self.height(i + 1, j),
+                self.height(i, j + 1),
+                self.height(i + 1, j + 1),
+            ];
+            let lo = corner_heights.iter().cloned().fold(INFINITY, f64::min);
+            let hi = corner_heights.iter().cloned().fold(-INFINITY, f64::max);
+            if y0.min(y1) <= hi && y0.max(y1) >= lo {
+                let found = self.intersect_cell(r, i as usize, j as usize, t_min, t_max);
+                if found.is_some() {
+                    return found;
+                }
+            }
+
+            if t_exit >= t1 {
+                return None;
+            }
+            if t_next_x < t_next_z {
+                i += step_i;
+                t_enter = t_next_x;
+                t_next_x += t_step_x;
+            } else {
+                j += step_j;
+                t_enter = t_next_z;
+                t_next_z += t_step_z;
+            }
+            if i < 0 || j < 0 || i > self.nx as isize - 2 || j > self.nz as isize - 2 {
+                return None;
+            }
+        }
+    }
+}
+
+impl Hittable for Heightfield {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.first_hit(&r, t_min, t_max) {
+            Some(h) => {
+                self.fill(&r, &h, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.first_hit(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = AABB::new(
+            point3::new(0.0, self.min_height - 0.0001, 0.0),
+            point3::new(self.size.x, self.max_height + 0.0001, self.size.z),
+        );
+        true
+    }
+}
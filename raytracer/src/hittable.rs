@@ -11,12 +11,25 @@ pub use Vec3 as color;
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: point3,
+    /// Shading normal, on the side the ray came from.
     pub normal: Vec3,
+    /// Normal of the actual surface, on the same side as `normal`.
+    pub geometric_normal: Vec3,
+    /// Derivatives of `p` along `u` and `v`, zero where there are none.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
     pub mat_ptr: Arc<dyn Material>, //材料
+    /// Interpolated vertex colour, for meshes that carry one.
+    pub vertex_color: Option<color>,
+}
+
+thread_local! {
+    /// Material for records that are thrown away after the hit.
+    static NO_MATERIAL: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(color::zero()));
 }
 
 impl HitRecord {
@@ -24,24 +37,39 @@ impl HitRecord {
         Self {
             p: Vec3::zero(),
             normal: Vec3::zero(),
+            geometric_normal: Vec3::zero(),
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
             t: 0.0,
             front_face: false,
             mat_ptr: Arc::new(Metal::new1(color::new(0.0, 0.0, 0.0))),
             u: 0.0,
             v: 0.0,
+            vertex_color: None,
         }
     }
+    /// A record for when only whether and where something was hit
+    /// matters. Unlike `new0` it does not allocate.
+    pub fn scratch() -> Self {
+        NO_MATERIAL.with(|mp| Self::new(mp.clone()))
+    }
     pub fn new(mp: Arc<dyn Material>) -> Self {
         Self {
             p: Vec3::zero(),
             normal: Vec3::zero(),
+            geometric_normal: Vec3::zero(),
+            dpdu: Vec3::zero(),
+            dpdv: Vec3::zero(),
             t: 0.0,
             front_face: false,
             mat_ptr: mp,
             u: 0.0,
             v: 0.0,
+            vertex_color: None,
         }
     }
+    /// Sets both normals to `outward_normal` turned against the ray and
+    /// clears the tangents and vertex colour, which primitives fill in after.
     pub fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_face = (r.direction() * (*outward_normal)) < 0.0;
         self.normal = if self.front_face {
@@ -49,12 +77,68 @@ impl HitRecord {
         } else {
             -(*outward_normal)
         };
+        self.geometric_normal = self.normal;
+        self.dpdu = Vec3::zero();
+        self.dpdv = Vec3::zero();
+        self.vertex_color = None;
+    }
+
+    /// Shades with `n` instead of the geometric normal, turned to the side
+    /// the ray came from.
+    pub fn set_shading_normal(&mut self, n: &Vec3) {
+        self.normal = if *n * self.geometric_normal < 0.0 {
+            -n.unit()
+        } else {
+            n.unit()
+        };
+    }
+
+    /// Orthonormal tangent, bitangent and shading normal. The tangent
+    /// follows `dpdu` where there is one, so anisotropic and normal-mapped
+    /// materials line up with the texture.
+    pub fn tangent_frame(&self) -> (Vec3, Vec3, Vec3) {
+        let n = self.normal;
+        let mut t = self.dpdu - n * (self.dpdu * n);
+        if t.squared_length() < 1e-16 {
+            let a = if n.x.abs() > 0.9 {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            };
+            t = Vec3::cross(a, n);
+        }
+        let t = t.unit();
+        (t, Vec3::cross(n, t), n)
+    }
+
+    /// Whether `dir` leaves on the side the ray came from. Shading normals
+    /// can say yes when the surface itself says no, which leaks light.
+    pub fn same_side(&self, dir: &Vec3) -> bool {
+        *dir * self.geometric_normal > 0.0 && *dir * self.normal > 0.0
     }
 }
 
 pub trait Hittable: Sync + Send {
     fn hit(&self, ray: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool;
+
+    /// Whether anything lies on the ray between `t_min` and `t_max`. Stops
+    /// at the first hit found, in any order, and fills no record, so
+    /// shadow and ambient occlusion rays should use it over `hit`.
+    fn occluded(&self, ray: Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(ray, t_min, t_max, &mut HitRecord::scratch())
+    }
+
+    /// Solid angle density `random` picks `dir` from `origin` with, zero
+    /// for shapes that cannot be sampled as lights.
+    fn pdf_value(&self, _origin: &point3, _dir: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// A direction from `origin` towards a random point on the shape.
+    fn random(&self, _origin: &point3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
 }
 #[derive(Clone)]
 pub struct HittableList {
@@ -96,6 +180,9 @@ impl Hittable for HittableList {
         }
         hit_anything
     }
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.objects.iter().any(|o| o.occluded(r, t_min, t_max))
+    }
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
         if self.objects.is_empty() {
             return false;
@@ -115,140 +202,83 @@ impl Hittable for HittableList {
         }
         true
     }
-}
-
-pub struct Translate {
-    ptr: Arc<dyn Hittable>,
-    offset: Vec3,
-}
 
-impl Translate {
-    pub fn new(p: Arc<dyn Hittable>, displacement: &Vec3) -> Self {
-        Self {
-            ptr: p,
-            offset: *displacement,
-        }
-    }
-}
-
-impl Hittable for Translate {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let moved_r = Ray {
-            orig: r.orig - self.offset,
-            dir: r.dir,
-            tm: r.tm,
-        };
-        if !self.ptr.hit(moved_r, t_min, t_max, rec) {
-            return false;
-        }
-
-        rec.p += self.offset;
-        let normal = rec.normal;
-        rec.set_face_normal(&moved_r, &normal);
-
-        true
-    }
-
-    fn bounding_box(&self, t0: f64, t1: f64, output_box: &mut AABB) -> bool {
-        if !self.ptr.bounding_box(t0, t1, output_box) {
-            return false;
+    /// Picks one member uniformly, so the density is their average.
+    fn pdf_value(&self, origin: &point3, dir: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
         }
-        *output_box = AABB::new(
-            output_box.min() + self.offset,
-            output_box.max() + self.offset,
-        );
-        true
+        let sum: f64 = self.objects.iter().map(|o| o.pdf_value(origin, dir)).sum();
+        sum / self.size() as f64
     }
-}
 
-pub struct RotateY {
-    pub ptr: Arc<dyn Hittable>,
-    pub sin_theta: f64,
-    pub cos_theta: f64,
-    pub hasbox: bool,
-    pub bbox: AABB,
-}
-
-impl RotateY {
-    pub fn new(p: Arc<dyn Hittable>, angle: f64) -> Self {
-        let radians = degrees_to_radians(angle);
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let mut min = point3::new(INFINITY, INFINITY, INFINITY);
-        let mut max = point3::new(-INFINITY, -INFINITY, -INFINITY);
-        let mut bbox = AABB::new(point3::ones(), point3::ones());
-        let hasbox = p.bounding_box(0.0, 1.0, &mut bbox);
-        for i in 0..2 {
-            for j in 0..2 {
-                for k in 0..2 {
-                    let x = i as f64 * bbox.max().x + (1 - i) as f64 * bbox.min().x;
-                    let y = j as f64 * bbox.max().y + (1 - j) as f64 * bbox.min().y;
-                    let z = k as f64 * bbox.max().z + (1 - k) as f64 * bbox.min().z;
-
-                    let newx = cos_theta * x + sin_theta * z;
-                    let newz = -sin_theta * x + cos_theta * z;
-
-                    let tester = Vec3::new(newx, y, newz);
-
-                    min.x = min.x.min(tester.x);
-                    max.x = max.x.max(tester.x);
-
-                    min.y = min.y.min(tester.y);
-                    max.y = max.y.max(tester.y);
-
-                    min.z = min.z.min(tester.z);
-                    max.z = max.z.max(tester.z);
-                }
-            }
-        }
-        let bbox = AABB::new(min, max);
-        Self {
-            ptr: p,
-            sin_theta,
-            cos_theta,
-            hasbox,
-            bbox,
+    fn random(&self, origin: &point3) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
         }
+        let i = ((random_double1() * self.size() as f64) as usize).min(self.size() - 1);
+        self.objects[i].random(origin)
     }
 }
 
-impl Hittable for RotateY {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let mut orig = r.orig;
-        let mut dir = r.dir;
-
-        orig.x = self.cos_theta * r.orig.x - self.sin_theta * r.orig.z;
-        orig.z = self.sin_theta * r.orig.x + self.cos_theta * r.orig.z;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csg::Csg;
+    use crate::curve::*;
+    use crate::heightfield::Heightfield;
+    use crate::perlin::Perlin;
+    use crate::sdf::*;
 
-        dir.x = self.cos_theta * r.dir.x - self.sin_theta * r.dir.z;
-        dir.z = self.sin_theta * r.dir.x + self.cos_theta * r.dir.z;
-
-        let rotated_r = Ray {
-            orig,
-            dir,
-            tm: r.tm,
+    /// `occluded` must say yes exactly when `hit` finds something in range.
+    #[test]
+    fn occluded_agrees_with_hit() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(color::new(0.5, 0.5, 0.5)));
+        let sphere = |x: f64, radius: f64| -> Arc<dyn Hittable> {
+            Arc::new(Sphere::new(point3::new(x, 0.0, 0.0), radius, mat.clone()))
         };
-
-        if !self.ptr.hit(rotated_r, t_min, t_max, rec) {
-            return false;
+        let objects: Vec<Arc<dyn Hittable>> = vec![
+            sphere(0.0, 1.0),
+            Arc::new(Csg::difference(sphere(0.0, 1.0), sphere(0.5, 0.8))),
+            Arc::new(Csg::intersection(sphere(-0.5, 1.0), sphere(0.5, 1.0))),
+            Arc::new(Csg::union(sphere(-0.5, 1.0), sphere(0.5, 1.0))),
+            Arc::new(Heightfield::from_noise(
+                &Perlin::new(),
+                33,
+                33,
+                2.0,
+                Vec3::new(2.0, 1.0, 2.0),
+                mat.clone(),
+            )),
+            Arc::new(SdfObject::new(
+                Arc::new(SdfTorus::new(point3::zero(), 0.8, 0.3)),
+                AABB::new(point3::new(-1.2, -0.4, -1.2), point3::new(1.2, 0.4, 1.2)),
+                mat.clone(),
+            )),
+            Arc::new(Curve::new(
+                [
+                    point3::new(-1.0, 0.0, 0.0),
+                    point3::new(-0.3, 1.0, 0.2),
+                    point3::new(0.3, -1.0, -0.2),
+                    point3::new(1.0, 0.0, 0.0),
+                ],
+                (0.3, 0.1),
+                CurveType::Cylinder,
+                mat.clone(),
+            )),
+        ];
+        for object in &objects {
+            let mut hits = 0;
+            for _i in 0..3000 {
+                let target = Vec3::random2(-1.0, 1.0);
+                let origin = Vec3::random2(-3.0, 3.0);
+                let r = Ray::new(origin, target - origin, 0.0);
+                let t_max = random_double2(0.2, 2.0);
+                let hit = object.hit(r, 0.001, t_max, &mut HitRecord::scratch());
+                assert_eq!(object.occluded(r, 0.001, t_max), hit);
+                hits += hit as usize;
+            }
+            assert!(hits > 100);
         }
-
-        let mut p = rec.p;
-        let mut normal = rec.normal;
-
-        p.x = self.cos_theta * rec.p.x + self.sin_theta * rec.p.z;
-        p.z = -self.sin_theta * rec.p.x + self.cos_theta * rec.p.z;
-
-        normal.x = self.cos_theta * rec.normal.x + self.sin_theta * rec.normal.z;
-        normal.z = -self.sin_theta * rec.normal.x + self.cos_theta * rec.normal.z;
-
-        rec.p = p;
-        rec.set_face_normal(&rotated_r, &normal);
-
-        true
-    }
-    fn bounding_box(&self, _t0: f64, _t1: f64, output_box: &mut AABB) -> bool {
-        *output_box = self.bbox;
-        self.hasbox
     }
 }
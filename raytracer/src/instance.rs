@@ -0,0 +1,63 @@
+use crate::transform::*;
+use crate::wide_bvh::*;
+use std::sync::Arc;
+
+/// One copy of a shared prototype, such as a mesh `LinearBvh`. Only the
+/// transform and an optional material are stored per copy, so many
+/// instances of a large object cost little more than one.
+pub struct Instance {
+    pub object: Transformed,
+    /// Replaces the prototype's materials when set.
+    pub material: Option<Arc<dyn Material>>,
+}
+
+impl Instance {
+    pub fn new(prototype: Arc<dyn Hittable>, transform: Transform) -> Self {
+        Self {
+            object: Transformed::new(prototype, transform),
+            material: None,
+        }
+    }
+
+    pub fn with_material(
+        prototype: Arc<dyn Hittable>,
+        transform: Transform,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            object: Transformed::new(prototype, transform),
+            material: Some(material),
+        }
+    }
+
+    /// Top-level BVH over `instances`. Each prototype keeps its own BVH,
+    /// built once however often it is placed.
+    pub fn bvh(instances: Vec<Instance>, time0: f64, time1: f64) -> Bvh4 {
+        let mut list = HittableList::new();
+        list.objects.reserve(instances.len());
+        for i in instances {
+            list.add(Arc::new(i));
+        }
+        Bvh4::from(&mut list, time0, time1)
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.object.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+        if let Some(m) = &self.material {
+            rec.mat_ptr = m.clone();
+        }
+        true
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.object.occluded(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.object.bounding_box(time0, time1, output_box)
+    }
+}
@@ -0,0 +1,609 @@
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::ray::*;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+/// Nodes the traversal stack holds without allocating.
+const STACK_SIZE: usize = 64;
+/// How many times its cost when built a subtree may grow to before
+/// `update` rebuilds it.
+pub const REBUILD_THRESHOLD: f64 = 1.5;
+
+/// One node of a `LinearBvh`, 32 bytes so two fit in a cache line. The
+/// bounds are `f32`, rounded outwards so they still contain their
+/// primitives.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, align(32))]
+pub struct LinearBvhNode {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    /// First primitive of a leaf, or the second child of an interior node,
+    /// whose first child comes straight after it.
+    pub offset: u32,
+    /// Primitives in a leaf; zero for interior nodes.
+    pub count: u16,
+    /// Split axis of an interior node.
+    pub axis: u8,
+    _pad: u8,
+}
+
+impl LinearBvhNode {
+    pub fn bbox(&self) -> AABB {
+        AABB::new(
+            point3::new(self.min[0] as f64, self.min[1] as f64, self.min[2] as f64),
+            point3::new(self.max[0] as f64, self.max[1] as f64, self.max[2] as f64),
+        )
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+
+    /// The node as stored in a BVH cache file, little-endian.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let mut b = [0; 32];
+        for (i, x) in self.min.iter().chain(self.max.iter()).enumerate() {
+            b[i * 4..i * 4 + 4].copy_from_slice(&x.to_le_bytes());
+        }
+        b[24..28].copy_from_slice(&self.offset.to_le_bytes());
+        b[28..30].copy_from_slice(&self.count.to_le_bytes());
+        b[30] = self.axis;
+        b
+    }
+
+    pub fn from_bytes(b: &[u8; 32]) -> Self {
+        let f = |i: usize| f32::from_le_bytes(b[i * 4..i * 4 + 4].try_into().unwrap());
+        Self {
+            min: [f(0), f(1), f(2)],
+            max: [f(3), f(4), f(5)],
+            offset: u32::from_le_bytes(b[24..28].try_into().unwrap()),
+            count: u16::from_le_bytes(b[28..30].try_into().unwrap()),
+            axis: b[30],
+            _pad: 0,
+        }
+    }
+
+    fn set_bounds(&mut self, b: &AABB) {
+        self.min = [
+            round_down(b.min().x),
+            round_down(b.min().y),
+            round_down(b.min().z),
+        ];
+        self.max = [
+            round_up(b.max().x),
+            round_up(b.max().y),
+            round_up(b.max().z),
+        ];
+    }
+}
+
+/// A `BvhNode` tree flattened into an array in depth-first order, with the
+/// primitives in one array in leaf order. Traversal is a loop over a small
+/// stack instead of virtual calls through every node.
+///
+/// For animation the tree can be kept from frame to frame: swap in the
+/// moved primitives with `set_primitive`, then `update` refits the boxes
+/// and rebuilds just the subtrees that have got much worse than they were.
+pub struct LinearBvh {
+    pub nodes: Vec<LinearBvhNode>,
+    pub primitives: Vec<Arc<dyn Hittable>>,
+    /// Longest path from the root, which bounds the traversal stack.
+    pub depth: usize,
+    /// Expected cost of each node's subtree when it was built.
+    built_cost: Vec<f64>,
+}
+
+impl LinearBvh {
+    pub fn from(list: &mut HittableList, time0: f64, time1: f64) -> Self {
+        Self::flatten(&BvhNode::from(list, time0, time1))
+    }
+
+    pub fn flatten(root: &BvhNode) -> Self {
+        let mut bvh = Self {
+            nodes: vec![],
+            primitives: vec![],
+            depth: 0,
+            built_cost: vec![],
+        };
+        // An empty tree is a leaf with nothing in it, which a zero count
+        // would mark as interior.
+        if let BvhContent::Leaf(objects) = &root.content {
+            if objects.is_empty() {
+                return bvh;
+            }
+        }
+        bvh.push(root, 1);
+        bvh.built_cost = bvh.subtree_costs();
+        bvh
+    }
+
+    /// A tree from nodes in depth-first order over `primitives` in leaf
+    /// order, such as one read back from a cache.
+    pub fn from_parts(nodes: Vec<LinearBvhNode>, primitives: Vec<Arc<dyn Hittable>>) -> Self {
+        let mut bvh = Self {
+            nodes,
+            primitives,
+            depth: 0,
+            built_cost: vec![],
+        };
+        bvh.depth = bvh.measure_depth();
+        bvh.built_cost = bvh.subtree_costs();
+        bvh
+    }
+
+    fn push(&mut self, node: &BvhNode, depth: usize) -> usize {
+        self.depth = self.depth.max(depth);
+        let index = self.nodes.len();
+        let mut flat = LinearBvhNode {
+            min: [0.0; 3],
+            max: [0.0; 3],
+            offset: 0,
+            count: 0,
+            axis: 0,
+            _pad: 0,
+        };
+        flat.set_bounds(&node.bvh_box);
+        self.nodes.push(flat);
+        match &node.content {
+            BvhContent::Interior { left, right, axis } => {
+                self.push(left, depth + 1);
+                let second = self.push(right, depth + 1);
+                self.nodes[index].offset = second as u32;
+                self.nodes[index].axis = *axis as u8;
+            }
+            BvhContent::Leaf(objects) => {
+                self.nodes[index].offset = self.primitives.len() as u32;
+                self.nodes[index].count = objects.len() as u16;
+                self.primitives.extend(objects.iter().cloned());
+            }
+        }
+        index
+    }
+
+    /// Replaces primitive `index`, counted in `primitives`, with `object`,
+    /// usually the same thing moved. The boxes are stale until `refit` or
+    /// `update`.
+    pub fn set_primitive(&mut self, index: usize, object: Arc<dyn Hittable>) {
+        self.primitives[index] = object;
+    }
+
+    /// Refits the boxes to the primitives over `time0`-`time1` and rebuilds
+    /// the subtrees whose cost has grown past `REBUILD_THRESHOLD` times what
+    /// it was. Returns how many subtrees were rebuilt. Rebuilding reorders
+    /// the primitives under those subtrees.
+    pub fn update(&mut self, time0: f64, time1: f64) -> usize {
+        self.refit(time0, time1);
+        self.rebuild_degraded(REBUILD_THRESHOLD, time0, time1)
+    }
+
+    /// Recomputes every box bottom-up, keeping the tree as it is.
+    pub fn refit(&mut self, time0: f64, time1: f64) {
+        // Children always come after their parent.
+        for i in (0..self.nodes.len()).rev() {
+            let node = self.nodes[i];
+            let b = if node.is_leaf() {
+                let first = node.offset as usize;
+                self.primitives[first..first + node.count as usize]
+                    .iter()
+                    .fold(AABB::empty(), |b, object| {
+                        let mut object_box = AABB::new0();
+                        if !object.bounding_box(time0, time1, &mut object_box) {
+                            eprintln!("No bounding box in LinearBvh refit");
+                        }
+                        AABB::surrounding_box(&b, &object_box)
+                    })
+            } else {
+                AABB::surrounding_box(
+                    &self.nodes[i + 1].bbox(),
+                    &self.nodes[node.offset as usize].bbox(),
+                )
+            };
+            self.nodes[i].set_bounds(&b);
+        }
+    }
+
+    /// Rebuilds the largest subtrees whose expected cost is over
+    /// `threshold` times their cost when built, and returns how many.
+    pub fn rebuild_degraded(&mut self, threshold: f64, time0: f64, time1: f64) -> usize {
+        let cost = self.subtree_costs();
+        let mut degraded = vec![];
+        let mut i = 0;
+        while i < self.nodes.len() {
+            if !self.nodes[i].is_leaf() && cost[i] > threshold * self.built_cost[i] {
+                degraded.push(i);
+                i = self.subtree_end(i);
+            } else {
+                i += 1;
+            }
+        }
+        // From the back, so rebuilding one does not move the others.
+        for i in degraded.iter().rev() {
+            self.rebuild_subtree(*i, time0, time1);
+        }
+        if !degraded.is_empty() {
+            self.depth = self.measure_depth();
+        }
+        degraded.len()
+    }
+
+    /// Builds the subtree at `index` again from its primitives and splices
+    /// it in place of the old one.
+    fn rebuild_subtree(&mut self, index: usize, time0: f64, time1: f64) {
+        let end = self.subtree_end(index);
+        let (first, last) = self.primitive_range(index);
+        let subtree = BvhNode::fromvec(&mut self.primitives, first, last, time0, time1);
+        let mut part = Self::flatten(&subtree);
+        for node in &mut part.nodes {
+            node.offset += if node.is_leaf() { first } else { index } as u32;
+        }
+        // Second-child links past the old subtree move with it.
+        let shift = part.nodes.len() as i64 - (end - index) as i64;
+        for (k, node) in self.nodes.iter_mut().enumerate() {
+            if (k < index || k >= end) && !node.is_leaf() && node.offset as usize >= end {
+                node.offset = (node.offset as i64 + shift) as u32;
+            }
+        }
+        self.nodes.splice(index..end, part.nodes);
+        self.built_cost.splice(index..end, part.built_cost);
+    }
+
+    /// One past the last node of the subtree at `index`.
+    fn subtree_end(&self, index: usize) -> usize {
+        let mut i = index;
+        while !self.nodes[i].is_leaf() {
+            i = self.nodes[i].offset as usize;
+        }
+        i + 1
+    }
+
+    /// The primitives under the node at `index`.
+    fn primitive_range(&self, index: usize) -> (usize, usize) {
+        let mut i = index;
+        while !self.nodes[i].is_leaf() {
+            i += 1;
+        }
+        let first = self.nodes[i].offset as usize;
+        let mut i = index;
+        while !self.nodes[i].is_leaf() {
+            i = self.nodes[i].offset as usize;
+        }
+        (
+            first,
+            (self.nodes[i].offset + self.nodes[i].count as u32) as usize,
+        )
+    }
+
+    /// Expected cost of each node's subtree for a ray through its box.
+    fn subtree_costs(&self) -> Vec<f64> {
+        let mut cost = vec![0.0; self.nodes.len()];
+        for i in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[i];
+            if node.is_leaf() {
+                cost[i] = node.count as f64;
+                continue;
+            }
+            let (left, right) = (i + 1, node.offset as usize);
+            let area = node.bbox().surface_area();
+            let children = if area > 0.0 {
+                (self.nodes[left].bbox().surface_area() * cost[left]
+                    + self.nodes[right].bbox().surface_area() * cost[right])
+                    / area
+            } else {
+                cost[left] + cost[right]
+            };
+            cost[i] = TRAVERSAL_COST + children;
+        }
+        cost
+    }
+
+    fn measure_depth(&self) -> usize {
+        let mut depth = vec![1; self.nodes.len()];
+        let mut deepest = 0;
+        for i in 0..self.nodes.len() {
+            deepest = deepest.max(depth[i]);
+            if !self.nodes[i].is_leaf() {
+                depth[i + 1] = depth[i] + 1;
+                depth[self.nodes[i].offset as usize] = depth[i] + 1;
+            }
+        }
+        deepest
+    }
+
+    /// Size, depth and expected cost of the tree, as `BvhNode::stats`.
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats {
+            interior_nodes: 0,
+            leaves: 0,
+            primitives: 0,
+            max_depth: self.depth,
+            min_leaf_size: usize::MAX,
+            max_leaf_size: 0,
+            sah_cost: 0.0,
+        };
+        let root_area = self.nodes.first().map_or(0.0, |n| n.bbox().surface_area());
+        let inv_root_area = if root_area > 0.0 {
+            1.0 / root_area
+        } else {
+            0.0
+        };
+        for node in &self.nodes {
+            let p = node.bbox().surface_area() * inv_root_area;
+            if node.is_leaf() {
+                let n = node.count as usize;
+                stats.leaves += 1;
+                stats.primitives += n;
+                stats.min_leaf_size = stats.min_leaf_size.min(n);
+                stats.max_leaf_size = stats.max_leaf_size.max(n);
+                stats.sah_cost += n as f64 * p;
+            } else {
+                stats.interior_nodes += 1;
+                stats.sah_cost += TRAVERSAL_COST * p;
+            }
+        }
+        stats
+    }
+}
+
+/// Largest `f32` no greater than `x`.
+pub fn round_down(x: f64) -> f32 {
+    let f = x as f32;
+    if f as f64 <= x {
+        f
+    } else if f > 0.0 {
+        f32::from_bits(f.to_bits() - 1)
+    } else if f == 0.0 {
+        -f32::from_bits(1)
+    } else {
+        f32::from_bits(f.to_bits() + 1)
+    }
+}
+
+/// Smallest `f32` no less than `x`.
+pub fn round_up(x: f64) -> f32 {
+    -round_down(-x)
+}
+
+impl Hittable for LinearBvh {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let orig = r.orign();
+        let dir = r.direction();
+        let inv_dir = Vec3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+        let dir_is_neg = [inv_dir.x < 0.0, inv_dir.y < 0.0, inv_dir.z < 0.0];
+
+        let mut small = [0u32; STACK_SIZE];
+        let mut large;
+        let stack: &mut [u32] = if self.depth <= STACK_SIZE {
+            &mut small
+        } else {
+            large = vec![0u32; self.depth];
+            &mut large
+        };
+        let mut top = 0;
+        let mut current = 0;
+        let mut closest_so_far = t_max;
+        let mut hit_anything = false;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox().hit_inv(&orig, &inv_dir, t_min, closest_so_far) {
+                if node.is_leaf() {
+                    let first = node.offset as usize;
+                    for object in &self.primitives[first..first + node.count as usize] {
+                        if object.hit(r, t_min, closest_so_far, rec) {
+                            hit_anything = true;
+                            closest_so_far = rec.t;
+                        }
+                    }
+                } else {
+                    // Go down the nearer child and keep the other for later.
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[top] = far as u32;
+                    top += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if top == 0 {
+                return hit_anything;
+            }
+            top -= 1;
+            current = stack[top] as usize;
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let orig = r.orign();
+        let dir = r.direction();
+        let inv_dir = Vec3::new(1.0 / dir.x, 1.0 / dir.y, 1.0 / dir.z);
+
+        let mut small = [0u32; STACK_SIZE];
+        let mut large;
+        let stack: &mut [u32] = if self.depth <= STACK_SIZE {
+            &mut small
+        } else {
+            large = vec![0u32; self.depth];
+            &mut large
+        };
+        let mut top = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox().hit_inv(&orig, &inv_dir, t_min, t_max) {
+                if node.is_leaf() {
+                    let first = node.offset as usize;
+                    let objects = &self.primitives[first..first + node.count as usize];
+                    if objects.iter().any(|o| o.occluded(r, t_min, t_max)) {
+                        return true;
+                    }
+                } else {
+                    // Any hit will do, so the order does not matter.
+                    stack[top] = node.offset;
+                    top += 1;
+                    current += 1;
+                    continue;
+                }
+            }
+            if top == 0 {
+                return false;
+            }
+            top -= 1;
+            current = stack[top] as usize;
+        }
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        match self.nodes.first() {
+            Some(root) => {
+                *output_box = root.bbox();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    fn centre(object: &Arc<dyn Hittable>) -> point3 {
+        let mut b = AABB::new0();
+        assert!(object.bounding_box(0.0, 1.0, &mut b));
+        (b.min() + b.max()) * 0.5
+    }
+
+    /// Checks that every node but the root has exactly one parent, that
+    /// each box holds its children, that the leaves cover the primitives
+    /// once in order, and that `depth` is the longest path.
+    fn check_structure(bvh: &LinearBvh) {
+        let n = bvh.nodes.len();
+        let mut parents = vec![0; n];
+        let mut next_primitive = 0;
+        let mut deepest = 0;
+        let mut todo = vec![(0, 1)];
+        while let Some((i, depth)) = todo.pop() {
+            deepest = deepest.max(depth);
+            let node = bvh.nodes[i];
+            if node.is_leaf() {
+                assert_eq!(node.offset as usize, next_primitive);
+                next_primitive += node.count as usize;
+                for object in &bvh.primitives[node.offset as usize..next_primitive] {
+                    let mut b = AABB::new0();
+                    object.bounding_box(0.0, 1.0, &mut b);
+                    assert!(contains(&node.bbox(), &b));
+                }
+                continue;
+            }
+            let (left, right) = (i + 1, node.offset as usize);
+            assert!(right > left && right < n);
+            assert_eq!(bvh.subtree_end(left), right);
+            for &child in [left, right].iter() {
+                parents[child] += 1;
+                assert!(contains(&node.bbox(), &bvh.nodes[child].bbox()));
+            }
+            // The left child is visited first, so leaves come in order.
+            todo.push((right, depth + 1));
+            todo.push((left, depth + 1));
+        }
+        assert_eq!(parents[0], 0);
+        assert!(parents[1..].iter().all(|p| *p == 1));
+        assert_eq!(next_primitive, bvh.primitives.len());
+        assert_eq!(bvh.depth, deepest);
+        assert_eq!(bvh.built_cost.len(), n);
+    }
+
+    fn contains(outer: &AABB, inner: &AABB) -> bool {
+        let (a, b) = (outer.min(), outer.max());
+        let (c, d) = (inner.min(), inner.max());
+        a.x <= c.x && a.y <= c.y && a.z <= c.z && b.x >= d.x && b.y >= d.y && b.z >= d.z
+    }
+
+    fn check_against_list(bvh: &LinearBvh) {
+        let mut list = HittableList::new();
+        for object in &bvh.primitives {
+            list.add(object.clone());
+        }
+        for _i in 0..2000 {
+            let r = Ray::new(Vec3::random2(-15.0, 15.0), Vec3::random2(-1.0, 1.0), 0.0);
+            let t_max = random_double2(1.0, 30.0);
+            let mut expected = HitRecord::new0();
+            let mut rec = HitRecord::new0();
+            let hit = list.hit(r, 0.001, t_max, &mut expected);
+            assert_eq!(bvh.hit(r, 0.001, t_max, &mut rec), hit);
+            if hit {
+                assert_eq!(rec.t, expected.t);
+            }
+            assert_eq!(bvh.occluded(r, 0.001, t_max), hit);
+        }
+    }
+
+    /// Moves the spheres left of `x` to `to` of where they were.
+    fn move_cluster(
+        bvh: &mut LinearBvh,
+        x: f64,
+        to: impl Fn(point3) -> point3,
+        mat: &Arc<dyn Material>,
+    ) -> usize {
+        let mut moved = 0;
+        for i in 0..bvh.primitives.len() {
+            let c = centre(&bvh.primitives[i]);
+            if c.x < x {
+                bvh.set_primitive(i, Arc::new(Sphere::new(to(c), 0.2, mat.clone())));
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    #[test]
+    fn refit_and_rebuild_keep_the_tree_valid() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        for _i in 0..1000 {
+            list.add(Arc::new(Sphere::new(
+                Vec3::random2(-10.0, 10.0),
+                0.2,
+                mat.clone(),
+            )));
+        }
+        let mut bvh = LinearBvh::from(&mut list, 0.0, 1.0);
+        check_structure(&bvh);
+        check_against_list(&bvh);
+
+        // Squeezing a cluster together lets the rebuild put several
+        // spheres in a leaf, so the node count changes.
+        let squeeze = |c: point3| Vec3::new(8.0, 3.0, -2.0) + c * 0.05;
+        assert!(move_cluster(&mut bvh, -6.0, squeeze, &mat) > 50);
+        bvh.refit(0.0, 1.0);
+        check_structure(&bvh);
+        check_against_list(&bvh);
+
+        // Keep the root and its first child so the rebuilds are of smaller
+        // subtrees on both sides of the array, which moves the links
+        // around them.
+        bvh.built_cost[0] = INFINITY;
+        bvh.built_cost[1] = INFINITY;
+        let nodes = bvh.nodes.len();
+        let rebuilt = bvh.rebuild_degraded(0.0, 0.0, 1.0);
+        assert!(rebuilt >= 3, "rebuilt {}", rebuilt);
+        assert!(bvh.nodes.len() < nodes);
+        check_structure(&bvh);
+        check_against_list(&bvh);
+
+        move_cluster(&mut bvh, -2.0, |c| c + Vec3::new(-5.0, 0.0, 5.0), &mat);
+        bvh.update(0.0, 1.0);
+        check_structure(&bvh);
+        check_against_list(&bvh);
+    }
+}
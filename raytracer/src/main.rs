@@ -1,28 +1,71 @@
 mod aabb;
-mod aarect;
 mod bbbox;
 mod bvh;
+mod bvh_cache;
 mod camera;
 mod constant_medium;
+mod csg;
+mod curve;
+mod environment;
+mod gltf;
+mod hair;
+mod heightfield;
 mod hittable;
+mod instance;
+mod json;
+mod linear_bvh;
 mod material;
 mod moving_sphere;
+mod obj;
 mod perlin;
+mod ply;
+mod quad;
+mod quadric;
 mod ray;
+mod region;
 mod rtweekend;
+mod sdf;
+mod shutter;
+mod sky;
 mod sphere;
+mod stl;
 mod texture;
+mod transform;
+mod triangle;
 #[allow(clippy::float_cmp)]
 mod vec3;
+mod wide_bvh;
 pub use crate::bbbox::*;
 pub use crate::bvh::*;
-use crate::camera::Camera;
+pub use crate::bvh_cache::*;
+pub use crate::camera::*;
 pub use crate::constant_medium::*;
+pub use crate::csg::*;
+pub use crate::curve::*;
+pub use crate::environment::*;
+pub use crate::gltf::*;
+pub use crate::hair::*;
+pub use crate::heightfield::*;
+pub use crate::instance::*;
+pub use crate::json::*;
+pub use crate::linear_bvh::*;
 pub use crate::material::*;
 pub use crate::moving_sphere::*;
+pub use crate::obj::*;
+pub use crate::perlin::Perlin;
+pub use crate::ply::*;
+pub use crate::quad::*;
+pub use crate::quadric::*;
+pub use crate::region::*;
 use crate::rtweekend::random_double1;
+pub use crate::sdf::*;
+pub use crate::shutter::*;
+pub use crate::sky::*;
 pub use crate::sphere::Sphere;
-use aarect::*;
+pub use crate::stl::*;
+pub use crate::transform::*;
+pub use crate::triangle::*;
+pub use crate::wide_bvh::*;
 use indicatif::ProgressBar;
 pub use std::thread;
 pub use threadpool::ThreadPool;
@@ -38,24 +81,102 @@ pub use Vec3 as point3;
 pub use Vec3 as color;
 
 use std::sync::mpsc::channel;
+use std::time::Instant;
 
 use crate::hittable::HitRecord;
 use crate::hittable::Hittable;
 use crate::hittable::HittableList;
 pub const MAXDEPTH: i64 = 50;
+/// Where built BVHs of large models are kept between runs.
+pub const BVH_CACHE_DIR: &str = "cache";
 
 pub struct ThreadTemp {
     pub x: u32,
-    pub color: Vec<[u8; 3]>,
+    pub color: Vec<color>,
 }
 
-fn ray_color(r: Ray, background: color, world: &HittableList, depth: i64) -> color {
+/// Command line switches for rendering part of the frame.
+#[derive(Default)]
+pub struct RenderArgs {
+    pub region: Option<Region>,
+    pub base: Option<String>,
+    pub partial: Option<String>,
+}
+
+impl RenderArgs {
+    pub fn parse(args: &[String]) -> Self {
+        let mut res = Self::default();
+        let mut it = args.iter();
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--region" => {
+                    let s = it.next().expect("--region needs x0,y0,x1,y1");
+                    res.region = Some(Region::parse(s).expect("bad --region"));
+                }
+                "--base" => res.base = it.next().cloned(),
+                "--partial" => res.partial = it.next().cloned(),
+                _ => eprintln!("unknown argument {}", arg),
+            }
+        }
+        res
+    }
+}
+
+/// `merge <output.png> <part>... [--base <image>]`: adds partial renders up
+/// and writes them over the base image, or over black.
+fn merge_partials(args: &[String]) {
+    let mut base: Option<String> = None;
+    let mut files = vec![];
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--base" {
+            base = it.next().cloned();
+        } else {
+            files.push(arg.clone());
+        }
+    }
+    if files.len() < 2 {
+        eprintln!("usage: raytracer merge <output.png> <part>... [--base <image>]");
+        return;
+    }
+    let parts: Vec<PartialImage> = files[1..]
+        .iter()
+        .map(|f| PartialImage::load(f).expect("failed to read partial render"))
+        .collect();
+    let merged = PartialImage::merge(&parts).expect("failed to merge partial renders");
+    let mut img: RgbImage = match base {
+        Some(path) => image::open(path).unwrap().to_rgb8(),
+        None => ImageBuffer::new(merged.width, merged.height),
+    };
+    merged.composite(&mut img);
+    img.save(&files[0]).unwrap();
+}
+
+/// `bsdf_pdf` is the density the material that sent `r` drew it with, zero
+/// for camera rays and specular bounces. It weights light found by chance
+/// against what light sampling at that vertex already counted.
+fn ray_color(
+    r: Ray,
+    background: &dyn Environment,
+    world: &HittableList,
+    depth: i64,
+    bsdf_pdf: f64,
+) -> color {
     let mut rec = HitRecord::new0();
     if depth <= 0 {
         return color::new(0.0, 0.0, 0.0);
     }
     if !world.hit(r, 0.001, INFINITY, &mut rec) {
-        return background;
+        let dir = r.direction().unit();
+        let env = if depth == MAXDEPTH {
+            background.camera_value(&dir)
+        } else {
+            background.value(&dir)
+        };
+        if bsdf_pdf > 0.0 && background.is_light() {
+            return env * power_heuristic(bsdf_pdf, background.pdf_value(&dir));
+        }
+        return env;
     }
     let mut scattered = Ray::new0();
     let mut attenuation = color::new0();
@@ -67,11 +188,24 @@ fn ray_color(r: Ray, background: color, world: &HittableList, depth: i64) -> col
     {
         return emitted;
     }
+    let scatter_pdf = rec.mat_ptr.scattering_pdf(r, &rec, &scattered);
+
+    let mut direct = color::zero();
+    if scatter_pdf > 0.0 && background.is_light() {
+        let to_light = Ray::new(rec.p, background.random(), r.time());
+        let light_pdf = background.pdf_value(&to_light.direction());
+        if light_pdf > 0.0 && !world.occluded(to_light, 0.001, INFINITY) {
+            let pdf = rec.mat_ptr.scattering_pdf(r, &rec, &to_light);
+            direct = Vec3::elemul(attenuation, background.value(&to_light.direction()))
+                * (pdf / light_pdf * power_heuristic(light_pdf, pdf));
+        }
+    }
 
     emitted
+        + direct
         + Vec3::elemul(
             attenuation,
-            ray_color(scattered, background, world, depth - 1),
+            ray_color(scattered, background, world, depth - 1, scatter_pdf),
         )
 }
 
@@ -107,33 +241,40 @@ fn cornell_smoke() -> HittableList {
         7.0, 7.0, 7.0,
     )))));
 
-    world.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
-    world.add(Arc::new(YzRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
-    world.add(Arc::new(XzRect::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
+    world.add(Arc::new(Quad::new(
+        point3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        green,
     )));
-    world.add(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        0.0,
+    world.add(Arc::new(Quad::new(
+        point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        red,
+    )));
+    world.add(Arc::new(Quad::new(
+        point3::new(113.0, 554.0, 127.0),
+        Vec3::new(0.0, 0.0, 305.0),
+        Vec3::new(330.0, 0.0, 0.0),
+        light,
+    )));
+    world.add(Arc::new(Quad::new(
+        point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
         white.clone(),
     )));
-    world.add(Arc::new(XzRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
+    world.add(Arc::new(Quad::new(
+        point3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
         white.clone(),
     )));
-    world.add(Arc::new(XyRect::new(
-        0.0,
-        555.0,
-        0.0,
-        555.0,
-        555.0,
+    world.add(Arc::new(Quad::new(
+        point3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
         white.clone(),
     )));
 
@@ -142,8 +283,10 @@ fn cornell_smoke() -> HittableList {
         &point3::new(165.0, 330.0, 165.0),
         white.clone(),
     ));
-    let box1 = Arc::new(RotateY::new(box1, 15.0));
-    let box1 = Arc::new(Translate::new(box1, &Vec3::new(265.0, 0.0, 295.0)));
+    let box1 = Arc::new(Transformed::new(
+        box1,
+        Transform::translate(Vec3::new(265.0, 0.0, 295.0)) * Transform::rotate_y(15.0),
+    ));
     world.add(Arc::new(ConstantMedium::new_by_color(
         box1,
         0.01,
@@ -155,8 +298,10 @@ fn cornell_smoke() -> HittableList {
         &point3::new(165.0, 165.0, 165.0),
         white,
     ));
-    let box2 = Arc::new(RotateY::new(box2, -18.0));
-    let box2 = Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0)));
+    let box2 = Arc::new(Transformed::new(
+        box2,
+        Transform::translate(Vec3::new(130.0, 0.0, 65.0)) * Transform::rotate_y(-18.0),
+    ));
     world.add(Arc::new(ConstantMedium::new_by_color(
         box2,
         0.01,
@@ -215,6 +360,310 @@ fn earth() -> HittableList {
     objects.add(Arc::new(Sphere::new(Vec3::zero(), 2.0, earth_surface)));
     return objects;*/
 }
+fn obj_model() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new_by_color(color::new(0.48, 0.83, 0.53)));
+    objects.add(Arc::new(Sphere::new(
+        point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    let default_mat = Arc::new(Lambertian::new_by_color(color::new(0.73, 0.73, 0.73)));
+    let model = ObjModel::load("model.obj", default_mat).unwrap();
+    let bvh = LinearBvh::cached(&mut model.triangles(), 0.0, 1.0, BVH_CACHE_DIR);
+    println!("bvh: {}", bvh.stats());
+    objects.add(Arc::new(bvh));
+
+    objects
+}
+
+fn scanned_models() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new_by_color(color::new(0.48, 0.83, 0.53)));
+    objects.add(Arc::new(Sphere::new(
+        point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    let painted = Arc::new(Lambertian::new(Arc::new(VertexColorTexture::new_by_color(
+        color::new(0.73, 0.73, 0.73),
+    ))));
+    let ply = Arc::new(load_ply("model.ply").unwrap());
+    objects.add(Arc::new(TriangleMesh::to_bvh(&ply, painted, 0.0, 1.0)));
+
+    let metal = Arc::new(Metal::new(color::new(0.8, 0.85, 0.88), 0.1));
+    let stl = Arc::new(load_stl("model.stl").unwrap());
+    objects.add(Arc::new(Transformed::new(
+        Arc::new(TriangleMesh::to_bvh(&stl, metal, 0.0, 1.0)),
+        Transform::translate(Vec3::new(0.0, 0.0, 2.5))
+            * Transform::rotate(30.0, Vec3::new(1.0, 1.0, 0.0)),
+    )));
+
+    objects
+}
+
+fn quadrics() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(Arc::new(CheckerTexture::new_by_color(
+        color::new(0.2, 0.3, 0.1),
+        color::new(0.9, 0.9, 0.9),
+    ))));
+    objects.add(Arc::new(Sphere::new(
+        point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    let place = |object: Arc<dyn Hittable>, x: f64, z: f64| -> Arc<dyn Hittable> {
+        Arc::new(Transformed::new(
+            object,
+            Transform::translate(Vec3::new(x, 0.0, z)),
+        ))
+    };
+    let red = Arc::new(Lambertian::new_by_color(color::new(0.7, 0.1, 0.1)));
+    let gold = Arc::new(Metal::new(color::new(0.9, 0.7, 0.3), 0.05));
+    let glass = Arc::new(Dielectric::new(1.5));
+
+    objects.add(place(
+        Arc::new(Cylinder::capped(0.5, 0.0, 1.5, 360.0, glass)),
+        0.0,
+        0.0,
+    ));
+    objects.add(place(
+        Arc::new(Cylinder::new(0.5, 0.0, 1.0, 270.0, red.clone())),
+        0.0,
+        -1.5,
+    ));
+    objects.add(place(
+        Arc::new(Cone::new(1.5, 0.5, 0.0, 1.2, 360.0, red.clone())),
+        0.0,
+        1.5,
+    ));
+    objects.add(place(
+        Arc::new(Paraboloid::new(0.6, 0.0, 0.8, 360.0, gold.clone())),
+        1.5,
+        -0.75,
+    ));
+    objects.add(place(
+        Arc::new(Hyperboloid::new(
+            point3::new(0.5, 0.0, -0.3),
+            point3::new(-0.3, 1.2, 0.5),
+            360.0,
+            red,
+        )),
+        1.5,
+        0.75,
+    ));
+    objects.add(Arc::new(Transformed::new(
+        Arc::new(Torus::new(0.5, 0.15, 0.0, 270.0, 360.0, gold.clone())),
+        Transform::translate(Vec3::new(-1.5, 0.65, 0.75)) * Transform::rotate_x(60.0),
+    )));
+    objects.add(place(
+        Arc::new(Disk::new(0.01, 0.6, 0.3, 300.0, gold)),
+        -1.5,
+        -0.75,
+    ));
+
+    objects
+}
+
+fn csg_shapes() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(Arc::new(CheckerTexture::new_by_color(
+        color::new(0.2, 0.3, 0.1),
+        color::new(0.9, 0.9, 0.9),
+    ))));
+    objects.add(Arc::new(Sphere::new(
+        point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    let red = Arc::new(Lambertian::new_by_color(color::new(0.7, 0.1, 0.1)));
+    let gold = Arc::new(Metal::new(color::new(0.9, 0.7, 0.3), 0.05));
+    let glass = Arc::new(Dielectric::new(1.5));
+
+    // A glass ball with a wedge cut out of its top.
+    let ball = Arc::new(Sphere::new(point3::new(0.0, 1.0, -2.5), 1.0, glass));
+    let wedge = Arc::new(Box6::new(
+        &point3::new(0.0, 1.0, -4.0),
+        &point3::new(2.0, 3.0, -2.5),
+        red.clone(),
+    ));
+    objects.add(Arc::new(Csg::difference(ball, wedge)));
+
+    // A cube drilled through along all three axes.
+    let cube = Arc::new(Box6::new(
+        &point3::new(-0.8, -0.8, -0.8),
+        &point3::new(0.8, 0.8, 0.8),
+        red,
+    ));
+    let drill: Arc<dyn Hittable> = Arc::new(Cylinder::capped(0.45, -1.0, 1.0, 360.0, gold.clone()));
+    let mut holes = Csg::union(
+        drill.clone(),
+        Arc::new(Transformed::new(drill.clone(), Transform::rotate_x(90.0))),
+    );
+    holes = Csg::union(
+        Arc::new(holes),
+        Arc::new(Transformed::new(drill, Transform::rotate_z(90.0))),
+    );
+    objects.add(Arc::new(Transformed::new(
+        Arc::new(Csg::difference(cube, Arc::new(holes))),
+        Transform::translate(Vec3::new(0.0, 0.8, 0.0)) * Transform::rotate_y(30.0),
+    )));
+
+    // A die: a cube with its corners rounded off by a sphere.
+    let die = Csg::intersection(
+        Arc::new(Box6::new(
+            &point3::new(-0.7, 0.0, 1.8),
+            &point3::new(0.7, 1.4, 3.2),
+            gold.clone(),
+        )),
+        Arc::new(Sphere::new(point3::new(0.0, 0.7, 2.5), 0.95, gold)),
+    );
+    objects.add(Arc::new(die));
+
+    objects
+}
+
+fn sdf_shapes() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new(Arc::new(CheckerTexture::new_by_color(
+        color::new(0.2, 0.3, 0.1),
+        color::new(0.9, 0.9, 0.9),
+    ))));
+    objects.add(Arc::new(Sphere::new(
+        point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    let red = Arc::new(Lambertian::new_by_color(color::new(0.7, 0.1, 0.1)));
+    let gold = Arc::new(Metal::new(color::new(0.9, 0.7, 0.3), 0.05));
+    let marble = Arc::new(Lambertian::new_by_color(color::new(0.8, 0.8, 0.75)));
+    let bounds = |min: point3, max: point3| AABB::new(min, max);
+
+    // Two balls and a capsule melted into one blob.
+    let blob = SmoothUnion::new(
+        Arc::new(SmoothUnion::new(
+            Arc::new(SdfSphere::new(point3::new(0.0, 0.6, -2.8), 0.5)),
+            Arc::new(SdfSphere::new(point3::new(0.0, 1.1, -2.2), 0.35)),
+            0.3,
+        )),
+        Arc::new(SdfCapsule::new(
+            point3::new(0.0, 0.35, -2.5),
+            point3::new(0.0, 0.3, -1.4),
+            0.2,
+        )),
+        0.2,
+    );
+    objects.add(Arc::new(SdfObject::new(
+        Arc::new(blob),
+        bounds(point3::new(-0.7, 0.0, -3.5), point3::new(0.7, 1.6, -1.1)),
+        red,
+    )));
+
+    // A twisted rounded column with a torus carved out of its middle.
+    let column = SmoothSubtraction::new(
+        Arc::new(Twist::new(
+            Arc::new(SdfBox::new(
+                point3::new(0.0, 0.9, 0.0),
+                Vec3::new(0.35, 0.9, 0.35),
+                0.05,
+            )),
+            1.5,
+            0.5,
+        )),
+        Arc::new(SdfTorus::new(point3::new(0.0, 0.9, 0.0), 0.45, 0.12)),
+        0.05,
+    );
+    objects.add(Arc::new(SdfObject::new(
+        Arc::new(column),
+        bounds(point3::new(-0.6, 0.0, -0.6), point3::new(0.6, 1.8, 0.6)),
+        gold,
+    )));
+
+    // A lumpy rock and a Mandelbulb.
+    let rock = Displace::new(
+        Arc::new(SdfSphere::new(point3::new(0.0, 0.5, 1.2), 0.45)),
+        0.08,
+        6.0,
+    );
+    objects.add(Arc::new(SdfObject::new(
+        Arc::new(rock),
+        bounds(point3::new(-0.6, -0.1, 0.6), point3::new(0.6, 1.1, 1.8)),
+        marble.clone(),
+    )));
+    objects.add(Arc::new(Transformed::new(
+        Arc::new(SdfObject::new(
+            Arc::new(Mandelbulb::new(8.0, 12)),
+            bounds(point3::new(-1.3, -1.3, -1.3), point3::new(1.3, 1.3, 1.3)),
+            marble,
+        )),
+        Transform::translate(Vec3::new(-0.5, 1.0, 3.0)) * Transform::scale(Vec3::ones() * 0.8),
+    )));
+
+    objects
+}
+
+fn terrain() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let grass = Arc::new(Lambertian::new_by_color(color::new(0.35, 0.45, 0.2)));
+    let land = Heightfield::from_noise(
+        &Perlin::new(),
+        1024,
+        1024,
+        4.0,
+        Vec3::new(40.0, 6.0, 40.0),
+        grass,
+    );
+    objects.add(Arc::new(Transformed::new(
+        Arc::new(land),
+        Transform::translate(Vec3::new(-20.0, 0.0, -20.0)),
+    )));
+
+    let water = Arc::new(Metal::new(color::new(0.4, 0.5, 0.6), 0.02));
+    objects.add(Arc::new(Quad::new(
+        point3::new(-20.0, 0.6, -20.0),
+        Vec3::new(0.0, 0.0, 40.0),
+        Vec3::new(40.0, 0.0, 0.0),
+        water,
+    )));
+
+    objects
+}
+
+fn furball() -> HittableList {
+    let mut objects = HittableList::new();
+
+    let ground = Arc::new(Lambertian::new_by_color(color::new(0.5, 0.5, 0.5)));
+    objects.add(Arc::new(Sphere::new(
+        point3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    )));
+
+    let skin = Arc::new(Lambertian::new_by_color(color::new(0.3, 0.2, 0.1)));
+    objects.add(Arc::new(Sphere::new(point3::new(0.0, 1.0, 0.0), 1.0, skin)));
+
+    let fur = Arc::new(HairMaterial::from_melanin(1.3, 0.5, 0.3, 0.3));
+    let strands = load_strands("hair.txt").unwrap();
+    let mut curves = Strand::to_list(&strands, CurveType::Flat, fur);
+    let bvh = LinearBvh::cached(&mut curves, 0.0, 1.0, BVH_CACHE_DIR);
+    println!("bvh: {}", bvh.stats());
+    objects.add(Arc::new(bvh));
+
+    objects
+}
+
 fn two_spheres() -> HittableList {
     let mut objects = HittableList::new();
 
@@ -285,24 +734,40 @@ fn cornell_box() -> HittableList {
     let green = Arc::new(Lambertian::new_by_color(color::new(0.12, 0.45, 0.15)));
     let light = Arc::new(DiffuseLight::new_by_color(color::new(15., 15., 15.)));
 
-    objects.add(Arc::new(YzRect::new(0., 555., 0., 555., 555., green)));
-    objects.add(Arc::new(YzRect::new(0., 555., 0., 555., 0., red)));
-    objects.add(Arc::new(XzRect::new(213., 343., 227., 332., 554., light)));
-    objects.add(Arc::new(XzRect::new(0., 555., 0., 555., 0., white.clone())));
-    objects.add(Arc::new(XzRect::new(
-        0.,
-        555.,
-        0.,
-        555.,
-        555.,
+    objects.add(Arc::new(Quad::new(
+        point3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        green,
+    )));
+    objects.add(Arc::new(Quad::new(
+        point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        red,
+    )));
+    objects.add(Arc::new(Quad::new(
+        point3::new(213.0, 554.0, 227.0),
+        Vec3::new(0.0, 0.0, 105.0),
+        Vec3::new(130.0, 0.0, 0.0),
+        light,
+    )));
+    objects.add(Arc::new(Quad::new(
+        point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
         white.clone(),
     )));
-    objects.add(Arc::new(XyRect::new(
-        0.,
-        555.,
-        0.,
-        555.,
-        555.,
+    objects.add(Arc::new(Quad::new(
+        point3::new(0.0, 555.0, 0.0),
+        Vec3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        white.clone(),
+    )));
+    objects.add(Arc::new(Quad::new(
+        point3::new(0.0, 0.0, 555.0),
+        Vec3::new(555.0, 0.0, 0.0),
+        Vec3::new(0.0, 555.0, 0.0),
         white.clone(),
     )));
     let box1 = Arc::new(Box6::new(
@@ -310,8 +775,10 @@ fn cornell_box() -> HittableList {
         &point3::new(165.0, 330.0, 165.0),
         white.clone(),
     ));
-    let box1 = Arc::new(RotateY::new(box1, 15.0));
-    let box1 = Arc::new(Translate::new(box1, &Vec3::new(265.0, 0.0, 295.0)));
+    let box1 = Arc::new(Transformed::new(
+        box1,
+        Transform::translate(Vec3::new(265.0, 0.0, 295.0)) * Transform::rotate_y(15.0),
+    ));
     objects.add(box1);
 
     let box2 = Arc::new(Box6::new(
@@ -319,8 +786,10 @@ fn cornell_box() -> HittableList {
         &point3::new(165.0, 165.0, 165.0),
         white,
     ));
-    let box2 = Arc::new(RotateY::new(box2, -18.0));
-    let box2 = Arc::new(Translate::new(box2, &Vec3::new(130.0, 0.0, 65.0)));
+    let box2 = Arc::new(Transformed::new(
+        box2,
+        Transform::translate(Vec3::new(130.0, 0.0, 65.0)) * Transform::rotate_y(-18.0),
+    ));
 
     objects.add(box2);
 
@@ -343,7 +812,12 @@ fn simple_light() -> HittableList {
     )));
 
     let difflight = Arc::new(DiffuseLight::new_by_color(color::new(4.0, 4.0, 4.0)));
-    objects.add(Arc::new(XyRect::new(3.0, 5.0, 1.0, 3.0, -2.0, difflight)));
+    objects.add(Arc::new(Quad::new(
+        point3::new(3.0, 1.0, -2.0),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 2.0, 0.0),
+        difflight,
+    )));
     objects
 }
 
@@ -423,8 +897,10 @@ fn random_scene() -> HittableList {
     world
 }
 fn final_scene() -> HittableList {
-    let mut boxes1 = HittableList::new();
     let ground = Arc::new(Lambertian::new_by_color(color::new(0.48, 0.83, 0.53)));
+    // One unit box, stretched into place 400 times.
+    let unit_box: Arc<dyn Hittable> = Arc::new(Box6::new(&point3::zero(), &point3::ones(), ground));
+    let mut boxes1 = vec![];
 
     let boxes_per_side = 20;
     for i in 0..boxes_per_side {
@@ -437,21 +913,24 @@ fn final_scene() -> HittableList {
             let y1 = random_double2(1.0, 101.0);
             let z1 = z0 + w;
 
-            boxes1.add(Arc::new(Box6::new(
-                &point3::new(x0, y0, z0),
-                &point3::new(x1, y1, z1),
-                ground.clone(),
-            )));
+            boxes1.push(Instance::new(
+                unit_box.clone(),
+                Transform::translate(Vec3::new(x0, y0, z0))
+                    * Transform::scale(Vec3::new(x1 - x0, y1 - y0, z1 - z0)),
+            ));
         }
     }
 
     let mut world = HittableList::new();
 
-    world.add(Arc::new(BvhNode::from(&mut boxes1, 0.0, 1.0)));
+    world.add(Arc::new(Instance::bvh(boxes1, 0.0, 1.0)));
 
     let light = Arc::new(DiffuseLight::new_by_color(color::new(7.0, 7.0, 7.0)));
-    world.add(Arc::new(XzRect::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
+    world.add(Arc::new(Quad::new(
+        point3::new(123.0, 554.0, 147.0),
+        Vec3::new(0.0, 0.0, 265.0),
+        Vec3::new(300.0, 0.0, 0.0),
+        light,
     )));
 
     let center1 = point3::new(400.0, 400.0, 200.0);
@@ -514,34 +993,41 @@ fn final_scene() -> HittableList {
         Arc::new(Lambertian { albedo: pertext }),
     )));
 
-    let mut boxes2 = HittableList::new();
     let white = Arc::new(Lambertian::new_by_color(color::new(0.73, 0.73, 0.73)));
+    let unit_sphere: Arc<dyn Hittable> = Arc::new(Sphere::new(point3::zero(), 1.0, white));
+    let mut boxes2 = vec![];
     let ns = 1000;
     for _j in 0..ns {
-        boxes2.add(Arc::new(Sphere::new(
-            point3::random2(0.0, 165.0),
-            10.0,
-            white.clone(),
-        )));
+        boxes2.push(Instance::new(
+            unit_sphere.clone(),
+            Transform::translate(point3::random2(0.0, 165.0))
+                * Transform::scale(Vec3::new(10.0, 10.0, 10.0)),
+        ));
     }
 
-    world.add(Arc::new(Translate::new(
-        Arc::new(RotateY::new(
-            Arc::new(BvhNode::from(&mut boxes2, 0.0, 1.0)),
-            15.0,
-        )),
-        &Vec3::new(-100.0, 270.0, 395.0),
+    world.add(Arc::new(Transformed::new(
+        Arc::new(Instance::bvh(boxes2, 0.0, 1.0)),
+        Transform::translate(Vec3::new(-100.0, 270.0, 395.0)) * Transform::rotate_y(15.0),
     )));
 
-    world
+    // Culling the top-level objects by box beats testing each in turn.
+    let mut top = HittableList::new();
+    top.add(Arc::new(LinearBvh::from(&mut world, 0.0, 1.0)));
+    top
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("merge") {
+        merge_partials(&args[1..]);
+        return;
+    }
+    let render_args = RenderArgs::parse(&args);
+
     let mut aspect_ratio = 16.0 / 9.0;
     let mut image_width: u32 = 400;
     let mut samples_per_pixel: u32 = 50;
-    let mut background = color::new0();
-    let _x = background;
+    let background: Arc<dyn Environment>;
 
     //world
     let mut world = HittableList::new();
@@ -550,42 +1036,50 @@ fn main() {
     let mut lookfrom = point3::new0();
     let mut lookat = point3::new0();
     let mut vfov = 40.0;
+    let mut vup = Vec3::new(0.0, 1.0, 0.0);
     let aperture = 0.0;
     let _xx = aperture;
+    let mut dist_to_focus = 10.0;
+    let mut lens: Option<Lens> = None;
+    let mut autofocus = false;
+    let mut shutter = Shutter::new(0.0, 1.0);
     let _xx = lookfrom;
     let _xx = lookat;
     let _xx = vfov;
 
     let _xx = 0;
 
+    let scene_start = Instant::now();
     match _xx {
         1 => {
             world = random_scene();
             lookfrom = point3::new(13.0, 2.0, 3.0);
             lookat = point3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
-            background = color::new(0.70, 0.80, 1.00);
+            background = Arc::new(PhysicalSky::new(35.0, 60.0, 3.0, color::new(0.3, 0.3, 0.3)));
+            shutter = Shutter::trapezoid(0.0, 0.9, 0.2, 0.2);
+            shutter.set_rolling(0.1);
         }
         2 => {
             world = two_spheres();
             lookfrom = point3::new(13.0, 2.0, 3.0);
             lookat = point3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
-            background = color::new(0.70, 0.80, 1.00);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.70, 0.80, 1.00)));
         }
         3 => {
             world = two_perlin_spheres();
             lookfrom = point3::new(13.0, 2.0, 3.0);
             lookat = point3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
-            background = color::new(0.70, 0.80, 1.00);
+            background = Arc::new(PhysicalSky::new(35.0, 60.0, 3.0, color::new(0.3, 0.3, 0.3)));
         }
         4 => {
             world = earth();
             lookfrom = point3::new(13.0, 2.0, 3.0);
             lookat = point3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
-            background = color::new(0.70, 0.80, 1.00);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.70, 0.80, 1.00)));
         }
         5 => {
             world = simple_light();
@@ -593,17 +1087,19 @@ fn main() {
             lookfrom = point3::new(26.0, 3.0, 6.0);
             lookat = point3::new(0.0, 2.0, 0.0);
             vfov = 20.0;
-            background = color::new(0.0, 0.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.0, 0.0, 0.0)));
         }
         6 => {
             world = cornell_box();
             aspect_ratio = 1.0;
             image_width = 600;
             samples_per_pixel = 200;
-            background = color::new(0.0, 0.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.0, 0.0, 0.0)));
             lookfrom = point3::new(278.0, 278.0, -800.0);
             lookat = point3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
+            lens = Some(Lens::new(50.0, 36.0, 2.8, 1.0));
+            autofocus = true;
         }
         7 => {
             world = cornell_smoke();
@@ -612,107 +1108,236 @@ fn main() {
             lookfrom = point3::new(278.0, 278.0, -800.0);
             lookat = point3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
-            background = color::new(0.0, 0.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.0, 0.0, 0.0)));
+            dist_to_focus = 800.0;
         }
         8 => {
             world = final_scene();
             aspect_ratio = 1.0;
             samples_per_pixel = 100;
-            background = color::new(0.0, 0.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.0, 0.0, 0.0)));
             lookfrom = point3::new(478.0, 278.0, -600.0);
             lookat = point3::new(278., 278., 0.);
             vfov = 40.0;
         }
+        9 => {
+            world = random_scene();
+            lookfrom = point3::new(13.0, 2.0, 3.0);
+            lookat = point3::new(0.0, 0.0, 0.0);
+            vfov = 20.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        10 => {
+            world = two_spheres();
+            lookfrom = point3::new(13.0, 2.0, 3.0);
+            lookat = point3::new(0.0, 0.0, 0.0);
+            vfov = 20.0;
+            background = Arc::new(ReflectionsOnly::new(Arc::new(TextureEnvironment::new(
+                Arc::new(ImageTexture::new("e.jpg")),
+                1.0,
+            ))));
+        }
+        11 => {
+            world = obj_model();
+            lookfrom = point3::new(13.0, 2.0, 3.0);
+            lookat = point3::new(0.0, 1.0, 0.0);
+            vfov = 20.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        12 => {
+            world = scanned_models();
+            lookfrom = point3::new(13.0, 2.0, 3.0);
+            lookat = point3::new(0.0, 1.0, 1.25);
+            vfov = 25.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        13 => {
+            let scene = GltfScene::load("scene.gltf").unwrap();
+            world = HittableList::new();
+            let bvh = scene.to_bvh(0.0, 1.0);
+            println!("bvh: {}", bvh.stats());
+            world.add(Arc::new(bvh));
+            let c = scene
+                .cameras
+                .first()
+                .cloned()
+                .expect("scene.gltf has no camera");
+            lookfrom = c.lookfrom;
+            lookat = c.lookat;
+            vup = c.vup;
+            vfov = c.vfov;
+            aspect_ratio = c.aspect_ratio.unwrap_or(aspect_ratio);
+            background = scene.environment(Arc::new(GradientEnvironment::sky()));
+        }
+        14 => {
+            world = quadrics();
+            lookfrom = point3::new(9.0, 4.0, 0.0);
+            lookat = point3::new(0.0, 0.6, 0.0);
+            vfov = 30.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        15 => {
+            world = csg_shapes();
+            lookfrom = point3::new(9.0, 4.0, 0.0);
+            lookat = point3::new(0.0, 0.7, 0.0);
+            vfov = 35.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        16 => {
+            world = sdf_shapes();
+            lookfrom = point3::new(9.0, 4.0, 0.0);
+            lookat = point3::new(0.0, 0.8, 0.0);
+            vfov = 35.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        17 => {
+            world = terrain();
+            lookfrom = point3::new(18.0, 9.0, 18.0);
+            lookat = point3::new(0.0, 1.0, 0.0);
+            vfov = 40.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        18 => {
+            world = furball();
+            lookfrom = point3::new(6.0, 2.5, 4.0);
+            lookat = point3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+            background = Arc::new(GradientEnvironment::sky());
+        }
+        19 => {
+            world = earth();
+            lookfrom = point3::new(13.0, 2.0, 3.0);
+            lookat = point3::new(0.0, 0.0, 0.0);
+            vfov = 20.0;
+            background = Arc::new(EnvironmentMap::new("sky.hdr", 0.0, 1.0).unwrap());
+        }
         _ => {
             world = final_scene();
             aspect_ratio = 1.0;
             samples_per_pixel = 10000;
-            background = color::new(0.0, 0.0, 0.0);
+            background = Arc::new(ConstantEnvironment::new(color::new(0.0, 0.0, 0.0)));
             lookfrom = point3::new(478.0, 278.0, -600.0);
             lookat = point3::new(278., 278., 0.);
             vfov = 40.0;
         }
     }
 
+    let scene_time = scene_start.elapsed();
+
     let k = image_width as f64;
     let image_height = k / aspect_ratio;
     let image_height = image_height as u32;
     //camera
-    let vup = Vec3::new(0.0, 1.0, 0.0);
-    let dist_to_focus = 10.0;
-
-    let mut cam: Camera = Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        vfov,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
-    );
-    cam = Camera::new2(&cam, 0.0, 1.0);
+    let mut cam: Camera = match lens {
+        Some(lens) => Camera::from_lens(lookfrom, lookat, vup, aspect_ratio, &lens, dist_to_focus),
+        None => Camera::new(
+            lookfrom,
+            lookat,
+            vup,
+            vfov,
+            aspect_ratio,
+            aperture,
+            dist_to_focus,
+        ),
+    };
+    cam.set_shutter(shutter);
+    if lens.is_some() {
+        cam.set_aperture_shape(ApertureShape::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        });
+        cam.set_cats_eye(0.3);
+    }
+    if autofocus && cam.autofocus(&world) {
+        println!("autofocus: {:.1}", cam.focus_dist());
+    }
 
     // Render
-    let mut img: RgbImage = ImageBuffer::new(image_width, image_height);
-    let bar = ProgressBar::new(image_width as u64);
+    let region = render_args
+        .region
+        .unwrap_or_else(|| Region::full(image_width, image_height))
+        .clip(image_width, image_height);
+    // The frame size is only known once the scene is set up, so a region
+    // off the frame can only be caught here.
+    assert!(
+        !region.is_empty(),
+        "--region is outside the {}x{} frame",
+        image_width,
+        image_height
+    );
+    let mut part = PartialImage::new(image_width, image_height, region);
+    let bar = ProgressBar::new(region.width() as u64);
 
     //let pixel_color: [[Color; width as usize]; height as usize] = [[Color::new(0.0, 0.0, 0.0); width as usize]; height as usize];
 
     println!("width:{} height:{}", image_width, image_height);
+    println!("scene built in {:.2}s", scene_time.as_secs_f64());
+    if render_args.region.is_some() {
+        println!(
+            "region:{},{} to {},{}",
+            region.x0, region.y0, region.x1, region.y1
+        );
+    }
 
     let thread_num = 32; //if is_ci() { 2 } else { 8 };
 
+    let render_start = Instant::now();
     let (tx, rx) = channel();
 
     for i in 0..thread_num {
-        let start = i * image_width / thread_num;
-        let end = (i + 1) * image_width / thread_num;
+        let start = region.x0 + i * region.width() / thread_num;
+        let end = region.x0 + (i + 1) * region.width() / thread_num;
 
         let _tx = tx.clone();
         let _world = world.clone();
         let _cam = cam.clone();
+        let _background = background.clone();
         thread::spawn(move || {
             for x in start..end {
                 let mut temp = ThreadTemp { x, color: vec![] };
-                for y in 0..image_height {
+                for y in region.y0..region.y1 {
                     let mut pixel_color = color::new(0.0, 0.0, 0.0);
                     for _s in 0..samples_per_pixel {
                         let u = (x as f64 + random_double2(0.0, 1.0)) / (image_width - 1) as f64;
                         let v = ((image_height - y) as f64 + random_double2(0.0, 1.0))
                             / (image_height - 1) as f64;
                         let r = _cam.get_ray(u, v);
-                        pixel_color += ray_color(r, background, &_world, MAXDEPTH);
+                        pixel_color += ray_color(r, &*_background, &_world, MAXDEPTH, 0.0)
+                            * _cam.vignetting(u, v);
                     }
-                    let mut r = pixel_color.x;
-                    let mut g = pixel_color.y;
-                    let mut b = pixel_color.z;
-
-                    let scale = 1.0 / samples_per_pixel as f64;
-                    r = (scale * r).sqrt();
-                    g = (scale * g).sqrt();
-                    b = (scale * b).sqrt();
-
-                    temp.color.push([
-                        (clamp(r, 0.0, 0.999) * 255.0) as u8,
-                        (clamp(g, 0.0, 0.999) * 255.0) as u8,
-                        (clamp(b, 0.0, 0.999) * 255.0) as u8,
-                    ]);
+                    temp.color.push(pixel_color);
                 }
                 _tx.send(temp).expect("failed to send");
             }
         });
     }
-    for receive in rx.iter().take(image_width as usize) {
+    for receive in rx.iter().take(region.width() as usize) {
         let x = receive.x;
         //print!("{}\n", x);
-        for y in 0..image_height {
-            let pixel = img.get_pixel_mut(x, y);
-            *pixel = image::Rgb(receive.color[y as usize]);
+        for y in region.y0..region.y1 {
+            part.add(
+                x,
+                y,
+                receive.color[(y - region.y0) as usize],
+                samples_per_pixel,
+            );
         }
         bar.inc(1);
     }
+    if let Some(path) = &render_args.partial {
+        part.save(path).unwrap();
+    }
+    let img: RgbImage = match &render_args.base {
+        Some(path) => {
+            let mut base = image::open(path).unwrap().to_rgb8();
+            part.composite(&mut base);
+            base
+        }
+        None => part.to_image(),
+    };
     img.save("output/test.png").unwrap();
     bar.finish();
+    println!("rendered in {:.2}s", render_start.elapsed().as_secs_f64());
     /*//render
     /*let _x = Vec3::new(1.0, 1.0, 1.0);
     println!("{:?}", _x);*/
@@ -17,6 +17,14 @@ pub trait Material: Sync + Send {
         scattered: &mut Ray,
     ) -> bool;
 
+    /// Density `scatter` picks the direction of `scattered` with. It is also
+    /// the cosine-weighted BRDF over the albedo, which is what light sampling
+    /// needs. Specular materials keep the default of zero and are not light
+    /// sampled.
+    fn scattering_pdf(&self, _ray_in: Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
     fn emitted(&self, _u: f64, _v: f64, _p: &point3) -> color {
         let _x = _u;
         let _y = _v;
@@ -52,17 +60,29 @@ impl Material for Lambertian {
             scatter_direction = rec.normal;
         }
         *scattered = Ray::new(rec.p, scatter_direction, _ray_in.time());
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        true
+        *attenuation = self.albedo.value_at(rec);
+        rec.same_side(&scatter_direction)
+    }
+
+    fn scattering_pdf(&self, _ray_in: Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let cosine = rec.normal * scattered.direction().unit();
+        if cosine < 0.0 || !rec.same_side(&scattered.direction()) {
+            0.0
+        } else {
+            cosine / PI
+        }
     }
 }
 
 pub struct Metal {
-    pub albedo: color,
+    pub albedo: Arc<dyn Texture>,
     pub fuss: f64,
 }
 impl Metal {
     pub fn new(a: color, f: f64) -> Self {
+        Self::new_by_texture(Arc::new(Solid::new(a)), f)
+    }
+    pub fn new_by_texture(a: Arc<dyn Texture>, f: f64) -> Self {
         let x: f64;
         if f < 1.0 {
             x = f;
@@ -73,7 +93,7 @@ impl Metal {
     }
     pub fn new1(a: color) -> Self {
         Self {
-            albedo: a,
+            albedo: Arc::new(Solid::new(a)),
             fuss: 1.0,
         }
     }
@@ -92,8 +112,47 @@ impl Material for Metal {
             reflected + Vec3::random_in_unit_sphere() * self.fuss,
             ray_in.time(),
         );
+        *attenuation = self.albedo.value_at(rec);
+        rec.same_side(&scattered.direction())
+    }
+}
+
+/// Brushed metal, the reflection is blurred by `fuzz_u` along the tangent
+/// and by `fuzz_v` along the bitangent.
+pub struct AnisotropicMetal {
+    pub albedo: color,
+    pub fuzz_u: f64,
+    pub fuzz_v: f64,
+}
+
+impl AnisotropicMetal {
+    pub fn new(albedo: color, fuzz_u: f64, fuzz_v: f64) -> Self {
+        Self {
+            albedo,
+            fuzz_u: fuzz_u.min(1.0),
+            fuzz_v: fuzz_v.min(1.0),
+        }
+    }
+}
+
+impl Material for AnisotropicMetal {
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let (t, b, n) = rec.tangent_frame();
+        let reflected = Vec3::reflect(ray_in.direction().unit(), n);
+        let r = Vec3::random_in_unit_sphere();
+        let direction = reflected
+            + t * (r.x * self.fuzz_u)
+            + b * (r.y * self.fuzz_v)
+            + n * (r.z * self.fuzz_u.min(self.fuzz_v));
+        *scattered = Ray::new(rec.p, direction, ray_in.time());
         *attenuation = self.albedo;
-        scattered.direction() * rec.normal > 0.0
+        rec.same_side(&direction)
     }
 }
 
@@ -105,6 +164,22 @@ impl Dielectric {
         Self { ir: x }
     }
 
+    /// Reflects or refracts by the Fresnel term, `u` picks which. Returns
+    /// the new direction and whether it was reflected.
+    fn bend(unit_direction: Vec3, normal: Vec3, refraction_ratio: f64, u: f64) -> (Vec3, bool) {
+        let cos_theta = Vec3::fmin(-unit_direction * normal, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let cannot_refract = refraction_ratio * sin_theta > 1.0;
+        if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > u {
+            (Vec3::reflect(unit_direction, normal), true)
+        } else {
+            (
+                Vec3::refract(unit_direction, normal, refraction_ratio),
+                false,
+            )
+        }
+    }
+
     pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         r0 = r0 * r0;
@@ -129,16 +204,14 @@ impl Material for Dielectric {
         }
         let unit_direction = ray_in.direction().unit();
 
-        let cos_theta = Vec3::fmin(-unit_direction * rec.normal, 1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let mut direction = Vec3::new0();
-        let _x = direction;
-        if cannot_refract || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double1()
-        {
-            direction = Vec3::reflect(unit_direction, rec.normal);
-        } else {
-            direction = Vec3::refract(unit_direction, rec.normal, refraction_ratio);
+        let u = random_double1();
+        let (mut direction, reflected) =
+            Dielectric::bend(unit_direction, rec.normal, refraction_ratio, u);
+        // Shading normals can send the ray to the wrong side of the surface,
+        // the geometric normal never does.
+        if (direction * rec.geometric_normal > 0.0) != reflected {
+            direction =
+                Dielectric::bend(unit_direction, rec.geometric_normal, refraction_ratio, u).0;
         }
 
         *scattered = Ray::new(rec.p, direction, ray_in.time());
@@ -202,7 +275,63 @@ impl Material for Isotropic {
             dir: Vec3::random_in_unit_sphere(),
             tm: r_in.tm,
         };
-        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        *attenuation = self.albedo.value_at(rec);
         true
     }
+
+    fn scattering_pdf(&self, _ray_in: Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// `inner` with its shading normal bent by a tangent-space normal map.
+/// `strength` scales the map's x and y.
+pub struct NormalMapped {
+    pub inner: Arc<dyn Material>,
+    pub map: Arc<dyn Texture>,
+    pub strength: f64,
+}
+
+impl NormalMapped {
+    pub fn new(inner: Arc<dyn Material>, map: Arc<dyn Texture>, strength: f64) -> Self {
+        Self {
+            inner,
+            map,
+            strength,
+        }
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let (t, b, n) = rec.tangent_frame();
+        let c = self.map.value_at(rec) * 2.0 - Vec3::ones();
+        let m = t * (c.x * self.strength) + b * (c.y * self.strength) + n * c.z;
+        let mut res = rec.clone();
+        // A normal bent past the surface would shade the wrong side.
+        if m * rec.geometric_normal > 1e-4 {
+            res.set_shading_normal(&m);
+        }
+        res
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(
+        &self,
+        ray_in: Ray,
+        rec: &HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        self.inner
+            .scatter(ray_in, &self.perturb(rec), attenuation, scattered)
+    }
+
+    fn scattering_pdf(&self, ray_in: Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        self.inner
+            .scattering_pdf(ray_in, &self.perturb(rec), scattered)
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &point3) -> color {
+        self.inner.emitted(u, v, p)
+    }
 }
@@ -55,34 +55,17 @@ impl MovingSphere {
 ///这里可能有错误
 impl Hittable for MovingSphere {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let oc: Vec3 = r.orign() - self.center(r.time());
-        let a: f64 = Vec3::squared_length(&r.direction());
-        let half_b: f64 = r.direction() * oc;
-        let c: f64 = Vec3::squared_length(&oc) - self.radius * self.radius;
-
-        let discriminant = half_b.powi(2) - a * c;
-        if discriminant > 0.0 {
-            let root: f64 = discriminant.sqrt();
-            let t = (-half_b - root) / a;
-            if t < t_max && t > t_min {
-                rec.t = t; //??????????
-                rec.p = Ray::at(&r, t);
-                let outward_normal = (rec.p - self.center(r.time())) / self.radius;
-                rec.set_face_normal(&r, &outward_normal);
-                rec.mat_ptr = self.mat_ptr.clone();
-                return true;
-            }
-            let t = (-half_b + root) / a;
-            if t < t_max && t > t_min {
-                rec.t = t;
-                rec.p = Ray::at(&r, t);
-                let outward_normal: Vec3 = (rec.p - self.center(r.time())) / self.radius;
-                rec.set_face_normal(&r, &outward_normal);
-                rec.mat_ptr = self.mat_ptr.clone();
-                return true;
+        let center = self.center(r.time());
+        match Sphere::intersect(&center, self.radius, &r, t_min, t_max) {
+            Some(t) => {
+                Sphere::set_hit(&center, self.radius, &self.mat_ptr, &r, t, rec);
+                true
             }
+            None => false,
         }
-        false
+    }
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        Sphere::intersect(&self.center(r.time()), self.radius, &r, t_min, t_max).is_some()
     }
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
         let box0 = AABB::new(
@@ -0,0 +1,351 @@
+use crate::hittable::*;
+use crate::linear_bvh::*;
+use crate::material::*;
+use crate::triangle::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Material parameters of one `newmtl` block of a `.mtl` file.
+#[derive(Clone)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub kd: color,
+    pub ks: color,
+    pub ke: color,
+    pub ns: f64,
+    pub ni: f64,
+    pub dissolve: f64,
+    pub illum: i32,
+    pub map_kd: Option<String>,
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            kd: color::new(0.8, 0.8, 0.8),
+            ks: color::zero(),
+            ke: color::zero(),
+            ns: 0.0,
+            ni: 1.5,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+
+    /// Picks the closest of the renderer's materials. Emission wins, then
+    /// transparency, then a dominant specular colour, else diffuse. Fails if
+    /// the `map_Kd` image cannot be read.
+    pub fn to_material(&self) -> io::Result<Arc<dyn Material>> {
+        let max = |c: &color| c.x.max(c.y).max(c.z);
+        if max(&self.ke) > 0.0 {
+            return Ok(Arc::new(DiffuseLight::new_by_color(self.ke)));
+        }
+        if self.dissolve < 1.0 || self.illum == 4 || self.illum == 6 || self.illum == 7 {
+            return Ok(Arc::new(Dielectric::new(self.ni)));
+        }
+        if self.illum == 3 || (max(&self.ks) > 0.0 && max(&self.ks) >= max(&self.kd)) {
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt();
+            return Ok(Arc::new(Metal::new(self.ks, fuzz)));
+        }
+        Ok(match &self.map_kd {
+            Some(path) => Arc::new(Lambertian::new(Arc::new(ImageTexture::open(path)?))),
+            None => Arc::new(Lambertian::new_by_color(self.kd)),
+        })
+    }
+}
+
+/// Faces of one `g`/`o` group that share a material.
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Arc<dyn Material>,
+    pub faces: Vec<usize>,
+}
+
+/// A Wavefront `.obj` file: one mesh for all vertices, split into groups.
+pub struct ObjModel {
+    pub mesh: Arc<TriangleMesh>,
+    pub groups: Vec<ObjGroup>,
+}
+
+impl ObjModel {
+    /// Faces without a material from the `.mtl` get `default_mat`.
+    pub fn load(path: &str, default_mat: Arc<dyn Material>) -> io::Result<Self> {
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let reader = BufReader::new(File::open(path)?);
+        let mut mesh = TriangleMesh::new();
+        let mut materials: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        let mut groups: Vec<ObjGroup> = vec![];
+        let mut group_name = String::from("default");
+        let mut material = default_mat.clone();
+        let mut new_group = true;
+
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(k) => k,
+                None => continue,
+            };
+            let rest: Vec<&str> = tokens.collect();
+            let bad = || invalid(&format!("{}:{}: bad `{}` line", path, line_no + 1, keyword));
+            match keyword {
+                "v" => {
+                    let v = parse_floats(&rest, 3).ok_or_else(bad)?;
+                    mesh.positions.push(point3::new(v[0], v[1], v[2]));
+                }
+                "vn" => {
+                    let v = parse_floats(&rest, 3).ok_or_else(bad)?;
+                    mesh.normals.push(Vec3::new(v[0], v[1], v[2]));
+                }
+                "vt" => {
+                    // `v` (and the unused `w`) may be left out.
+                    let v = parse_floats(&rest, rest.len().min(3)).ok_or_else(bad)?;
+                    if v.is_empty() {
+                        return Err(bad());
+                    }
+                    mesh.uvs.push([v[0], v.get(1).copied().unwrap_or(0.0)]);
+                }
+                "f" => {
+                    if rest.len() < 3 {
+                        return Err(bad());
+                    }
+                    let mut corners = Vec::with_capacity(rest.len());
+                    for c in rest.iter() {
+                        corners.push(parse_corner(c, &mesh).ok_or_else(bad)?);
+                    }
+                    if new_group {
+                        groups.push(ObjGroup {
+                            name: group_name.clone(),
+                            material: material.clone(),
+                            faces: vec![],
+                        });
+                        new_group = false;
+                    }
+                    for k in 1..corners.len() - 1 {
+                        let face = mesh.face_count();
+                        add_face(&mut mesh, [corners[0], corners[k], corners[k + 1]]);
+                        groups.last_mut().unwrap().faces.push(face);
+                    }
+                }
+                "g" | "o" => {
+                    group_name = rest.join(" ");
+                    new_group = true;
+                }
+                "usemtl" => {
+                    let name = rest.join(" ");
+                    material = materials
+                        .get(&name)
+                        .cloned()
+                        .unwrap_or_else(|| default_mat.clone());
+                    new_group = true;
+                }
+                "mtllib" => {
+                    for lib in rest.iter() {
+                        for m in load_mtl(&dir.join(lib))? {
+                            materials.insert(m.name.clone(), m.to_material()?);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if mesh.face_count() == 0 {
+            return Err(invalid(&format!("{}: no faces", path)));
+        }
+        Ok(Self {
+            mesh: Arc::new(mesh),
+            groups,
+        })
+    }
+
+    pub fn group(&self, name: &str) -> HittableList {
+        let mut list = HittableList::new();
+        for g in self.groups.iter().filter(|g| g.name == name) {
+            for &face in g.faces.iter() {
+                list.add(Arc::new(Triangle::new(
+                    self.mesh.clone(),
+                    face,
+                    g.material.clone(),
+                )));
+            }
+        }
+        list
+    }
+
+    pub fn triangles(&self) -> HittableList {
+        let mut list = HittableList::new();
+        list.objects.reserve(self.mesh.face_count());
+        for g in self.groups.iter() {
+            for &face in g.faces.iter() {
+                list.add(Arc::new(Triangle::new(
+                    self.mesh.clone(),
+                    face,
+                    g.material.clone(),
+                )));
+            }
+        }
+        list
+    }
+
+    pub fn to_bvh(&self, time0: f64, time1: f64) -> LinearBvh {
+        LinearBvh::from(&mut self.triangles(), time0, time1)
+    }
+}
+
+/// Reads every material of a `.mtl` file. Texture paths are made relative
+/// to the directory of the file.
+pub fn load_mtl(path: &Path) -> io::Result<Vec<MtlMaterial>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let reader = BufReader::new(File::open(path)?);
+    let mut res: Vec<MtlMaterial> = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(k) => k,
+            None => continue,
+        };
+        let rest: Vec<&str> = tokens.collect();
+        if keyword == "newmtl" {
+            res.push(MtlMaterial::new(&rest.join(" ")));
+            continue;
+        }
+        let m = match res.last_mut() {
+            Some(m) => m,
+            None => continue,
+        };
+        let rgb = || parse_floats(&rest, 3).map(|v| color::new(v[0], v[1], v[2]));
+        let one = || parse_floats(&rest, 1).map(|v| v[0]);
+        match keyword {
+            "Kd" => m.kd = rgb().unwrap_or(m.kd),
+            "Ks" => m.ks = rgb().unwrap_or(m.ks),
+            "Ke" => m.ke = rgb().unwrap_or(m.ke),
+            "Ns" => m.ns = one().unwrap_or(m.ns),
+            "Ni" => m.ni = one().unwrap_or(m.ni),
+            "d" => m.dissolve = one().unwrap_or(m.dissolve),
+            "Tr" => m.dissolve = 1.0 - one().unwrap_or(0.0),
+            "illum" => m.illum = one().map(|x| x as i32).unwrap_or(m.illum),
+            "map_Kd" => {
+                // Options like `-s 1 1 1` come first, the file name last.
+                if let Some(file) = rest.last() {
+                    m.map_kd = Some(dir.join(file).to_string_lossy().into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(res)
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn parse_floats(tokens: &[&str], n: usize) -> Option<Vec<f64>> {
+    if tokens.len() < n {
+        return None;
+    }
+    tokens[..n].iter().map(|t| t.parse::<f64>().ok()).collect()
+}
+
+/// Turns a 1-based or negative (counted from the end) index into 0-based.
+fn resolve_index(s: &str, count: usize) -> Option<u32> {
+    let i: i64 = s.parse().ok()?;
+    let i = if i < 0 { count as i64 + i } else { i - 1 };
+    if i < 0 || i >= count as i64 {
+        return None;
+    }
+    Some(i as u32)
+}
+
+/// `v`, `v/vt`, `v//vn` or `v/vt/vn`.
+fn parse_corner(s: &str, mesh: &TriangleMesh) -> Option<[u32; 3]> {
+    let mut parts = s.split('/');
+    let p = resolve_index(parts.next()?, mesh.positions.len())?;
+    let t = match parts.next() {
+        Some(x) if !x.is_empty() => resolve_index(x, mesh.uvs.len())?,
+        _ => NO_INDEX,
+    };
+    let n = match parts.next() {
+        Some(x) if !x.is_empty() => resolve_index(x, mesh.normals.len())?,
+        _ => NO_INDEX,
+    };
+    Some([p, t, n])
+}
+
+fn add_face(mesh: &mut TriangleMesh, corners: [[u32; 3]; 3]) {
+    let face = mesh.face_count();
+    let has_uv = corners.iter().any(|c| c[1] != NO_INDEX);
+    let has_normal = corners.iter().any(|c| c[2] != NO_INDEX);
+    if has_uv && mesh.uv_indices.is_empty() {
+        mesh.uv_indices = vec![NO_INDEX; 3 * face];
+    }
+    if has_normal && mesh.normal_indices.is_empty() {
+        mesh.normal_indices = vec![NO_INDEX; 3 * face];
+    }
+    for c in corners.iter() {
+        mesh.position_indices.push(c[0]);
+        if !mesh.uv_indices.is_empty() {
+            mesh.uv_indices.push(c[1]);
+        }
+        if !mesh.normal_indices.is_empty() {
+            mesh.normal_indices.push(c[2]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn write(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("raytracer-{}-{}", std::process::id(), name));
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn reads_short_texture_coordinates() {
+        let path = write(
+            "short-vt.obj",
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0.25\nvt 0.5 0.75\nvt 1 1 0\nf 1/1 2/2 3/3\n",
+        );
+        let model = ObjModel::load(path.to_str().unwrap(), grey()).unwrap();
+        assert_eq!(model.mesh.uvs, vec![[0.25, 0.0], [0.5, 0.75], [1.0, 1.0]]);
+        fs::remove_file(&path).unwrap();
+
+        let path = write("empty-vt.obj", "v 0 0 0\nvt\n");
+        assert!(ObjModel::load(path.to_str().unwrap(), grey()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_texture_is_an_error() {
+        let mtl = write(
+            "missing-map.mtl",
+            "newmtl a\nKd 1 0 0\nmap_Kd no-such-file.png\n",
+        );
+        let obj = write(
+            "missing-map.obj",
+            &format!(
+                "mtllib {}\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl a\nf 1 2 3\n",
+                mtl.file_name().unwrap().to_str().unwrap()
+            ),
+        );
+        assert!(ObjModel::load(obj.to_str().unwrap(), grey()).is_err());
+        fs::remove_file(&mtl).unwrap();
+        fs::remove_file(&obj).unwrap();
+    }
+}
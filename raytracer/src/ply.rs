@@ -0,0 +1,431 @@
+use crate::triangle::*;
+use crate::vec3::*;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+#[derive(Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum PlyType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyType {
+    fn parse(s: &str) -> Option<Self> {
+        Some(match s {
+            "char" | "int8" => PlyType::I8,
+            "uchar" | "uint8" => PlyType::U8,
+            "short" | "int16" => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32" => PlyType::I32,
+            "uint" | "uint32" => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+
+    /// Scale that maps the type's full range of a colour channel to [0, 1].
+    fn color_scale(self) -> f64 {
+        match self {
+            PlyType::U8 => 1.0 / 255.0,
+            PlyType::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+struct PlyProperty {
+    name: String,
+    ty: PlyType,
+    /// Type of the element count for list properties.
+    list: Option<PlyType>,
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+impl PlyElement {
+    /// Fewest bytes one element can take up: a list may be empty and an
+    /// ASCII value is at least one character.
+    fn min_size(&self, format: PlyFormat) -> u64 {
+        let size: usize = match format {
+            PlyFormat::Ascii => self.properties.len(),
+            _ => self
+                .properties
+                .iter()
+                .map(|p| p.list.unwrap_or(p.ty).size())
+                .sum(),
+        };
+        size.max(1) as u64
+    }
+}
+
+/// Reads the body of a PLY file one value at a time, whatever the format.
+struct PlyBody<R: BufRead> {
+    input: R,
+    format: PlyFormat,
+    tokens: Vec<String>,
+    next: usize,
+}
+
+impl<R: BufRead> PlyBody<R> {
+    fn read(&mut self, ty: PlyType) -> io::Result<f64> {
+        if self.format == PlyFormat::Ascii {
+            while self.next >= self.tokens.len() {
+                let mut line = String::new();
+                if self.input.read_line(&mut line)? == 0 {
+                    return Err(invalid("PLY body ends early"));
+                }
+                self.tokens = line.split_whitespace().map(String::from).collect();
+                self.next = 0;
+            }
+            self.next += 1;
+            return self.tokens[self.next - 1]
+                .parse::<f64>()
+                .map_err(|_| invalid("bad number in PLY body"));
+        }
+
+        let mut b = [0u8; 8];
+        let n = ty.size();
+        self.input.read_exact(&mut b[..n])?;
+        if self.format == PlyFormat::BinaryBigEndian {
+            b[..n].reverse();
+        }
+        Ok(match ty {
+            PlyType::I8 => b[0] as i8 as f64,
+            PlyType::U8 => b[0] as f64,
+            PlyType::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            PlyType::I32 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::U32 => u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F32 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            PlyType::F64 => f64::from_le_bytes(b),
+        })
+    }
+}
+
+/// Loads a triangle mesh from an ASCII or binary PLY file. Vertex normals
+/// (`nx ny nz`) and colours (`red green blue`) are kept when present,
+/// polygons are split into fans. The file is streamed, so only the mesh
+/// itself has to fit in memory.
+pub fn load_ply(path: &str) -> io::Result<TriangleMesh> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut input = BufReader::new(file);
+    let mut line = String::new();
+    let mut header_len = input.read_line(&mut line)? as u64;
+    if line.trim() != "ply" {
+        return Err(invalid("not a PLY file"));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    loop {
+        line.clear();
+        let n = input.read_line(&mut line)?;
+        if n == 0 {
+            return Err(invalid("PLY header has no end_header"));
+        }
+        header_len += n as u64;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", f, ..] => {
+                format = Some(match *f {
+                    "ascii" => PlyFormat::Ascii,
+                    "binary_little_endian" => PlyFormat::BinaryLittleEndian,
+                    "binary_big_endian" => PlyFormat::BinaryBigEndian,
+                    _ => return Err(invalid("unknown PLY format")),
+                })
+            }
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid("bad PLY element count"))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_ty, ty, name] => {
+                let e = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("PLY property outside an element"))?;
+                e.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty).ok_or_else(|| invalid("bad PLY type"))?,
+                    list: Some(PlyType::parse(count_ty).ok_or_else(|| invalid("bad PLY type"))?),
+                });
+            }
+            ["property", ty, name] => {
+                let e = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("PLY property outside an element"))?;
+                e.properties.push(PlyProperty {
+                    name: name.to_string(),
+                    ty: PlyType::parse(ty).ok_or_else(|| invalid("bad PLY type"))?,
+                    list: None,
+                });
+            }
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("PLY header has no format"))?;
+    // Check the counts against the file before trusting them with memory.
+    let mut remaining = size.saturating_sub(header_len);
+    for e in elements.iter() {
+        let need = (e.count as u64)
+            .checked_mul(e.min_size(format))
+            .filter(|&n| n <= remaining)
+            .ok_or_else(|| invalid("PLY element count exceeds the file size"))?;
+        remaining -= need;
+    }
+
+    let mut body = PlyBody {
+        input,
+        format,
+        tokens: vec![],
+        next: 0,
+    };
+    let mut mesh = TriangleMesh::new();
+    let mut values: Vec<f64> = vec![];
+    for e in elements.iter() {
+        let find = |n: &str| e.properties.iter().position(|p| p.name == n);
+        if e.name == "vertex" {
+            let pos = [find("x"), find("y"), find("z")];
+            let nor = [find("nx"), find("ny"), find("nz")];
+            let col = [find("red"), find("green"), find("blue")];
+            if pos.contains(&None) {
+                return Err(invalid("PLY vertex without x, y, z"));
+            }
+            let has_normal = !nor.contains(&None);
+            let has_color = !col.contains(&None);
+            mesh.positions.reserve(e.count);
+            if has_normal {
+                mesh.normals.reserve(e.count);
+            }
+            if has_color {
+                mesh.colors.reserve(e.count);
+            }
+            for _i in 0..e.count {
+                values.clear();
+                for p in e.properties.iter() {
+                    values.push(read_property(&mut body, p)?);
+                }
+                let get = |i: Option<usize>| values[i.unwrap()];
+                mesh.positions
+                    .push(Vec3::new(get(pos[0]), get(pos[1]), get(pos[2])));
+                if has_normal {
+                    mesh.normals
+                        .push(Vec3::new(get(nor[0]), get(nor[1]), get(nor[2])));
+                }
+                if has_color {
+                    let scale = e.properties[col[0].unwrap()].ty.color_scale();
+                    mesh.colors
+                        .push(Vec3::new(get(col[0]), get(col[1]), get(col[2])) * scale);
+                }
+            }
+        } else if e.name == "face" {
+            let idx = find("vertex_indices")
+                .or_else(|| find("vertex_index"))
+                .ok_or_else(|| invalid("PLY face without vertex_indices"))?;
+            let corners = e
+                .count
+                .checked_mul(3)
+                .ok_or_else(|| invalid("too many PLY faces"))?;
+            mesh.position_indices.reserve(corners);
+            let mut corners: Vec<u32> = vec![];
+            for _i in 0..e.count {
+                for (k, p) in e.properties.iter().enumerate() {
+                    if k != idx {
+                        read_property(&mut body, p)?;
+                        continue;
+                    }
+                    let n = body.read(p.list.unwrap_or(PlyType::U8))? as usize;
+                    corners.clear();
+                    for _j in 0..n {
+                        corners.push(body.read(p.ty)? as u32);
+                    }
+                }
+                for k in 1..corners.len().saturating_sub(1) {
+                    for &c in [corners[0], corners[k], corners[k + 1]].iter() {
+                        if c as usize >= mesh.positions.len() {
+                            return Err(invalid("PLY face index out of range"));
+                        }
+                        mesh.position_indices.push(c);
+                    }
+                }
+            }
+        } else {
+            for _i in 0..e.count {
+                for p in e.properties.iter() {
+                    read_property(&mut body, p)?;
+                }
+            }
+        }
+    }
+
+    if !mesh.normals.is_empty() {
+        mesh.normal_indices = mesh.position_indices.clone();
+    }
+    Ok(mesh)
+}
+
+/// Reads one property, a list is skipped and reads as its length.
+fn read_property<R: BufRead>(body: &mut PlyBody<R>, p: &PlyProperty) -> io::Result<f64> {
+    match p.list {
+        Some(count_ty) => {
+            let n = body.read(count_ty)?;
+            for _i in 0..n as usize {
+                body.read(p.ty)?;
+            }
+            Ok(n)
+        }
+        None => body.read(p.ty),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CORNERS: [[f32; 3]; 4] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ];
+    const RGB: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    fn header(format: &str, vertices: u64, faces: u64) -> String {
+        format!(
+            "ply\nformat {} 1.0\ncomment a unit quad\nelement vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property float nx\nproperty float ny\nproperty float nz\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\n\
+             element face {}\nproperty list uchar int vertex_indices\nend_header\n",
+            format, vertices, faces
+        )
+    }
+
+    fn ascii() -> Vec<u8> {
+        let mut text = header("ascii", 4, 1);
+        for (p, c) in CORNERS.iter().zip(RGB.iter()) {
+            text += &format!(
+                "{} {} {} 0 0 1 {} {} {}\n",
+                p[0], p[1], p[2], c[0], c[1], c[2]
+            );
+        }
+        text += "4 0 1 2 3\n";
+        text.into_bytes()
+    }
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut bytes = header(format, 4, 1).into_bytes();
+        let put = |b: &mut Vec<u8>, x: [u8; 4]| {
+            if big_endian {
+                b.extend(x.iter().rev());
+            } else {
+                b.extend_from_slice(&x);
+            }
+        };
+        for (p, c) in CORNERS.iter().zip(RGB.iter()) {
+            for &x in p.iter().chain([0.0f32, 0.0, 1.0].iter()) {
+                put(&mut bytes, x.to_le_bytes());
+            }
+            bytes.extend_from_slice(c);
+        }
+        bytes.push(4);
+        for i in 0..4i32 {
+            put(&mut bytes, i.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn load(name: &str, bytes: &[u8]) -> io::Result<TriangleMesh> {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("raytracer-{}-{}.ply", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let mesh = load_ply(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    fn check_quad(mesh: &TriangleMesh) {
+        let positions: Vec<point3> = CORNERS
+            .iter()
+            .map(|p| Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        assert_eq!(mesh.positions, positions);
+        assert_eq!(mesh.position_indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.normal_indices, mesh.position_indices);
+        assert!(mesh.normals.iter().all(|&n| n == Vec3::new(0.0, 0.0, 1.0)));
+        assert_eq!(mesh.colors[0], Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.colors[3], Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn reads_every_format() {
+        check_quad(&load("ascii", &ascii()).unwrap());
+        check_quad(&load("little", &binary(false)).unwrap());
+        check_quad(&load("big", &binary(true)).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let text = ascii();
+        assert!(load("short-ascii", &text[..text.len() - 4]).is_err());
+        let bytes = binary(false);
+        assert!(load("short-binary", &bytes[..bytes.len() - 1]).is_err());
+        let head = header("binary_little_endian", 4, 1);
+        assert!(load("header-only", head.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn rejects_counts_larger_than_the_file() {
+        for &(vertices, faces) in [(4_000_000_000_000, 1), (4, 1 << 62), (u64::MAX, 1)].iter() {
+            let mut bytes = header("binary_little_endian", vertices, faces).into_bytes();
+            bytes.extend_from_slice(&binary(false)[header("binary_little_endian", 4, 1).len()..]);
+            assert!(load("lying-binary", &bytes).is_err());
+
+            let mut text = header("ascii", vertices, faces).into_bytes();
+            text.extend_from_slice(&ascii()[header("ascii", 4, 1).len()..]);
+            assert!(load("lying-ascii", &text).is_err());
+        }
+    }
+}
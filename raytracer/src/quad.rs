@@ -0,0 +1,144 @@
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+/// A flat parallelogram with corner `q` and edges `u` and `v`, or the
+/// triangle `q`, `q + u`, `q + v`. The front face is on the side of
+/// `u x v`, and the texture coordinates run 0 to 1 along `u` and `v`.
+pub struct Quad {
+    pub q: point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub mp: Arc<dyn Material>,
+    pub is_triangle: bool,
+    normal: Vec3,
+    d: f64,
+    /// `n / (n * n)` for `n = u x v`, turns a point on the plane into
+    /// coordinates along `u` and `v`.
+    w: Vec3,
+    area: f64,
+}
+
+impl Quad {
+    pub fn new(q: point3, u: Vec3, v: Vec3, mat: Arc<dyn Material>) -> Self {
+        let n = Vec3::cross(u, v);
+        let normal = n.unit();
+        Self {
+            q,
+            u,
+            v,
+            mp: mat,
+            is_triangle: false,
+            normal,
+            d: normal * q,
+            w: n / (n * n),
+            area: n.length(),
+        }
+    }
+
+    pub fn new_triangle(q: point3, u: Vec3, v: Vec3, mat: Arc<dyn Material>) -> Self {
+        let mut tmp = Self::new(q, u, v, mat);
+        tmp.is_triangle = true;
+        tmp.area *= 0.5;
+        tmp
+    }
+
+    /// Whether plane coordinates `a`, `b` fall inside the shape.
+    fn contains(&self, a: f64, b: f64) -> bool {
+        if self.is_triangle {
+            a >= 0.0 && b >= 0.0 && a + b <= 1.0
+        } else {
+            (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b)
+        }
+    }
+
+    /// `t`, the point and its plane coordinates where `ray` crosses the
+    /// shape in `t_min..t_max`.
+    fn intersect(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, point3, f64, f64)> {
+        let denom = self.normal * ray.direction();
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+        let t = (self.d - self.normal * ray.orign()) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let alpha = self.w * Vec3::cross(planar, self.v);
+        let beta = self.w * Vec3::cross(self.u, planar);
+        if !self.contains(alpha, beta) {
+            return None;
+        }
+        Some((t, p, alpha, beta))
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, p, alpha, beta) = match self.intersect(&ray, t_min, t_max) {
+            Some(h) => h,
+            None => return false,
+        };
+        rec.u = alpha;
+        rec.v = beta;
+        rec.t = t;
+        rec.set_face_normal(&ray, &self.normal);
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
+        rec.mat_ptr = self.mp.clone();
+        rec.p = p;
+        true
+    }
+
+    fn occluded(&self, ray: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&ray, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let mut corners = vec![self.q, self.q + self.u, self.q + self.v];
+        if !self.is_triangle {
+            corners.push(self.q + self.u + self.v);
+        }
+        let mut min = [INFINITY; 3];
+        let mut max = [-INFINITY; 3];
+        for c in corners.iter() {
+            for i in 0..3 {
+                min[i] = min[i].min(c.get(i as i32));
+                max[i] = max[i].max(c.get(i as i32));
+            }
+        }
+        // Keep the box of an axis-aligned quad from being flat.
+        for i in 0..3 {
+            if max[i] - min[i] < 0.0002 {
+                min[i] -= 0.0001;
+                max[i] += 0.0001;
+            }
+        }
+        *output_box = AABB::new(
+            point3::new(min[0], min[1], min[2]),
+            point3::new(max[0], max[1], max[2]),
+        );
+        true
+    }
+
+    fn pdf_value(&self, origin: &point3, dir: &Vec3) -> f64 {
+        let mut rec = HitRecord::new(self.mp.clone());
+        if !self.hit(Ray::new(*origin, *dir, 0.0), 0.001, INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let distance_squared = rec.t * rec.t * dir.squared_length();
+        let cosine = (*dir * self.normal).abs() / dir.length();
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &point3) -> Vec3 {
+        let (mut a, mut b) = (random_double1(), random_double1());
+        if self.is_triangle && a + b > 1.0 {
+            a = 1.0 - a;
+            b = 1.0 - b;
+        }
+        self.q + self.u * a + self.v * b - *origin
+    }
+}
@@ -0,0 +1,895 @@
+use crate::hittable::*;
+use crate::material::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+// All shapes here stand on the y axis, the renderer's up, and sweep `phi`
+// from +x towards +z up to `phi_max` degrees. Use `Transformed` to place
+// them. Texture `u` follows `phi` and `v` runs along the axis.
+
+/// Angle of `(x, z)` about the y axis in `[0, 2 pi)`.
+fn phi_of(x: f64, z: f64) -> f64 {
+    let phi = z.atan2(x);
+    if phi < 0.0 {
+        phi + 2.0 * PI
+    } else {
+        phi
+    }
+}
+
+/// Roots of `a t^2 + b t + c` in increasing order, a single root twice
+/// when `a` is zero.
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        return Some((-c / b, -c / b));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    // Avoids cancellation between `b` and the root.
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return Some((0.0, 0.0));
+    }
+    let (t0, t1) = (q / a, c / q);
+    Some(if t0 < t1 { (t0, t1) } else { (t1, t0) })
+}
+
+fn is_zero(x: f64) -> bool {
+    x.abs() < 1e-9
+}
+
+/// Real roots of `c[3] x^3 + c[2] x^2 + c[1] x + c[0]`, unordered.
+pub fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let cc = c[0] / c[3];
+    // Substitute x = y - a/3 to get y^3 + 3p y + 2q.
+    let sq_a = a * a;
+    let p = (-sq_a / 3.0 + b) / 3.0;
+    let q = (2.0 / 27.0 * a * sq_a - a * b / 3.0 + cc) / 2.0;
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+    let mut roots = if is_zero(d) {
+        if is_zero(q) {
+            vec![0.0]
+        } else {
+            let u = (-q).cbrt();
+            vec![2.0 * u, -u]
+        }
+    } else if d < 0.0 {
+        let phi = (-q / (-cb_p).sqrt()).clamp(-1.0, 1.0).acos() / 3.0;
+        let t = 2.0 * (-p).sqrt();
+        vec![
+            t * phi.cos(),
+            -t * (phi + PI / 3.0).cos(),
+            -t * (phi - PI / 3.0).cos(),
+        ]
+    } else {
+        let sqrt_d = d.sqrt();
+        vec![(sqrt_d - q).cbrt() - (sqrt_d + q).cbrt()]
+    };
+    for x in roots.iter_mut() {
+        *x -= a / 3.0;
+    }
+    roots
+}
+
+/// Real roots of `c[4] x^4 + ... + c[0]` by Ferrari's method, each
+/// polished with a few Newton steps, in increasing order.
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let cc = c[1] / c[4];
+    let d = c[0] / c[4];
+    // Substitute x = y - a/4 to get y^4 + p y^2 + q y + r.
+    let sq_a = a * a;
+    let p = -3.0 / 8.0 * sq_a + b;
+    let q = sq_a * a / 8.0 - a * b / 2.0 + cc;
+    let r = -3.0 / 256.0 * sq_a * sq_a + sq_a * b / 16.0 - a * cc / 4.0 + d;
+
+    let mut roots = vec![];
+    if is_zero(r) {
+        roots = solve_cubic([q, p, 0.0, 1.0]);
+        roots.push(0.0);
+    } else {
+        let z = solve_cubic([r * p / 2.0 - q * q / 8.0, -r, -p / 2.0, 1.0])[0];
+        let u = z * z - r;
+        let v = 2.0 * z - p;
+        let u = if is_zero(u) {
+            0.0
+        } else if u > 0.0 {
+            u.sqrt()
+        } else {
+            return roots;
+        };
+        let v = if is_zero(v) {
+            0.0
+        } else if v > 0.0 {
+            v.sqrt()
+        } else {
+            return roots;
+        };
+        let v = if q < 0.0 { -v } else { v };
+        for (k, s) in [(z - u, v), (z + u, -v)].iter() {
+            if let Some((y0, y1)) = solve_quadratic(1.0, *s, *k) {
+                roots.push(y0);
+                roots.push(y1);
+            }
+        }
+    }
+
+    let f = |x: f64| (((c[4] * x + c[3]) * x + c[2]) * x + c[1]) * x + c[0];
+    let df = |x: f64| ((4.0 * c[4] * x + 3.0 * c[3]) * x + 2.0 * c[2]) * x + c[1];
+    for x in roots.iter_mut() {
+        *x -= a / 4.0;
+        for _i in 0..2 {
+            let slope = df(*x);
+            if slope != 0.0 {
+                *x -= f(*x) / slope;
+            }
+        }
+    }
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+/// What a shape found at a hit, before it goes into the `HitRecord`.
+struct SurfaceHit {
+    t: f64,
+    p: point3,
+    outward_normal: Vec3,
+    u: f64,
+    v: f64,
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+
+impl SurfaceHit {
+    fn fill(&self, r: &Ray, mp: &Arc<dyn Material>, rec: &mut HitRecord) {
+        rec.t = self.t;
+        rec.p = self.p;
+        rec.u = self.u;
+        rec.v = self.v;
+        rec.set_face_normal(r, &self.outward_normal.unit());
+        rec.dpdu = self.dpdu;
+        rec.dpdv = self.dpdv;
+        rec.mat_ptr = mp.clone();
+    }
+}
+
+/// Tangent along `u` for a sweep of `phi_max` radians.
+fn sweep_tangent(p: &point3, phi_max: f64) -> Vec3 {
+    Vec3::new(-phi_max * p.z, 0.0, phi_max * p.x)
+}
+
+/// The ring `inner_radius <= r <= radius` in the plane `y = height`, a
+/// full disk for an inner radius of zero. Faces +y.
+pub struct Disk {
+    pub height: f64,
+    pub radius: f64,
+    pub inner_radius: f64,
+    pub phi_max: f64,
+    pub mp: Arc<dyn Material>,
+}
+
+impl Disk {
+    pub fn new(
+        height: f64,
+        radius: f64,
+        inner_radius: f64,
+        phi_max: f64,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            height,
+            radius,
+            inner_radius,
+            phi_max: degrees_to_radians(phi_max.clamp(0.0, 360.0)),
+            mp: mat,
+        }
+    }
+
+    /// The nearest hit in `t_min..t_max`.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        if r.direction().y == 0.0 {
+            return None;
+        }
+        let t = (self.height - r.orign().y) / r.direction().y;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = r.at(t);
+        let dist = (p.x * p.x + p.z * p.z).sqrt();
+        if dist > self.radius || dist < self.inner_radius {
+            return None;
+        }
+        let phi = phi_of(p.x, p.z);
+        if phi > self.phi_max {
+            return None;
+        }
+        let dr = self.radius - self.inner_radius;
+        let radial = if dist > 0.0 {
+            Vec3::new(p.x, 0.0, p.z) / dist
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        Some(SurfaceHit {
+            t,
+            p,
+            outward_normal: Vec3::new(0.0, 1.0, 0.0),
+            u: phi / self.phi_max,
+            v: (self.radius - dist) / dr,
+            dpdu: sweep_tangent(&p, self.phi_max),
+            dpdv: -radial * dr,
+        })
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.intersect(&r, t_min, t_max) {
+            Some(h) => {
+                h.fill(&r, &self.mp, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = AABB::new(
+            point3::new(-self.radius, self.height - 0.0001, -self.radius),
+            point3::new(self.radius, self.height + 0.0001, self.radius),
+        );
+        true
+    }
+}
+
+/// The tube of `radius` from `y_min` to `y_max`, open unless made with
+/// `capped`.
+pub struct Cylinder {
+    pub radius: f64,
+    pub y_min: f64,
+    pub y_max: f64,
+    pub phi_max: f64,
+    pub capped: bool,
+    pub mp: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    pub fn new(radius: f64, y_min: f64, y_max: f64, phi_max: f64, mat: Arc<dyn Material>) -> Self {
+        Self {
+            radius,
+            y_min: y_min.min(y_max),
+            y_max: y_min.max(y_max),
+            phi_max: degrees_to_radians(phi_max.clamp(0.0, 360.0)),
+            capped: false,
+            mp: mat,
+        }
+    }
+
+    /// Closed at both ends by disks swept as far as the side.
+    pub fn capped(
+        radius: f64,
+        y_min: f64,
+        y_max: f64,
+        phi_max: f64,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let mut tmp = Self::new(radius, y_min, y_max, phi_max, mat);
+        tmp.capped = true;
+        tmp
+    }
+
+    fn hit_side(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        let (o, d) = (r.orign(), r.direction());
+        let a = d.x * d.x + d.z * d.z;
+        if a == 0.0 {
+            return None;
+        }
+        let b = 2.0 * (d.x * o.x + d.z * o.z);
+        let c = o.x * o.x + o.z * o.z - self.radius * self.radius;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+        for &t in [t0, t1].iter() {
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = r.at(t);
+            let phi = phi_of(p.x, p.z);
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            let height = self.y_max - self.y_min;
+            return Some(SurfaceHit {
+                t,
+                p,
+                outward_normal: Vec3::new(p.x, 0.0, p.z),
+                u: phi / self.phi_max,
+                v: (p.y - self.y_min) / height,
+                dpdu: sweep_tangent(&p, self.phi_max),
+                dpdv: Vec3::new(0.0, height, 0.0),
+            });
+        }
+        None
+    }
+
+    /// The end at `y`, facing away from the tube along `up`.
+    fn hit_cap(&self, r: &Ray, y: f64, up: f64, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        if r.direction().y == 0.0 {
+            return None;
+        }
+        let t = (y - r.orign().y) / r.direction().y;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = r.at(t);
+        let dist = (p.x * p.x + p.z * p.z).sqrt();
+        let phi = phi_of(p.x, p.z);
+        if dist > self.radius || phi > self.phi_max {
+            return None;
+        }
+        let radial = if dist > 0.0 {
+            Vec3::new(p.x, 0.0, p.z) / dist
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        Some(SurfaceHit {
+            t,
+            p,
+            outward_normal: Vec3::new(0.0, up, 0.0),
+            u: phi / self.phi_max,
+            v: (self.radius - dist) / self.radius,
+            dpdu: sweep_tangent(&p, self.phi_max),
+            dpdv: -radial * self.radius,
+        })
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let mut closest = self.hit_side(&r, t_min, t_max);
+        if self.capped {
+            for &(y, up) in [(self.y_min, -1.0), (self.y_max, 1.0)].iter() {
+                let limit = closest.as_ref().map(|h| h.t).unwrap_or(t_max);
+                if let Some(h) = self.hit_cap(&r, y, up, t_min, limit) {
+                    closest = Some(h);
+                }
+            }
+        }
+        match closest {
+            Some(h) => {
+                h.fill(&r, &self.mp, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit_side(&r, t_min, t_max).is_some()
+            || (self.capped
+                && (self.hit_cap(&r, self.y_min, -1.0, t_min, t_max).is_some()
+                    || self.hit_cap(&r, self.y_max, 1.0, t_min, t_max).is_some()))
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = AABB::new(
+            point3::new(-self.radius, self.y_min, -self.radius),
+            point3::new(self.radius, self.y_max, self.radius),
+        );
+        true
+    }
+}
+
+/// An open cone with its base of `radius` at y = 0 and its apex at
+/// y = `height`, cut to `y_min <= y <= y_max`.
+pub struct Cone {
+    pub height: f64,
+    pub radius: f64,
+    pub y_min: f64,
+    pub y_max: f64,
+    pub phi_max: f64,
+    pub mp: Arc<dyn Material>,
+}
+
+impl Cone {
+    pub fn new(
+        height: f64,
+        radius: f64,
+        y_min: f64,
+        y_max: f64,
+        phi_max: f64,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let y_max = y_max.min(height);
+        Self {
+            height,
+            radius,
+            y_min: y_min.max(0.0).min(y_max),
+            y_max,
+            phi_max: degrees_to_radians(phi_max.clamp(0.0, 360.0)),
+            mp: mat,
+        }
+    }
+
+    /// The nearest hit in `t_min..t_max`.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        let (o, d) = (r.orign(), r.direction());
+        let k = (self.radius / self.height).powi(2);
+        let oy = o.y - self.height;
+        let a = d.x * d.x + d.z * d.z - k * d.y * d.y;
+        let b = 2.0 * (d.x * o.x + d.z * o.z - k * d.y * oy);
+        let c = o.x * o.x + o.z * o.z - k * oy * oy;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+        for &t in [t0, t1].iter() {
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = r.at(t);
+            let phi = phi_of(p.x, p.z);
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            let v = p.y / self.height;
+            let shrink = (1.0 - v).max(1e-12);
+            return Some(SurfaceHit {
+                t,
+                p,
+                outward_normal: Vec3::new(p.x, k * (self.height - p.y), p.z),
+                u: phi / self.phi_max,
+                v,
+                dpdu: sweep_tangent(&p, self.phi_max),
+                dpdv: Vec3::new(-p.x / shrink, self.height, -p.z / shrink),
+            });
+        }
+        None
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.intersect(&r, t_min, t_max) {
+            Some(h) => {
+                h.fill(&r, &self.mp, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        // The cone is widest at its lowest cut.
+        let radius = self.radius * (1.0 - self.y_min / self.height);
+        *output_box = AABB::new(
+            point3::new(-radius, self.y_min, -radius),
+            point3::new(radius, self.y_max, radius),
+        );
+        true
+    }
+}
+
+/// The bowl `x^2 + z^2 = radius^2 y / y_max`, cut to `y_min <= y <= y_max`.
+pub struct Paraboloid {
+    pub radius: f64,
+    pub y_min: f64,
+    pub y_max: f64,
+    pub phi_max: f64,
+    pub mp: Arc<dyn Material>,
+}
+
+impl Paraboloid {
+    pub fn new(radius: f64, y_min: f64, y_max: f64, phi_max: f64, mat: Arc<dyn Material>) -> Self {
+        let y_max = y_max.max(y_min);
+        Self {
+            radius,
+            y_min: y_min.max(0.0),
+            y_max,
+            phi_max: degrees_to_radians(phi_max.clamp(0.0, 360.0)),
+            mp: mat,
+        }
+    }
+
+    /// The nearest hit in `t_min..t_max`.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        let (o, d) = (r.orign(), r.direction());
+        let k = self.radius * self.radius / self.y_max;
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (d.x * o.x + d.z * o.z) - k * d.y;
+        let c = o.x * o.x + o.z * o.z - k * o.y;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+        for &t in [t0, t1].iter() {
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = r.at(t);
+            let phi = phi_of(p.x, p.z);
+            if p.y < self.y_min || p.y > self.y_max || phi > self.phi_max {
+                continue;
+            }
+            let height = self.y_max - self.y_min;
+            let y = p.y.max(1e-12);
+            return Some(SurfaceHit {
+                t,
+                p,
+                outward_normal: Vec3::new(2.0 * p.x, -k, 2.0 * p.z),
+                u: phi / self.phi_max,
+                v: (p.y - self.y_min) / height,
+                dpdu: sweep_tangent(&p, self.phi_max),
+                dpdv: Vec3::new(p.x / (2.0 * y), 1.0, p.z / (2.0 * y)) * height,
+            });
+        }
+        None
+    }
+}
+
+impl Hittable for Paraboloid {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.intersect(&r, t_min, t_max) {
+            Some(h) => {
+                h.fill(&r, &self.mp, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = AABB::new(
+            point3::new(-self.radius, self.y_min, -self.radius),
+            point3::new(self.radius, self.y_max, self.radius),
+        );
+        true
+    }
+}
+
+/// The surface swept by the segment `p1`-`p2` turning about the y axis.
+/// The ends must be at different heights.
+pub struct Hyperboloid {
+    pub p1: point3,
+    pub p2: point3,
+    pub phi_max: f64,
+    pub mp: Arc<dyn Material>,
+    /// `x^2 + z^2 = alpha y^2 + beta y + gamma` on the surface.
+    alpha: f64,
+    beta: f64,
+    gamma: f64,
+    r_max: f64,
+}
+
+impl Hyperboloid {
+    pub fn new(p1: point3, p2: point3, phi_max: f64, mat: Arc<dyn Material>) -> Self {
+        assert!(
+            p1.y != p2.y,
+            "a hyperboloid needs its ends at different heights"
+        );
+        // Along the segment, x and z are linear in y, so the squared
+        // radius is a quadratic in y.
+        let dy = p2.y - p1.y;
+        let sx = (p2.x - p1.x) / dy;
+        let sz = (p2.z - p1.z) / dy;
+        let x0 = p1.x - sx * p1.y;
+        let z0 = p1.z - sz * p1.y;
+        Self {
+            p1,
+            p2,
+            phi_max: degrees_to_radians(phi_max.clamp(0.0, 360.0)),
+            mp: mat,
+            alpha: sx * sx + sz * sz,
+            beta: 2.0 * (sx * x0 + sz * z0),
+            gamma: x0 * x0 + z0 * z0,
+            r_max: (p1.x * p1.x + p1.z * p1.z)
+                .max(p2.x * p2.x + p2.z * p2.z)
+                .sqrt(),
+        }
+    }
+
+    /// The nearest hit in `t_min..t_max`.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        let (o, d) = (r.orign(), r.direction());
+        let a = d.x * d.x + d.z * d.z - self.alpha * d.y * d.y;
+        let b = 2.0 * (d.x * o.x + d.z * o.z - self.alpha * o.y * d.y) - self.beta * d.y;
+        let c = o.x * o.x + o.z * o.z - self.alpha * o.y * o.y - self.beta * o.y - self.gamma;
+        let (t0, t1) = solve_quadratic(a, b, c)?;
+        let (y_min, y_max) = (self.p1.y.min(self.p2.y), self.p1.y.max(self.p2.y));
+        for &t in [t0, t1].iter() {
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = r.at(t);
+            if p.y < y_min || p.y > y_max {
+                continue;
+            }
+            // `phi` is measured from where the segment itself lies at this
+            // height.
+            let v = (p.y - self.p1.y) / (self.p2.y - self.p1.y);
+            let pr = self.p1 * (1.0 - v) + self.p2 * v;
+            let phi = phi_of(pr.x * p.x + pr.z * p.z, pr.x * p.z - pr.z * p.x);
+            if phi > self.phi_max {
+                continue;
+            }
+            let (sin, cos) = phi.sin_cos();
+            let e = self.p2 - self.p1;
+            return Some(SurfaceHit {
+                t,
+                p,
+                outward_normal: Vec3::new(p.x, -self.alpha * p.y - 0.5 * self.beta, p.z),
+                u: phi / self.phi_max,
+                v,
+                dpdu: sweep_tangent(&p, self.phi_max),
+                dpdv: Vec3::new(e.x * cos - e.z * sin, e.y, e.x * sin + e.z * cos),
+            });
+        }
+        None
+    }
+}
+
+impl Hittable for Hyperboloid {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.intersect(&r, t_min, t_max) {
+            Some(h) => {
+                h.fill(&r, &self.mp, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = AABB::new(
+            point3::new(-self.r_max, self.p1.y.min(self.p2.y), -self.r_max),
+            point3::new(self.r_max, self.p1.y.max(self.p2.y), self.r_max),
+        );
+        true
+    }
+}
+
+/// A ring of tube radius `minor` whose centre circle of radius `major`
+/// lies in the plane y = 0. Hit by solving a quartic. `theta` goes round
+/// the tube from the outer equator towards +y, and only the part from
+/// `theta_min` to `theta_max` degrees is kept.
+pub struct Torus {
+    pub major: f64,
+    pub minor: f64,
+    pub theta_min: f64,
+    pub theta_max: f64,
+    pub phi_max: f64,
+    pub mp: Arc<dyn Material>,
+}
+
+impl Torus {
+    pub fn new(
+        major: f64,
+        minor: f64,
+        theta_min: f64,
+        theta_max: f64,
+        phi_max: f64,
+        mat: Arc<dyn Material>,
+    ) -> Self {
+        let theta_max = theta_max.clamp(0.0, 360.0);
+        Self {
+            major,
+            minor,
+            theta_min: degrees_to_radians(theta_min.clamp(0.0, theta_max)),
+            theta_max: degrees_to_radians(theta_max),
+            phi_max: degrees_to_radians(phi_max.clamp(0.0, 360.0)),
+            mp: mat,
+        }
+    }
+
+    /// The nearest hit in `t_min..t_max`.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<SurfaceHit> {
+        let length = r.direction().length();
+        if length == 0.0 {
+            return None;
+        }
+        let d = r.direction() / length;
+        // Start the quartic where the ray enters the bounding sphere, so
+        // its coefficients stay small for distant rays.
+        let outer = self.major + self.minor;
+        let b = r.orign() * d;
+        let disc = b * b - (r.orign() * r.orign() - outer * outer);
+        if disc < 0.0 {
+            return None;
+        }
+        let start = (-b - disc.sqrt()).max(0.0);
+        let o = r.orign() + d * start;
+
+        let rr = self.major * self.major;
+        let f = o * d;
+        let e = o * o - rr - self.minor * self.minor;
+        let roots = solve_quartic([
+            e * e - 4.0 * rr * (self.minor * self.minor - o.y * o.y),
+            4.0 * f * e + 8.0 * rr * o.y * d.y,
+            2.0 * e + 4.0 * f * f + 4.0 * rr * d.y * d.y,
+            4.0 * f,
+            1.0,
+        ]);
+        for s in roots {
+            let t = (start + s) / length;
+            if t < t_min || t > t_max {
+                continue;
+            }
+            let p = r.at(t);
+            let phi = phi_of(p.x, p.z);
+            if phi > self.phi_max {
+                continue;
+            }
+            let ring = (p.x * p.x + p.z * p.z).sqrt();
+            let theta = phi_of(ring - self.major, p.y);
+            if theta < self.theta_min || theta > self.theta_max {
+                continue;
+            }
+            let theta_range = self.theta_max - self.theta_min;
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            let centre = Vec3::new(cos_phi, 0.0, sin_phi) * self.major;
+            return Some(SurfaceHit {
+                t,
+                p,
+                outward_normal: p - centre,
+                u: phi / self.phi_max,
+                v: (theta - self.theta_min) / theta_range,
+                dpdu: sweep_tangent(&p, self.phi_max),
+                dpdv: Vec3::new(-sin_theta * cos_phi, cos_theta, -sin_theta * sin_phi)
+                    * (theta_range * self.minor),
+            });
+        }
+        None
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        match self.intersect(&r, t_min, t_max) {
+            Some(h) => {
+                h.fill(&r, &self.mp, rec);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let outer = self.major + self.minor;
+        *output_box = AABB::new(
+            point3::new(-outer, -self.minor, -outer),
+            point3::new(outer, self.minor, outer),
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(mut found: Vec<f64>, expected: &[f64], tolerance: f64) {
+        found.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        assert_eq!(found.len(), expected.len(), "roots {:?}", found);
+        for (x, e) in found.iter().zip(expected) {
+            assert!((x - e).abs() < tolerance, "roots {:?}", found);
+        }
+    }
+
+    #[test]
+    fn quadratic_roots() {
+        assert_eq!(solve_quadratic(1.0, -4.0, 3.0), Some((1.0, 3.0)));
+        assert_eq!(solve_quadratic(-1.0, 4.0, -3.0), Some((1.0, 3.0)));
+        assert_eq!(solve_quadratic(1.0, -2.0, 1.0), Some((1.0, 1.0)));
+        assert_eq!(solve_quadratic(0.0, 2.0, -4.0), Some((2.0, 2.0)));
+        assert_eq!(solve_quadratic(1.0, 0.0, 1.0), None);
+        assert_eq!(solve_quadratic(0.0, 0.0, 1.0), None);
+        // Neither root is lost to cancellation.
+        let (t0, t1) = solve_quadratic(1.0, -1e8, 1.0).unwrap();
+        assert!((t0 - 1e-8).abs() < 1e-20 && (t1 - 1e8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cubic_roots() {
+        // (x - 1)(x - 2)(x - 3)
+        assert_roots(solve_cubic([-6.0, 11.0, -6.0, 1.0]), &[1.0, 2.0, 3.0], 1e-9);
+        // (x - 1)^2 (x + 2)
+        assert_roots(solve_cubic([2.0, -3.0, 0.0, 1.0]), &[-2.0, 1.0], 1e-9);
+        // (x - 1)^3
+        assert_roots(solve_cubic([-1.0, 3.0, -3.0, 1.0]), &[1.0], 1e-9);
+        // (x - 2)(x^2 + 1)
+        assert_roots(solve_cubic([-2.0, 1.0, -2.0, 1.0]), &[2.0], 1e-9);
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(
+            solve_quartic([24.0, -50.0, 35.0, -10.0, 1.0]),
+            &[1.0, 2.0, 3.0, 4.0],
+            1e-9,
+        );
+        // 2 (x + 1)(x - 1)(x - 2)(x - 3), with a leading coefficient.
+        assert_roots(
+            solve_quartic([-12.0, 10.0, 10.0, -10.0, 2.0]),
+            &[-1.0, 1.0, 2.0, 3.0],
+            1e-9,
+        );
+        // (x - 1)^2 (x - 2)^2: double roots only converge to about the
+        // square root of the precision.
+        assert_roots(
+            solve_quartic([4.0, -12.0, 13.0, -6.0, 1.0]),
+            &[1.0, 1.0, 2.0, 2.0],
+            1e-6,
+        );
+        // (x^2 + 1)(x - 1)(x - 2)
+        assert_roots(
+            solve_quartic([2.0, -3.0, 3.0, -3.0, 1.0]),
+            &[1.0, 2.0],
+            1e-9,
+        );
+        // (x^2 + 1)(x^2 + 4) and x^4 + 1
+        assert_roots(solve_quartic([4.0, 0.0, 5.0, 0.0, 1.0]), &[], 0.0);
+        assert_roots(solve_quartic([1.0, 0.0, 0.0, 0.0, 1.0]), &[], 0.0);
+    }
+
+    #[test]
+    fn torus_and_cone_cuts() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)));
+        let down = |x: f64| Ray::new(point3::new(x, 2.0, 0.0), Vec3::new(0.0, -1.0, 0.0), 0.0);
+
+        // Only the upper half of the tube is kept, so a ray down through
+        // it stops on top and a ray up from below meets the far side.
+        let torus = Torus::new(1.0, 0.25, 0.0, 180.0, 360.0, mat.clone());
+        let mut rec = HitRecord::new0();
+        assert!(torus.hit(down(1.0), 0.001, INFINITY, &mut rec));
+        assert!((rec.p.y - 0.25).abs() < 1e-9);
+        let up = Ray::new(point3::new(1.0, -2.0, 0.0), Vec3::new(0.0, 1.0, 0.0), 0.0);
+        assert!(torus.hit(up, 0.001, INFINITY, &mut rec));
+        assert!((rec.p.y - 0.25).abs() < 1e-9 && !rec.front_face);
+        let r = Ray::new(point3::new(2.0, -0.1, 0.0), Vec3::new(-1.0, 0.0, 0.0), 0.0);
+        assert!(!torus.occluded(r, 0.001, 1.0));
+
+        // A cone of height 2 cut at y = 1 is a frustum, and rays down
+        // through its missing tip pass.
+        let cone = Cone::new(2.0, 1.0, 0.0, 1.0, 360.0, mat);
+        assert!(!cone.hit(down(0.0), 0.001, INFINITY, &mut rec));
+        assert!(cone.hit(down(0.6), 0.001, INFINITY, &mut rec));
+        assert!((rec.p.y - 0.8).abs() < 1e-9);
+        let mut output_box = AABB::new(point3::zero(), point3::zero());
+        assert!(cone.bounding_box(0.0, 1.0, &mut output_box));
+        assert!((output_box.maximum.y - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic]
+    fn flat_hyperboloid() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)));
+        Hyperboloid::new(
+            point3::new(1.0, 0.0, 0.0),
+            point3::new(0.0, 0.0, 1.0),
+            360.0,
+            mat,
+        );
+    }
+}
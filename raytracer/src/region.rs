@@ -0,0 +1,363 @@
+pub use crate::rtweekend::*;
+pub use crate::vec3::Vec3;
+use image::{ImageBuffer, RgbImage};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+pub use Vec3 as color;
+
+const PARTIAL_MAGIC: &[u8; 8] = b"RTPART\0\0";
+const PARTIAL_VERSION: u32 = 1;
+/// Bytes of the header, and of each pixel after it.
+const PARTIAL_HEADER: u64 = 8 + 7 * 4;
+const PARTIAL_PIXEL: u64 = 3 * 8 + 4;
+
+/// Pixel rectangle of the frame, `x1` and `y1` exclusive, `y` from the top row.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Region {
+    pub fn new(x0: u32, y0: u32, x1: u32, y1: u32) -> Self {
+        Self { x0, y0, x1, y1 }
+    }
+
+    pub fn full(width: u32, height: u32) -> Self {
+        Self::new(0, 0, width, height)
+    }
+
+    /// Reads `x0,y0,x1,y1`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let v: Vec<u32> = s
+            .split(',')
+            .map(|x| x.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        if v.len() != 4 {
+            return None;
+        }
+        let region = Self::new(v[0], v[1], v[2], v[3]);
+        if region.is_empty() {
+            return None;
+        }
+        Some(region)
+    }
+
+    pub fn clip(&self, width: u32, height: u32) -> Self {
+        Self::new(
+            self.x0.min(width),
+            self.y0.min(height),
+            self.x1.min(width),
+            self.y1.min(height),
+        )
+    }
+
+    pub fn width(&self) -> u32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1 - self.y0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x0 >= self.x1 || self.y0 >= self.y1
+    }
+
+    /// Number of pixels, or `None` if that does not fit in memory.
+    pub fn area(&self) -> Option<usize> {
+        (self.width() as usize).checked_mul(self.height() as usize)
+    }
+
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+}
+
+/// Summed linear radiance of a region, kept with the sample count of every
+/// pixel so renders of the same frame can be added up later.
+pub struct PartialImage {
+    pub width: u32,
+    pub height: u32,
+    pub region: Region,
+    pub sum: Vec<color>,
+    pub counts: Vec<u32>,
+}
+
+impl PartialImage {
+    pub fn new(width: u32, height: u32, region: Region) -> Self {
+        let n = region.area().expect("region too large");
+        Self {
+            width,
+            height,
+            region,
+            sum: vec![color::zero(); n],
+            counts: vec![0; n],
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y - self.region.y0) as usize * self.region.width() as usize + (x - self.region.x0) as usize
+    }
+
+    /// Adds `samples` samples summing to `sum` to frame pixel (x, y).
+    pub fn add(&mut self, x: u32, y: u32, sum: color, samples: u32) {
+        let i = self.index(x, y);
+        self.sum[i] += sum;
+        self.counts[i] += samples;
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> Option<image::Rgb<u8>> {
+        let i = self.index(x, y);
+        if self.counts[i] == 0 {
+            return None;
+        }
+        Some(to_rgb(self.sum[i], self.counts[i]))
+    }
+
+    /// The region on its own, as large as the region.
+    pub fn to_image(&self) -> RgbImage {
+        let mut img: RgbImage = ImageBuffer::new(self.region.width(), self.region.height());
+        for y in self.region.y0..self.region.y1 {
+            for x in self.region.x0..self.region.x1 {
+                if let Some(p) = self.pixel(x, y) {
+                    img.put_pixel(x - self.region.x0, y - self.region.y0, p);
+                }
+            }
+        }
+        img
+    }
+
+    /// Writes the pixels that have samples over a full frame render.
+    pub fn composite(&self, base: &mut RgbImage) {
+        for y in self.region.y0..self.region.y1.min(base.height()) {
+            for x in self.region.x0..self.region.x1.min(base.width()) {
+                if let Some(p) = self.pixel(x, y) {
+                    base.put_pixel(x, y, p);
+                }
+            }
+        }
+    }
+
+    /// Adds up partial renders of one frame into a full frame. Pixels covered
+    /// by several parts are weighted by their sample counts.
+    pub fn merge(parts: &[PartialImage]) -> io::Result<PartialImage> {
+        let first = match parts.first() {
+            Some(p) => p,
+            None => return Err(invalid("nothing to merge")),
+        };
+        let mut merged = PartialImage::new(
+            first.width,
+            first.height,
+            Region::full(first.width, first.height),
+        );
+        for part in parts {
+            if part.width != first.width || part.height != first.height {
+                return Err(invalid("partial renders of different frame sizes"));
+            }
+            for y in part.region.y0..part.region.y1 {
+                for x in part.region.x0..part.region.x1 {
+                    let (i, j) = (part.index(x, y), merged.index(x, y));
+                    merged.counts[j] = merged.counts[j]
+                        .checked_add(part.counts[i])
+                        .ok_or_else(|| invalid("too many samples in one pixel"))?;
+                    merged.sum[j] += part.sum[i];
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(PARTIAL_MAGIC)?;
+        for v in [
+            PARTIAL_VERSION,
+            self.width,
+            self.height,
+            self.region.x0,
+            self.region.y0,
+            self.region.x1,
+            self.region.y1,
+        ]
+        .iter()
+        {
+            out.write_all(&v.to_le_bytes())?;
+        }
+        for (c, n) in self.sum.iter().zip(self.counts.iter()) {
+            out.write_all(&c.x.to_le_bytes())?;
+            out.write_all(&c.y.to_le_bytes())?;
+            out.write_all(&c.z.to_le_bytes())?;
+            out.write_all(&n.to_le_bytes())?;
+        }
+        out.flush()
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != PARTIAL_MAGIC {
+            return Err(invalid("not a partial render"));
+        }
+        if read_u32(&mut input)? != PARTIAL_VERSION {
+            return Err(invalid("unsupported partial render version"));
+        }
+        let width = read_u32(&mut input)?;
+        let height = read_u32(&mut input)?;
+        let region = Region::new(
+            read_u32(&mut input)?,
+            read_u32(&mut input)?,
+            read_u32(&mut input)?,
+            read_u32(&mut input)?,
+        );
+        if region.is_empty() || region.clip(width, height) != region {
+            return Err(invalid("bad region in partial render"));
+        }
+        // Check the size before allocating anything for it.
+        let expected = region
+            .area()
+            .and_then(|n| (n as u64).checked_mul(PARTIAL_PIXEL))
+            .and_then(|n| n.checked_add(PARTIAL_HEADER));
+        if expected != Some(file_len) {
+            return Err(invalid("partial render has the wrong length"));
+        }
+        let mut part = PartialImage::new(width, height, region);
+        for i in 0..part.sum.len() {
+            part.sum[i] = color::new(
+                read_f64(&mut input)?,
+                read_f64(&mut input)?,
+                read_f64(&mut input)?,
+            );
+            part.counts[i] = read_u32(&mut input)?;
+        }
+        Ok(part)
+    }
+}
+
+/// Averages, gamma corrects and quantizes a summed pixel.
+pub fn to_rgb(sum: color, samples: u32) -> image::Rgb<u8> {
+    let scale = 1.0 / samples as f64;
+    let r = (scale * sum.x).sqrt();
+    let g = (scale * sum.y).sqrt();
+    let b = (scale * sum.z).sqrt();
+    image::Rgb([
+        (clamp(r, 0.0, 0.999) * 255.0) as u8,
+        (clamp(g, 0.0, 0.999) * 255.0) as u8,
+        (clamp(b, 0.0, 0.999) * 255.0) as u8,
+    ])
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    input.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_f64(input: &mut impl Read) -> io::Result<f64> {
+    let mut b = [0u8; 8];
+    input.read_exact(&mut b)?;
+    Ok(f64::from_le_bytes(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("raytracer-{}-{}.part", name, std::process::id()));
+        path.to_str().unwrap().to_string()
+    }
+
+    /// A part over `region` of an 8x6 frame, every pixel with `samples`
+    /// samples of `value`.
+    fn part(region: Region, value: f64, samples: u32) -> PartialImage {
+        let mut part = PartialImage::new(8, 6, region);
+        for y in region.y0..region.y1 {
+            for x in region.x0..region.x1 {
+                part.add(
+                    x,
+                    y,
+                    color::new(value, x as f64, y as f64) * samples as f64,
+                    samples,
+                );
+            }
+        }
+        part
+    }
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!(Region::parse("1, 2,3,4"), Some(Region::new(1, 2, 3, 4)));
+        assert_eq!(Region::parse("1,2,3"), None);
+        assert_eq!(Region::parse("3,2,3,4"), None);
+        assert_eq!(Region::parse("1,2,3,-4"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("region-round-trip");
+        let saved = part(Region::new(2, 1, 7, 4), 0.25, 3);
+        saved.save(&path).unwrap();
+        let loaded = PartialImage::load(&path);
+
+        // Cut off the last byte.
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        let truncated = PartialImage::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!((loaded.width, loaded.height), (8, 6));
+        assert_eq!(loaded.region, saved.region);
+        assert_eq!(loaded.counts, saved.counts);
+        for (a, b) in loaded.sum.iter().zip(&saved.sum) {
+            assert_eq!((a.x, a.y, a.z), (b.x, b.y, b.z));
+        }
+        assert!(truncated.is_err());
+    }
+
+    #[test]
+    fn merges_overlapping_parts() {
+        let a = part(Region::new(0, 0, 5, 4), 0.2, 2);
+        let b = part(Region::new(3, 2, 8, 6), 0.8, 6);
+        let merged = PartialImage::merge(&[a, b]).unwrap();
+        assert_eq!(merged.region, Region::full(8, 6));
+
+        let at = |x: u32, y: u32| {
+            let i = merged.index(x, y);
+            (merged.sum[i] / merged.counts[i] as f64, merged.counts[i])
+        };
+        assert_eq!(at(0, 0).1, 2);
+        assert!((at(0, 0).0.x - 0.2).abs() < 1e-12);
+        assert_eq!(at(7, 5).1, 6);
+        // Where they overlap the parts are weighted by sample count.
+        let (mean, count) = at(4, 3);
+        assert_eq!(count, 8);
+        assert!((mean.x - (0.2 * 2.0 + 0.8 * 6.0) / 8.0).abs() < 1e-12);
+        assert!((mean.y - 4.0).abs() < 1e-12 && (mean.z - 3.0).abs() < 1e-12);
+        // Neither part covers (7, 0).
+        assert_eq!(at(7, 0).1, 0);
+        assert!(merged.pixel(7, 0).is_none());
+    }
+
+    #[test]
+    fn rejects_bad_merges() {
+        let full = Region::full(8, 6);
+        assert!(PartialImage::merge(&[]).is_err());
+        let many = part(full, 0.5, u32::MAX - 1);
+        assert!(PartialImage::merge(&[many, part(full, 0.5, 2)]).is_err());
+        let other = PartialImage::new(4, 4, Region::full(4, 4));
+        assert!(PartialImage::merge(&[part(full, 0.5, 1), other]).is_err());
+    }
+}
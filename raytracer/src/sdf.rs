@@ -0,0 +1,452 @@
+use crate::hittable::*;
+use crate::perlin::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+/// A signed distance field: negative inside, positive outside.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: &point3) -> f64;
+
+    /// How much faster than distance the value can change. Sphere tracing
+    /// divides its steps by this, so fields that overestimate the distance
+    /// still do not step through the surface.
+    fn lipschitz(&self) -> f64 {
+        1.0
+    }
+}
+
+pub struct SdfSphere {
+    pub center: point3,
+    pub radius: f64,
+}
+
+impl SdfSphere {
+    pub fn new(center: point3, radius: f64) -> Self {
+        Self { center, radius }
+    }
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: &point3) -> f64 {
+        (*p - self.center).length() - self.radius
+    }
+}
+
+/// An axis-aligned box around `center`, rounded off by `rounding`.
+pub struct SdfBox {
+    pub center: point3,
+    pub half_size: Vec3,
+    pub rounding: f64,
+}
+
+impl SdfBox {
+    pub fn new(center: point3, half_size: Vec3, rounding: f64) -> Self {
+        Self {
+            center,
+            half_size,
+            rounding,
+        }
+    }
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: &point3) -> f64 {
+        let d = *p - self.center;
+        let r = self.rounding;
+        let q = Vec3::new(
+            d.x.abs() - self.half_size.x + r,
+            d.y.abs() - self.half_size.y + r,
+            d.z.abs() - self.half_size.z + r,
+        );
+        let outside = Vec3::new(q.x.max(0.0), q.y.max(0.0), q.z.max(0.0)).length();
+        outside + q.x.max(q.y).max(q.z).min(0.0) - r
+    }
+}
+
+/// A ring lying in the xz plane around `center`.
+pub struct SdfTorus {
+    pub center: point3,
+    pub major: f64,
+    pub minor: f64,
+}
+
+impl SdfTorus {
+    pub fn new(center: point3, major: f64, minor: f64) -> Self {
+        Self {
+            center,
+            major,
+            minor,
+        }
+    }
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: &point3) -> f64 {
+        let d = *p - self.center;
+        let ring = (d.x * d.x + d.z * d.z).sqrt() - self.major;
+        (ring * ring + d.y * d.y).sqrt() - self.minor
+    }
+}
+
+/// The points within `radius` of the segment `a`-`b`.
+pub struct SdfCapsule {
+    pub a: point3,
+    pub b: point3,
+    pub radius: f64,
+}
+
+impl SdfCapsule {
+    pub fn new(a: point3, b: point3, radius: f64) -> Self {
+        Self { a, b, radius }
+    }
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: &point3) -> f64 {
+        let pa = *p - self.a;
+        let ba = self.b - self.a;
+        let h = ((pa * ba) / (ba * ba)).clamp(0.0, 1.0);
+        (pa - ba * h).length() - self.radius
+    }
+}
+
+/// Polynomial smooth minimum, blending over a width of `k`.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b * (1.0 - h) + a * h - k * h * (1.0 - h)
+}
+
+/// `a` and `b` melted together over a width of `k`.
+pub struct SmoothUnion {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl SmoothUnion {
+    pub fn new(a: Arc<dyn Sdf>, b: Arc<dyn Sdf>, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: &point3) -> f64 {
+        smooth_min(self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn lipschitz(&self) -> f64 {
+        self.a.lipschitz().max(self.b.lipschitz())
+    }
+}
+
+/// `a` with `b` carved out, the edge rounded over a width of `k`.
+pub struct SmoothSubtraction {
+    pub a: Arc<dyn Sdf>,
+    pub b: Arc<dyn Sdf>,
+    pub k: f64,
+}
+
+impl SmoothSubtraction {
+    pub fn new(a: Arc<dyn Sdf>, b: Arc<dyn Sdf>, k: f64) -> Self {
+        Self { a, b, k }
+    }
+}
+
+impl Sdf for SmoothSubtraction {
+    fn distance(&self, p: &point3) -> f64 {
+        -smooth_min(-self.a.distance(p), self.b.distance(p), self.k)
+    }
+
+    fn lipschitz(&self) -> f64 {
+        self.a.lipschitz().max(self.b.lipschitz())
+    }
+}
+
+/// Copies of `inner` every `period` along each axis, with the copy at the
+/// origin in the middle. A zero period leaves that axis alone.
+pub struct Repeat {
+    pub inner: Arc<dyn Sdf>,
+    pub period: Vec3,
+}
+
+impl Repeat {
+    pub fn new(inner: Arc<dyn Sdf>, period: Vec3) -> Self {
+        Self { inner, period }
+    }
+}
+
+impl Sdf for Repeat {
+    fn distance(&self, p: &point3) -> f64 {
+        let wrap = |x: f64, c: f64| {
+            if c > 0.0 {
+                x - c * (x / c).round()
+            } else {
+                x
+            }
+        };
+        self.inner.distance(&Vec3::new(
+            wrap(p.x, self.period.x),
+            wrap(p.y, self.period.y),
+            wrap(p.z, self.period.z),
+        ))
+    }
+
+    fn lipschitz(&self) -> f64 {
+        self.inner.lipschitz()
+    }
+}
+
+/// `inner` twisted about the y axis by `rate` radians per unit of height.
+/// `reach` is how far from the axis the shape extends, which bounds how
+/// much the twist stretches the field.
+pub struct Twist {
+    pub inner: Arc<dyn Sdf>,
+    pub rate: f64,
+    pub reach: f64,
+}
+
+impl Twist {
+    pub fn new(inner: Arc<dyn Sdf>, rate: f64, reach: f64) -> Self {
+        Self { inner, rate, reach }
+    }
+}
+
+impl Sdf for Twist {
+    fn distance(&self, p: &point3) -> f64 {
+        let (sin, cos) = (self.rate * p.y).sin_cos();
+        self.inner.distance(&Vec3::new(
+            cos * p.x - sin * p.z,
+            p.y,
+            sin * p.x + cos * p.z,
+        ))
+    }
+
+    fn lipschitz(&self) -> f64 {
+        let stretch = self.rate * self.reach;
+        self.inner.lipschitz() * (1.0 + stretch * stretch).sqrt()
+    }
+}
+
+/// `inner` pushed out by Perlin noise of `amplitude`, with features about
+/// `1 / frequency` across.
+pub struct Displace {
+    pub inner: Arc<dyn Sdf>,
+    pub noise: Perlin,
+    pub amplitude: f64,
+    pub frequency: f64,
+}
+
+impl Displace {
+    pub fn new(inner: Arc<dyn Sdf>, amplitude: f64, frequency: f64) -> Self {
+        Self {
+            inner,
+            noise: Perlin::new(),
+            amplitude,
+            frequency,
+        }
+    }
+}
+
+impl Sdf for Displace {
+    fn distance(&self, p: &point3) -> f64 {
+        self.inner.distance(p) - self.amplitude * self.noise.noise(&(*p * self.frequency))
+    }
+
+    fn lipschitz(&self) -> f64 {
+        // Trilinear Perlin noise changes by at most about 2 per unit.
+        self.inner.lipschitz() + 2.0 * self.amplitude.abs() * self.frequency
+    }
+}
+
+/// The Mandelbulb fractal of the given power, about 1.2 across, by its
+/// distance estimator.
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: usize,
+}
+
+impl Mandelbulb {
+    pub fn new(power: f64, iterations: usize) -> Self {
+        Self { power, iterations }
+    }
+}
+
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: &point3) -> f64 {
+        let mut z = *p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+        for _i in 0..self.iterations {
+            if r > 2.0 {
+                break;
+            }
+            let theta = (z.y / r).clamp(-1.0, 1.0).acos() * self.power;
+            let phi = z.z.atan2(z.x) * self.power;
+            dr = r.powf(self.power - 1.0) * self.power * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ) * zr
+                + *p;
+            r = z.length();
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+/// Renders an `Sdf` by sphere tracing inside `bbox`, which must hold the
+/// whole surface. Normals come from the gradient of the field.
+pub struct SdfObject {
+    pub sdf: Arc<dyn Sdf>,
+    pub bbox: AABB,
+    pub mp: Arc<dyn Material>,
+    pub max_steps: usize,
+    /// Distance at which a point counts as on the surface.
+    pub epsilon: f64,
+}
+
+impl SdfObject {
+    pub fn new(sdf: Arc<dyn Sdf>, bbox: AABB, mat: Arc<dyn Material>) -> Self {
+        Self {
+            sdf,
+            bbox,
+            mp: mat,
+            max_steps: 512,
+            epsilon: 1e-4,
+        }
+    }
+
+    /// Where `r` is inside the box, clipped to `t_min`-`t_max`.
+    fn clip(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction().get(a);
+            let mut near = (self.bbox.min().get(a) - r.orign().get(a)) * inv_d;
+            let mut far = (self.bbox.max().get(a) - r.orign().get(a)) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 <= t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    fn gradient(&self, p: &point3) -> Vec3 {
+        // Four samples on a tetrahedron instead of six on the axes.
+        let h = self.epsilon;
+        let k = [
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        k.iter().fold(Vec3::zero(), |g, k| {
+            g + *k * self.sdf.distance(&(*p + *k * h))
+        })
+    }
+
+    /// Marches along `r` to the first surface in `t_min`-`t_max`. A ray
+    /// spawned on the surface starts within `epsilon` of it, so a point near
+    /// the surface only counts once the field stops growing.
+    fn march(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let (t0, t1) = self.clip(r, t_min, t_max)?;
+        let speed = r.direction().length();
+        let step_scale = 1.0 / (self.sdf.lipschitz() * speed);
+        // Rays that start inside, such as refracted ones, march on the
+        // negated field towards the way out.
+        let side = if self.sdf.distance(&r.at(t0)) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let mut t = t0;
+        let mut last = -INFINITY;
+        for _i in 0..self.max_steps {
+            let d = side * self.sdf.distance(&r.at(t));
+            if d < self.epsilon && d <= last {
+                return Some(t);
+            }
+            last = d;
+            // Leaving the band around the surface takes steps of at least
+            // `epsilon`, else grazing rays crawl.
+            t += d.max(self.epsilon) * step_scale;
+            if t > t1 {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let t = match self.march(&r, t_min, t_max) {
+            Some(t) => t,
+            None => return false,
+        };
+        let p = r.at(t);
+        let outward_normal = self.gradient(&p).unit();
+        rec.t = t;
+        rec.p = p;
+        Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        rec.set_face_normal(&r, &outward_normal);
+        rec.mat_ptr = self.mp.clone();
+        true
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.march(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = self.bbox;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_sphere() -> SdfObject {
+        SdfObject::new(
+            Arc::new(SdfSphere::new(point3::zero(), 1.0)),
+            AABB::new(Vec3::new(-1.0, -1.0, -1.0), Vec3::new(1.0, 1.0, 1.0)),
+            Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5))),
+        )
+    }
+
+    #[test]
+    fn hits_from_outside() {
+        let s = unit_sphere();
+        let r = Ray::new(point3::new(0.3, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let t = s.march(&r, 0.001, INFINITY).unwrap();
+        assert!((t - (3.0 - (1.0f64 - 0.09).sqrt())).abs() < 1e-3);
+        let r = Ray::new(point3::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        assert!(s.march(&r, 0.001, INFINITY).is_none());
+    }
+
+    #[test]
+    fn grazing_rays_leave_their_own_surface() {
+        let s = unit_sphere();
+        let on_surface = point3::new(0.0, 0.0, 1.0);
+        for &z in [0.0, 0.01, 0.05, 0.1].iter() {
+            let r = Ray::new(on_surface, Vec3::new(1.0, 0.0, z), 0.0);
+            assert!(s.march(&r, 0.001, INFINITY).is_none(), "z = {}", z);
+            assert!(!s.occluded(r, 0.001, INFINITY));
+        }
+        // Turning into the sphere still finds the way out on the far side.
+        let r = Ray::new(on_surface, Vec3::new(1.0, 0.0, -0.05), 0.0);
+        let t = s.march(&r, 0.001, INFINITY).unwrap();
+        assert!((t - 0.1).abs() < 0.01);
+    }
+}
@@ -0,0 +1,91 @@
+pub use crate::rtweekend::*;
+
+/// How the shutter lets light in over time. Times are in the same units as
+/// `MovingSphere` uses, measured from the start of the frame.
+#[derive(Clone, Copy)]
+pub struct Shutter {
+    /// Shutter starts opening.
+    pub open: f64,
+    /// Shutter is fully closed again.
+    pub close: f64,
+    /// Time it takes from closed to fully open.
+    pub ramp_open: f64,
+    /// Time it takes from fully open to closed.
+    pub ramp_close: f64,
+    /// Readout time from the top scanline to the bottom one, 0 for a global shutter.
+    pub rolling: f64,
+    /// Added to every sampled time, shifts the exposure against the frame.
+    pub offset: f64,
+}
+
+impl Shutter {
+    /// Ideal box shutter, every instant in `open..close` is equally likely.
+    pub fn new(open: f64, close: f64) -> Self {
+        Self::trapezoid(open, close, 0.0, 0.0)
+    }
+
+    pub fn trapezoid(open: f64, close: f64, ramp_open: f64, ramp_close: f64) -> Self {
+        let length = (close - open).max(0.0);
+        let ramp_open = clamp(ramp_open, 0.0, length);
+        let ramp_close = clamp(ramp_close, 0.0, length - ramp_open);
+        Self {
+            open,
+            close,
+            ramp_open,
+            ramp_close,
+            rolling: 0.0,
+            offset: 0.0,
+        }
+    }
+
+    pub fn set_rolling(&mut self, readout: f64) {
+        self.rolling = readout;
+    }
+
+    pub fn set_offset(&mut self, offset: f64) {
+        self.offset = offset;
+    }
+
+    /// Middle of the exposure of the centre scanline.
+    pub fn mid(&self) -> f64 {
+        (self.open + self.close) / 2.0 + self.rolling / 2.0 + self.offset
+    }
+
+    /// Relative transmission of the shutter at `time`, 1 when fully open.
+    pub fn curve(&self, time: f64) -> f64 {
+        if time < self.open || time > self.close {
+            0.0
+        } else if time < self.open + self.ramp_open {
+            (time - self.open) / self.ramp_open
+        } else if time > self.close - self.ramp_close {
+            (self.close - time) / self.ramp_close
+        } else {
+            1.0
+        }
+    }
+
+    /// Draws a time from the shutter curve for a ray through film height `t`
+    /// (0 at the bottom, 1 at the top, the top row is read out first).
+    pub fn sample(&self, t: f64) -> f64 {
+        self.sample_curve() + self.rolling * (1.0 - t) + self.offset
+    }
+
+    fn sample_curve(&self) -> f64 {
+        let flat = self.close - self.open - self.ramp_open - self.ramp_close;
+        let area_open = self.ramp_open / 2.0;
+        let area_close = self.ramp_close / 2.0;
+        let total = area_open + flat + area_close;
+        if total <= 0.0 {
+            return self.open;
+        }
+
+        let a = random_double1() * total;
+        if a < area_open {
+            self.open + (2.0 * self.ramp_open * a).sqrt()
+        } else if a < area_open + flat {
+            self.open + self.ramp_open + (a - area_open)
+        } else {
+            self.close - (2.0 * self.ramp_close * (total - a)).sqrt()
+        }
+    }
+}
@@ -0,0 +1,294 @@
+pub use crate::environment::*;
+pub use crate::vec3::Vec3;
+pub use Vec3 as color;
+
+/// Angular radius of the sun seen from the ground.
+const SUN_ANGULAR_RADIUS: f64 = 0.004_65;
+/// Irradiance of the sun outside the atmosphere, in the units `PhysicalSky` uses.
+const SUN_IRRADIANCE: f64 = 10.0;
+/// kcd/m^2 of the Preetham luminance to scene radiance.
+const SKY_SCALE: f64 = 0.1;
+
+/// Clear sky after Preetham, Shirley and Smits, "A Practical Analytic Model
+/// for Daylight", with a sun disk and a flat ground below the horizon.
+pub struct PhysicalSky {
+    pub sun_dir: Vec3,
+    pub turbidity: f64,
+    pub ground_albedo: color,
+    /// Radiance of the sun disk.
+    pub sun_radiance: color,
+    /// Radiance of the ground, lit by sun and sky.
+    pub ground_radiance: color,
+    cos_sun_max: f64,
+    theta_s: f64,
+    zenith: Vec3,
+    perez: [[f64; 5]; 3],
+}
+
+impl PhysicalSky {
+    /// `elevation` and `azimuth` in degrees, azimuth 0 is towards +z and 90 towards +x.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: color) -> Self {
+        let el = degrees_to_radians(elevation);
+        let az = degrees_to_radians(azimuth);
+        let sun_dir = Vec3::new(el.cos() * az.sin(), el.sin(), el.cos() * az.cos());
+        let t = turbidity;
+        let theta_s = PI / 2.0 - el.max(0.0);
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let big_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let th = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+        let dot = |a: [f64; 4]| a[0] * th[0] + a[1] * th[1] + a[2] * th[2] + a[3] * th[3];
+        let x = t * t * dot([0.00166, -0.00375, 0.00209, 0.0])
+            + t * dot([-0.02903, 0.06377, -0.03202, 0.00394])
+            + dot([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * dot([0.00275, -0.00610, 0.00317, 0.0])
+            + t * dot([-0.04214, 0.08970, -0.04153, 0.00516])
+            + dot([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        // Distribution coefficients in the order of `zenith`: x, y, Y.
+        let perez = [
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+        ];
+
+        let cos_sun_max = SUN_ANGULAR_RADIUS.cos();
+        let solid_angle = 2.0 * PI * (1.0 - cos_sun_max);
+        let sun_radiance = if elevation > 0.0 {
+            sun_transmittance(theta_s, t) * (SUN_IRRADIANCE / solid_angle)
+        } else {
+            color::zero()
+        };
+
+        let mut sky = Self {
+            sun_dir,
+            turbidity,
+            ground_albedo,
+            sun_radiance,
+            ground_radiance: color::zero(),
+            cos_sun_max,
+            theta_s,
+            zenith: Vec3::new(x, y, big_y),
+            perez,
+        };
+        let irradiance = sky.sky_irradiance() + sky.sun_radiance * (solid_angle * sun_dir.y);
+        sky.ground_radiance = Vec3::elemul(ground_albedo, irradiance) / PI;
+        sky
+    }
+
+    fn perez(&self, c: &[f64; 5], theta: f64, gamma: f64) -> f64 {
+        (1.0 + c[0] * (c[1] / theta.cos().max(0.01)).exp())
+            * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+    }
+
+    /// Sky radiance without the sun disk, for a direction above the horizon.
+    pub fn sky(&self, dir: &Vec3) -> color {
+        let theta = clamp(dir.y, 0.0, 1.0).acos();
+        let gamma = clamp(*dir * self.sun_dir, -1.0, 1.0).acos();
+        let mut xyy = [0.0; 3];
+        for (i, c) in self.perez.iter().enumerate() {
+            let zenith = [self.zenith.x, self.zenith.y, self.zenith.z][i];
+            xyy[i] = zenith * self.perez(c, theta, gamma) / self.perez(c, 0.0, self.theta_s);
+        }
+        xyy_to_rgb(xyy[0], xyy[1], xyy[2] * SKY_SCALE)
+    }
+
+    /// Irradiance the sky alone puts on a horizontal plane.
+    fn sky_irradiance(&self) -> color {
+        let n_theta = 16;
+        let n_phi = 32;
+        let d_theta = PI / 2.0 / n_theta as f64;
+        let d_phi = 2.0 * PI / n_phi as f64;
+        let mut sum = color::zero();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                sum += self.sky(&dir) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+        sum
+    }
+
+    fn sun_probability(&self) -> f64 {
+        if self.sun_radiance.y > 0.0 {
+            0.5
+        } else {
+            0.0
+        }
+    }
+}
+
+impl Environment for PhysicalSky {
+    fn value(&self, dir: &Vec3) -> color {
+        if dir.y < 0.0 {
+            return self.ground_radiance;
+        }
+        let mut c = self.sky(dir);
+        if *dir * self.sun_dir >= self.cos_sun_max {
+            c += self.sun_radiance;
+        }
+        c
+    }
+
+    fn is_light(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, dir: &Vec3) -> f64 {
+        let p_sun = self.sun_probability();
+        let mut pdf = (1.0 - p_sun) / (4.0 * PI);
+        if *dir * self.sun_dir >= self.cos_sun_max {
+            pdf += p_sun / (2.0 * PI * (1.0 - self.cos_sun_max));
+        }
+        pdf
+    }
+
+    fn random(&self) -> Vec3 {
+        if random_double1() >= self.sun_probability() {
+            return Vec3::random_unit_vector();
+        }
+        random_in_cone(&self.sun_dir, self.cos_sun_max)
+    }
+}
+
+/// Linear sRGB of a CIE xyY colour, clamped to be non-negative.
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> color {
+    if y <= 0.0 || big_y <= 0.0 {
+        return color::zero();
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+    color::new(
+        (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+    )
+}
+
+/// Sunlight left after Rayleigh and aerosol scattering along the air mass
+/// for sun zenith angle `theta_s`, at red, green and blue wavelengths.
+fn sun_transmittance(theta_s: f64, turbidity: f64) -> color {
+    let theta_deg = theta_s * 180.0 / PI;
+    let m = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_deg).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let t = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08) * m;
+        let aerosol = beta * lambda.powf(-1.3) * m;
+        (-(rayleigh + aerosol)).exp()
+    };
+    color::new(t(0.65), t(0.57), t(0.475))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn luminance(c: color) -> f64 {
+        0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+    }
+
+    #[test]
+    fn converts_xyy() {
+        // D65 white.
+        let white = xyy_to_rgb(0.3127, 0.3290, 1.0);
+        for c in [white.x, white.y, white.z].iter() {
+            assert!((c - 1.0).abs() < 2e-3, "{:?}", white);
+        }
+        assert_eq!(xyy_to_rgb(0.3, 0.0, 1.0).x, 0.0);
+    }
+
+    #[test]
+    fn zenith_is_the_zenith_colour() {
+        for &(elevation, turbidity) in [(10.0, 2.0), (45.0, 3.0), (70.0, 6.0)].iter() {
+            let sky = PhysicalSky::new(elevation, 30.0, turbidity, color::new(0.3, 0.3, 0.3));
+            let z = sky.zenith;
+            let expected = xyy_to_rgb(z.x, z.y, z.z * SKY_SCALE);
+            let got = sky.sky(&Vec3::new(0.0, 1.0, 0.0));
+            assert!((got - expected).length() < 1e-9 * expected.length());
+            // A clear sky is bluish overhead.
+            assert!(got.z > got.x);
+        }
+    }
+
+    #[test]
+    fn luminance_follows_its_own_distribution() {
+        let t = 3.0;
+        let sky = PhysicalSky::new(30.0, 120.0, t, color::new(0.3, 0.3, 0.3));
+        // Preetham's coefficients for Y, brightening strongly round the sun.
+        let c = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        for i in 0..8 {
+            for j in 0..16 {
+                let theta = (i as f64 + 0.5) / 8.0 * PI / 2.0;
+                let phi = j as f64 / 16.0 * 2.0 * PI;
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let gamma = (dir * sky.sun_dir).acos();
+                let expected = sky.zenith.z * SKY_SCALE * sky.perez(&c, theta, gamma)
+                    / sky.perez(&c, 0.0, sky.theta_s);
+                let got = luminance(sky.sky(&dir));
+                assert!(
+                    (got - expected).abs() < 1e-3 * expected,
+                    "{} {}",
+                    got,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn brightest_near_the_sun() {
+        let sky = PhysicalSky::new(30.0, 120.0, 3.0, color::new(0.3, 0.3, 0.3));
+        // A little way above the sun.
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let near = (sky.sun_dir + (up - sky.sun_dir * (up * sky.sun_dir)).unit() * 0.05).unit();
+        let near = luminance(sky.sky(&near));
+        for i in 0..16 {
+            for j in 0..64 {
+                let theta = (i as f64 + 0.5) / 16.0 * PI / 2.0;
+                let phi = j as f64 / 64.0 * 2.0 * PI;
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                if dir * sky.sun_dir < 0.9 {
+                    assert!(luminance(sky.sky(&dir)) < near);
+                }
+            }
+        }
+    }
+}
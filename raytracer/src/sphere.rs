@@ -37,38 +37,72 @@ impl Sphere {
         *u = phi / (2. * PI);
         *v = theta / PI;
     }
+
+    /// dp/du and dp/dv of the mapping of `get_sphere_uv` at unit normal `p`.
+    pub fn get_sphere_tangents(p: &point3, radius: f64) -> (Vec3, Vec3) {
+        let s = (p.x * p.x + p.z * p.z).sqrt().max(1e-12);
+        (
+            Vec3::new(-p.z, 0.0, p.x) * (2.0 * PI * radius),
+            Vec3::new(p.x * p.y / s, -s, p.z * p.y / s) * (PI * radius),
+        )
+    }
+
+    /// The nearest `t` in `t_min..t_max` where `r` meets the sphere at
+    /// `center`.
+    pub fn intersect(center: &point3, radius: f64, r: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let oc = r.orign() - *center;
+        let a = r.direction().squared_length();
+        let half_b = r.direction() * oc;
+        let c = oc.squared_length() - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if discriminant <= 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        let near = (-half_b - root) / a;
+        if near < t_max && near > t_min {
+            return Some(near);
+        }
+        let far = (-half_b + root) / a;
+        if far < t_max && far > t_min {
+            return Some(far);
+        }
+        None
+    }
+
+    /// Fills `rec` for the hit at `t` on the sphere at `center`.
+    pub fn set_hit(
+        center: &point3,
+        radius: f64,
+        mat: &Arc<dyn Material>,
+        r: &Ray,
+        t: f64,
+        rec: &mut HitRecord,
+    ) {
+        rec.t = t;
+        rec.p = r.at(t);
+        let outward_normal = (rec.p - *center) / radius;
+        rec.set_face_normal(r, &outward_normal);
+        Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        let (dpdu, dpdv) = Sphere::get_sphere_tangents(&outward_normal, radius);
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        rec.mat_ptr = mat.clone();
+    }
 }
 impl Hittable for Sphere {
     fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
-        let oc: Vec3 = r.orign() - self.center;
-        let a: f64 = Vec3::squared_length(&r.direction());
-        let half_b: f64 = r.direction() * oc;
-        let c: f64 = Vec3::squared_length(&oc) - self.radius * self.radius;
-        let discriminant = half_b.powi(2) - a * c;
-        if discriminant > 0.0 {
-            let root: f64 = discriminant.sqrt();
-            let t = (-half_b - root) / a;
-            if t < t_max && t > t_min {
-                rec.t = t; //??????????
-                rec.p = Ray::at(&r, t);
-                let outward_normal: Vec3 = (rec.p - self.center()) / self.radius();
-                rec.set_face_normal(&r, &outward_normal);
-                Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
-                rec.mat_ptr = self.mat_ptr.clone();
-                return true;
-            }
-            let t = (-half_b + root) / a;
-            if t < t_max && t > t_min {
-                rec.t = t;
-                rec.p = Ray::at(&r, t);
-                let outward_normal: Vec3 = (rec.p - self.center()) / self.radius();
-                rec.set_face_normal(&r, &outward_normal);
-                Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
-                rec.mat_ptr = self.mat_ptr.clone();
-                return true;
+        match Sphere::intersect(&self.center, self.radius, &r, t_min, t_max) {
+            Some(t) => {
+                Sphere::set_hit(&self.center, self.radius, &self.mat_ptr, &r, t, rec);
+                true
             }
+            None => false,
         }
-        false
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        Sphere::intersect(&self.center, self.radius, &r, t_min, t_max).is_some()
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
@@ -0,0 +1,221 @@
+use crate::triangle::*;
+use crate::vec3::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Loads a triangle mesh from an ASCII or binary STL file. STL stores every
+/// triangle on its own, so corners at the same position are merged into
+/// one vertex. Facet normals are ignored, the triangle's own winding gives
+/// the geometric normal.
+pub fn load_stl(path: &str) -> io::Result<TriangleMesh> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut input = BufReader::new(file);
+
+    let mut header = [0u8; 84];
+    // A binary file is 84 header bytes and 50 bytes per facet.
+    let (is_binary, count, binary_size) = match input.read_exact(&mut header) {
+        Ok(()) => {
+            let count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]);
+            let binary_size = 84 + 50 * count as u64;
+            let is_binary = size == binary_size || !header.starts_with(b"solid");
+            (is_binary, count as usize, binary_size)
+        }
+        Err(_) => (false, 0, 0),
+    };
+
+    let mut mesh = TriangleMesh::new();
+    let mut welder = Welder::default();
+    if is_binary {
+        if binary_size > size {
+            return Err(invalid(&format!(
+                "{}: {} facets do not fit in {} bytes",
+                path, count, size
+            )));
+        }
+        let corners = count
+            .checked_mul(3)
+            .ok_or_else(|| invalid(&format!("{}: too many facets", path)))?;
+        mesh.position_indices.reserve(corners);
+        let mut facet = [0u8; 50];
+        for _i in 0..count {
+            input.read_exact(&mut facet)?;
+            // Skip the 12 byte normal, the 2 byte attribute follows the corners.
+            for k in 0..3 {
+                let at = |j: usize| {
+                    let o = 12 + 12 * k + 4 * j;
+                    f32::from_le_bytes([facet[o], facet[o + 1], facet[o + 2], facet[o + 3]])
+                };
+                let i = welder.index(&mut mesh, [at(0), at(1), at(2)]);
+                mesh.position_indices.push(i);
+            }
+        }
+    } else {
+        // Start over, the header bytes are part of the text.
+        let mut input = BufReader::new(File::open(path)?);
+        let mut line = String::new();
+        let mut line_no = 0;
+        // Vertices of the open facet, `None` between facets.
+        let mut facet: Option<usize> = None;
+        loop {
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            line_no += 1;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let bad = |what: &str| invalid(&format!("{}:{}: {}", path, line_no, what));
+            match tokens.first() {
+                Some(&"facet") => {
+                    if facet.is_some() {
+                        return Err(bad("`facet` before `endfacet`"));
+                    }
+                    facet = Some(0);
+                }
+                Some(&"endfacet") => match facet.take() {
+                    Some(3) => {}
+                    Some(_) => return Err(bad("facet with other than 3 vertices")),
+                    None => return Err(bad("`endfacet` without `facet`")),
+                },
+                Some(&"vertex") => {
+                    let count = match facet.as_mut() {
+                        Some(count) => count,
+                        None => return Err(bad("`vertex` outside a facet")),
+                    };
+                    *count += 1;
+                    let v: Option<Vec<f32>> = tokens[1..].iter().map(|t| t.parse().ok()).collect();
+                    match v {
+                        Some(v) if v.len() == 3 => {
+                            let i = welder.index(&mut mesh, [v[0], v[1], v[2]]);
+                            mesh.position_indices.push(i);
+                        }
+                        _ => return Err(bad("bad `vertex` line")),
+                    }
+                }
+                _ => {}
+            }
+        }
+        if facet.is_some() {
+            return Err(invalid(&format!("{}: last facet has no `endfacet`", path)));
+        }
+    }
+
+    if mesh.face_count() == 0 {
+        return Err(invalid(&format!("{}: no facets", path)));
+    }
+    mesh.positions.shrink_to_fit();
+    Ok(mesh)
+}
+
+/// Gives corners with bit-identical coordinates the same vertex index.
+#[derive(Default)]
+struct Welder {
+    seen: HashMap<[u32; 3], u32>,
+}
+
+impl Welder {
+    fn index(&mut self, mesh: &mut TriangleMesh, p: [f32; 3]) -> u32 {
+        // +0.0 and -0.0 are the same point.
+        let key = [
+            (p[0] + 0.0).to_bits(),
+            (p[1] + 0.0).to_bits(),
+            (p[2] + 0.0).to_bits(),
+        ];
+        let positions = &mut mesh.positions;
+        *self.seen.entry(key).or_insert_with(|| {
+            positions.push(Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64));
+            (positions.len() - 1) as u32
+        })
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Two triangles of a unit quad, sharing the diagonal.
+    const FACETS: [[[f32; 3]; 3]; 2] = [
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+        [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+    ];
+
+    fn ascii() -> Vec<u8> {
+        let mut text = String::from("solid quad\n");
+        for f in FACETS.iter() {
+            text += "  facet normal 0 0 1\n    outer loop\n";
+            for v in f.iter() {
+                text += &format!("      vertex {} {} {}\n", v[0], v[1], v[2]);
+            }
+            text += "    endloop\n  endfacet\n";
+        }
+        text += "endsolid quad\n";
+        text.into_bytes()
+    }
+
+    fn binary(header: &[u8], count: u32) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80];
+        bytes[..header.len()].copy_from_slice(header);
+        bytes.extend_from_slice(&count.to_le_bytes());
+        for f in FACETS.iter() {
+            for &x in [0.0f32, 0.0, 1.0].iter().chain(f.iter().flatten()) {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    fn load(name: &str, bytes: &[u8]) -> io::Result<TriangleMesh> {
+        let path: PathBuf =
+            std::env::temp_dir().join(format!("raytracer-{}-{}.stl", name, std::process::id()));
+        fs::write(&path, bytes).unwrap();
+        let mesh = load_stl(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        mesh
+    }
+
+    fn check_quad(mesh: &TriangleMesh) {
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.position_indices, vec![0, 1, 2, 0, 2, 3]);
+        for (face, f) in FACETS.iter().enumerate() {
+            let v = mesh.vertices(face);
+            for k in 0..3 {
+                let p = f[k];
+                assert_eq!(v[k], Vec3::new(p[0] as f64, p[1] as f64, p[2] as f64));
+            }
+        }
+    }
+
+    #[test]
+    fn reads_both_formats() {
+        check_quad(&load("ascii", &ascii()).unwrap());
+        check_quad(&load("binary", &binary(b"quad", 2)).unwrap());
+        // Some exporters start binary headers with `solid` too.
+        check_quad(&load("binary-solid", &binary(b"solid quad", 2)).unwrap());
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let text = ascii();
+        let cut = String::from_utf8(text.clone())
+            .unwrap()
+            .find("endfacet")
+            .unwrap();
+        assert!(load("short-ascii", &text[..cut]).is_err());
+        let bytes = binary(b"quad", 2);
+        assert!(load("short-binary", &bytes[..bytes.len() - 10]).is_err());
+    }
+
+    #[test]
+    fn rejects_counts_larger_than_the_file() {
+        assert!(load("lying", &binary(b"quad", 3)).is_err());
+        assert!(load("lying-max", &binary(b"quad", u32::MAX)).is_err());
+    }
+}
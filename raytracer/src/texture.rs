@@ -1,9 +1,16 @@
+use crate::hittable::HitRecord;
 use crate::perlin::*;
 use image::ImageBuffer;
+use std::io;
 pub use std::path::*;
 use std::sync::Arc;
 pub trait Texture: Sync + Send {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
+
+    /// Value at a hit, for textures that need more of it than (u, v, p).
+    fn value_at(&self, rec: &HitRecord) -> Vec3 {
+        self.value(rec.u, rec.v, &rec.p)
+    }
 }
 
 pub struct Solid {
@@ -104,9 +111,19 @@ impl ImageTexture {
     }*/
 
     pub fn new(filename: &str) -> Self {
+        Self::from_image(image::open(filename).unwrap().to_rgb8())
+    }
+
+    /// Like `new`, but a missing or unreadable file is an error.
+    pub fn open(filename: &str) -> io::Result<Self> {
+        let img = image::open(filename).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", filename, e))
+        })?;
+        Ok(Self::from_image(img.to_rgb8()))
+    }
+
+    pub fn from_image(data2: ImageBuffer<image::Rgb<u8>, std::vec::Vec<u8>>) -> Self {
         let bytes_per_pixel: i64 = 3;
-        //let components_per_pixel = bytes_per_pixel;
-        let data2 = image::open(filename).unwrap().to_rgb8();
         let width2 = data2.width();
         let height2 = data2.height();
 
@@ -119,6 +136,21 @@ impl ImageTexture {
             bytes_per_scanline,
         }
     }
+
+    /// Average colour over all pixels, in [0, 1].
+    pub fn mean(&self) -> color {
+        if self.data.is_empty() {
+            return color::zero();
+        }
+        let mut sum = [0u64; 3];
+        for pixel in self.data.pixels() {
+            for (s, c) in sum.iter_mut().zip(pixel.0.iter()) {
+                *s += *c as u64;
+            }
+        }
+        let scale = 1.0 / (255.0 * self.data.pixels().len() as f64);
+        color::new(sum[0] as f64, sum[1] as f64, sum[2] as f64) * scale
+    }
 }
 impl Texture for ImageTexture {
     fn value(&self, u: f64, v: f64, _p: &point3) -> point3 {
@@ -193,3 +225,54 @@ impl Texture for ImageTexture {
     }
 }
 */
+
+/// `texture` multiplied by a constant colour.
+pub struct TintTexture {
+    pub texture: Arc<dyn Texture>,
+    pub tint: color,
+}
+
+impl TintTexture {
+    pub fn new(texture: Arc<dyn Texture>, tint: color) -> Self {
+        Self { texture, tint }
+    }
+}
+
+impl Texture for TintTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        Vec3::elemul(self.texture.value(u, v, p), self.tint)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vec3 {
+        Vec3::elemul(self.texture.value_at(rec), self.tint)
+    }
+}
+
+/// The colour stored on the vertices of a mesh, `fallback` where the hit
+/// object has none.
+pub struct VertexColorTexture {
+    pub fallback: Arc<dyn Texture>,
+}
+
+impl VertexColorTexture {
+    pub fn new(fallback: Arc<dyn Texture>) -> Self {
+        Self { fallback }
+    }
+
+    pub fn new_by_color(c: color) -> Self {
+        Self::new(Arc::new(Solid::new(c)))
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.fallback.value(u, v, p)
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Vec3 {
+        match rec.vertex_color {
+            Some(c) => c,
+            None => self.value(rec.u, rec.v, &rec.p),
+        }
+    }
+}
@@ -0,0 +1,338 @@
+pub use crate::hittable::*;
+pub use crate::ray::*;
+pub use crate::vec3::*;
+use std::ops::Mul;
+use std::sync::Arc;
+
+/// Row-major 4x4 matrix acting on column vectors, `m[row][col]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    /// Gauss-Jordan with partial pivoting, `None` when singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inv = Self::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col].abs() < 1e-14 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let d = a[col][col];
+            for j in 0..4 {
+                a[col][j] /= d;
+                inv[col][j] /= d;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for j in 0..4 {
+                        a[row][j] -= f * a[col][j];
+                        inv[row][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        Some(Self { m: inv })
+    }
+
+    /// Determinant of the upper 3x3, negative when handedness flips.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+/// An affine transform with its inverse. `a * b` applies `b` first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub m: Matrix4,
+    pub m_inv: Matrix4,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Self {
+            m: Matrix4::identity(),
+            m_inv: Matrix4::identity(),
+        }
+    }
+
+    /// `None` for a singular matrix.
+    pub fn from_matrix(m: Matrix4) -> Option<Self> {
+        m.inverse().map(|m_inv| Self { m, m_inv })
+    }
+
+    /// Sixteen numbers column by column, as glTF and OpenGL store them.
+    pub fn from_column_major(c: &[f64]) -> Option<Self> {
+        if c.len() != 16 {
+            return None;
+        }
+        let mut m = [[0.0; 4]; 4];
+        for (col, v) in c.chunks(4).enumerate() {
+            for (row, x) in v.iter().enumerate() {
+                m[row][col] = *x;
+            }
+        }
+        Self::from_matrix(Matrix4::new(m))
+    }
+
+    pub fn inverse(&self) -> Self {
+        Self {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+
+    pub fn translate(delta: Vec3) -> Self {
+        let mut m = Matrix4::identity();
+        let mut m_inv = Matrix4::identity();
+        for i in 0..3 {
+            m.m[i][3] = delta.get(i as i32);
+            m_inv.m[i][3] = -delta.get(i as i32);
+        }
+        Self { m, m_inv }
+    }
+
+    /// Non-uniform scale, no factor may be zero.
+    pub fn scale(s: Vec3) -> Self {
+        let mut m = Matrix4::identity();
+        let mut m_inv = Matrix4::identity();
+        for i in 0..3 {
+            m.m[i][i] = s.get(i as i32);
+            m_inv.m[i][i] = 1.0 / s.get(i as i32);
+        }
+        Self { m, m_inv }
+    }
+
+    /// `degrees` counter-clockwise about `axis`, looking down the axis.
+    pub fn rotate(degrees: f64, axis: Vec3) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        let mut m = Matrix4::identity();
+        m.m[0][0] = a.x * a.x + (1.0 - a.x * a.x) * cos;
+        m.m[0][1] = a.x * a.y * (1.0 - cos) - a.z * sin;
+        m.m[0][2] = a.x * a.z * (1.0 - cos) + a.y * sin;
+        m.m[1][0] = a.x * a.y * (1.0 - cos) + a.z * sin;
+        m.m[1][1] = a.y * a.y + (1.0 - a.y * a.y) * cos;
+        m.m[1][2] = a.y * a.z * (1.0 - cos) - a.x * sin;
+        m.m[2][0] = a.x * a.z * (1.0 - cos) - a.y * sin;
+        m.m[2][1] = a.y * a.z * (1.0 - cos) + a.x * sin;
+        m.m[2][2] = a.z * a.z + (1.0 - a.z * a.z) * cos;
+        // Rotations are orthogonal.
+        Self {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Self::rotate(degrees, Vec3::new(1.0, 0.0, 0.0))
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Self::rotate(degrees, Vec3::new(0.0, 1.0, 0.0))
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Self::rotate(degrees, Vec3::new(0.0, 0.0, 1.0))
+    }
+
+    /// Rotation by the unit quaternion `x i + y j + z k + w`.
+    pub fn quaternion(x: f64, y: f64, z: f64, w: f64) -> Self {
+        let n = (x * x + y * y + z * z + w * w).sqrt();
+        let (x, y, z, w) = (x / n, y / n, z / n, w / n);
+        let m = Matrix4::new([
+            [
+                1.0 - 2.0 * (y * y + z * z),
+                2.0 * (x * y - z * w),
+                2.0 * (x * z + y * w),
+                0.0,
+            ],
+            [
+                2.0 * (x * y + z * w),
+                1.0 - 2.0 * (x * x + z * z),
+                2.0 * (y * z - x * w),
+                0.0,
+            ],
+            [
+                2.0 * (x * z - y * w),
+                2.0 * (y * z + x * w),
+                1.0 - 2.0 * (x * x + y * y),
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Self {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// Moves `x` by `amount` times `y`, `x` and `y` being axes 0 to 2.
+    pub fn shear(x: usize, y: usize, amount: f64) -> Self {
+        let mut m = Matrix4::identity();
+        let mut m_inv = Matrix4::identity();
+        m.m[x][y] = amount;
+        m_inv.m[x][y] = -amount;
+        Self { m, m_inv }
+    }
+
+    /// Scale, then rotate, then translate.
+    pub fn trs(translation: Vec3, rotation: Transform, scale: Vec3) -> Self {
+        Self::translate(translation) * rotation * Self::scale(scale)
+    }
+
+    pub fn point(&self, p: &point3) -> point3 {
+        let m = &self.m.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    pub fn vector(&self, v: &Vec3) -> Vec3 {
+        let m = &self.m.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+
+    /// By the inverse transpose, so normals stay perpendicular to the
+    /// surface under scale and shear. The result is not normalized.
+    pub fn normal(&self, n: &Vec3) -> Vec3 {
+        let m = &self.m_inv.m;
+        Vec3::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+
+    /// Keeps the direction unnormalized so `t` means the same on both sides.
+    pub fn ray(&self, r: &Ray) -> Ray {
+        Ray::new(self.point(&r.orig), self.vector(&r.dir), r.tm)
+    }
+
+    /// The tightest box around the transformed box, after Arvo.
+    pub fn bounding_box(&self, b: &AABB) -> AABB {
+        let m = &self.m.m;
+        let mut min = [m[0][3], m[1][3], m[2][3]];
+        let mut max = min;
+        for (i, row) in m.iter().take(3).enumerate() {
+            for (j, x) in row.iter().take(3).enumerate() {
+                let a = x * b.min().get(j as i32);
+                let c = x * b.max().get(j as i32);
+                min[i] += a.min(c);
+                max[i] += a.max(c);
+            }
+        }
+        AABB::new(
+            point3::new(min[0], min[1], min[2]),
+            point3::new(max[0], max[1], max[2]),
+        )
+    }
+
+    pub fn swaps_handedness(&self) -> bool {
+        self.m.determinant3() < 0.0
+    }
+}
+
+impl Mul for Transform {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self {
+            m: self.m * other.m,
+            m_inv: other.m_inv * self.m_inv,
+        }
+    }
+}
+
+/// `ptr` moved into place by any affine transform.
+pub struct Transformed {
+    pub ptr: Arc<dyn Hittable>,
+    pub transform: Transform,
+}
+
+impl Transformed {
+    pub fn new(p: Arc<dyn Hittable>, transform: Transform) -> Self {
+        Self { ptr: p, transform }
+    }
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let local = self.transform.inverse().ray(&r);
+        if !self.ptr.hit(local, t_min, t_max, rec) {
+            return false;
+        }
+        // Dot products with the ray direction keep their sign, so
+        // `front_face` still holds.
+        let t = &self.transform;
+        rec.p = t.point(&rec.p);
+        rec.normal = t.normal(&rec.normal).unit();
+        rec.geometric_normal = t.normal(&rec.geometric_normal).unit();
+        rec.dpdu = t.vector(&rec.dpdu);
+        rec.dpdv = t.vector(&rec.dpdv);
+        true
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.ptr
+            .occluded(self.transform.inverse().ray(&r), t_min, t_max)
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        if !self.ptr.bounding_box(time0, time1, output_box) {
+            return false;
+        }
+        *output_box = self.transform.bounding_box(output_box);
+        true
+    }
+}
@@ -0,0 +1,245 @@
+use crate::hittable::*;
+use crate::linear_bvh::*;
+use crate::material::*;
+use crate::ray::*;
+use std::sync::Arc;
+
+/// Index meaning "this corner has no normal / uv".
+pub const NO_INDEX: u32 = u32::MAX;
+
+/// Vertex and index buffers shared by all triangles of a mesh. Every face
+/// has three entries in each non-empty index buffer.
+#[derive(Default)]
+pub struct TriangleMesh {
+    pub positions: Vec<point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f64; 2]>,
+    /// One colour per position, or empty.
+    pub colors: Vec<color>,
+    pub position_indices: Vec<u32>,
+    pub normal_indices: Vec<u32>,
+    pub uv_indices: Vec<u32>,
+}
+
+impl TriangleMesh {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn face_count(&self) -> usize {
+        self.position_indices.len() / 3
+    }
+
+    pub fn vertices(&self, face: usize) -> [point3; 3] {
+        let i = &self.position_indices[3 * face..3 * face + 3];
+        [
+            self.positions[i[0] as usize],
+            self.positions[i[1] as usize],
+            self.positions[i[2] as usize],
+        ]
+    }
+
+    pub fn face_uvs(&self, face: usize) -> Option<[[f64; 2]; 3]> {
+        if self.uv_indices.is_empty() {
+            return None;
+        }
+        let i = &self.uv_indices[3 * face..3 * face + 3];
+        if i.contains(&NO_INDEX) {
+            return None;
+        }
+        Some([
+            self.uvs[i[0] as usize],
+            self.uvs[i[1] as usize],
+            self.uvs[i[2] as usize],
+        ])
+    }
+
+    pub fn face_colors(&self, face: usize) -> Option<[color; 3]> {
+        if self.colors.is_empty() {
+            return None;
+        }
+        let i = &self.position_indices[3 * face..3 * face + 3];
+        Some([
+            self.colors[i[0] as usize],
+            self.colors[i[1] as usize],
+            self.colors[i[2] as usize],
+        ])
+    }
+
+    pub fn face_normals(&self, face: usize) -> Option<[Vec3; 3]> {
+        if self.normal_indices.is_empty() {
+            return None;
+        }
+        let i = &self.normal_indices[3 * face..3 * face + 3];
+        if i.contains(&NO_INDEX) {
+            return None;
+        }
+        let n = [
+            self.normals[i[0] as usize],
+            self.normals[i[1] as usize],
+            self.normals[i[2] as usize],
+        ];
+        // A zero normal says nothing about the surface.
+        if n.iter().any(|n| n.squared_length() == 0.0) {
+            return None;
+        }
+        Some(n)
+    }
+
+    /// One `Triangle` per face, all with material `mat`.
+    pub fn triangles(mesh: &Arc<TriangleMesh>, mat: Arc<dyn Material>) -> HittableList {
+        let mut list = HittableList::new();
+        list.objects.reserve(mesh.face_count());
+        for face in 0..mesh.face_count() {
+            list.add(Arc::new(Triangle::new(mesh.clone(), face, mat.clone())));
+        }
+        list
+    }
+
+    pub fn to_bvh(
+        mesh: &Arc<TriangleMesh>,
+        mat: Arc<dyn Material>,
+        time0: f64,
+        time1: f64,
+    ) -> LinearBvh {
+        LinearBvh::from(&mut Self::triangles(mesh, mat), time0, time1)
+    }
+}
+
+pub struct Triangle {
+    pub mesh: Arc<TriangleMesh>,
+    pub face: usize,
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(mesh: Arc<TriangleMesh>, face: usize, mat: Arc<dyn Material>) -> Self {
+        Self {
+            mesh,
+            face,
+            mat_ptr: mat,
+        }
+    }
+
+    /// `t` and the barycentric coordinates of the second and third
+    /// corners where `r` crosses the face in `t_min..t_max`, by
+    /// Möller–Trumbore.
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let [p0, p1, p2] = self.mesh.vertices(self.face);
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let pvec = Vec3::cross(r.dir, e2);
+        let det = e1 * pvec;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = r.orig - p0;
+        let b1 = (tvec * pvec) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let qvec = Vec3::cross(tvec, e1);
+        let b2 = (r.dir * qvec) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = (e2 * qvec) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        Some((t, b1, b2))
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let (t, b1, b2) = match self.intersect(&r, t_min, t_max) {
+            Some(h) => h,
+            None => return false,
+        };
+        let [p0, p1, p2] = self.mesh.vertices(self.face);
+        let e1 = p1 - p0;
+        let e2 = p2 - p0;
+        let b0 = 1.0 - b1 - b2;
+        rec.t = t;
+        rec.p = r.at(t);
+        let uvs = self.mesh.face_uvs(self.face);
+        match uvs {
+            Some(uv) => {
+                rec.u = b0 * uv[0][0] + b1 * uv[1][0] + b2 * uv[2][0];
+                rec.v = b0 * uv[0][1] + b1 * uv[1][1] + b2 * uv[2][1];
+            }
+            None => {
+                rec.u = b1;
+                rec.v = b2;
+            }
+        }
+
+        // The winding may disagree with the vertex normals, trust the normals.
+        let shading_normal = self
+            .mesh
+            .face_normals(self.face)
+            .map(|n| n[0] * b0 + n[1] * b1 + n[2] * b2)
+            .filter(|n| n.squared_length() > 0.0);
+        let mut outward_normal = Vec3::cross(e1, e2).unit();
+        if let Some(n) = shading_normal {
+            if n * outward_normal < 0.0 {
+                outward_normal = -outward_normal;
+            }
+        }
+        rec.set_face_normal(&r, &outward_normal);
+        if let Some(n) = shading_normal {
+            rec.set_shading_normal(&n);
+        }
+
+        let (dpdu, dpdv) = match uvs {
+            Some(uv) => {
+                let duv02 = [uv[0][0] - uv[2][0], uv[0][1] - uv[2][1]];
+                let duv12 = [uv[1][0] - uv[2][0], uv[1][1] - uv[2][1]];
+                let det = duv02[0] * duv12[1] - duv02[1] * duv12[0];
+                if det.abs() < 1e-12 {
+                    (e1, e2)
+                } else {
+                    let dp02 = p0 - p2;
+                    let dp12 = p1 - p2;
+                    (
+                        (dp02 * duv12[1] - dp12 * duv02[1]) / det,
+                        (dp12 * duv02[0] - dp02 * duv12[0]) / det,
+                    )
+                }
+            }
+            None => (e1, e2),
+        };
+        rec.dpdu = dpdu;
+        rec.dpdv = dpdv;
+        rec.vertex_color = self
+            .mesh
+            .face_colors(self.face)
+            .map(|c| c[0] * b0 + c[1] * b1 + c[2] * b2);
+        rec.mat_ptr = self.mat_ptr.clone();
+        true
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(&r, t_min, t_max).is_some()
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let [p0, p1, p2] = self.mesh.vertices(self.face);
+        let pad = 0.0001;
+        *output_box = AABB::new(
+            point3::new(
+                p0.x.min(p1.x).min(p2.x) - pad,
+                p0.y.min(p1.y).min(p2.y) - pad,
+                p0.z.min(p1.z).min(p2.z) - pad,
+            ),
+            point3::new(
+                p0.x.max(p1.x).max(p2.x) + pad,
+                p0.y.max(p1.y).max(p2.y) + pad,
+                p0.z.max(p1.z).max(p2.z) + pad,
+            ),
+        );
+        true
+    }
+}
@@ -0,0 +1,455 @@
+use crate::bvh::*;
+use crate::hittable::*;
+use crate::linear_bvh::*;
+use crate::ray::*;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// Stack entries the traversal holds without allocating.
+const STACK_SIZE: usize = 128;
+/// Widens the far side of every slab by a few `f32` roundings, so a ray
+/// that grazes a box is not lost to the lower precision. The error from
+/// rounding the origin is absolute, not relative, and `LaneRay::pad`
+/// covers it.
+const FAR_SLOP: f32 = 1.0 + 4.0 * f32::EPSILON;
+/// Marks an unused child slot.
+const EMPTY: u32 = u32::MAX;
+
+pub type Bvh4 = WideBvh<4>;
+pub type Bvh8 = WideBvh<8>;
+
+/// One node of a `WideBvh`: up to `N` children with their boxes stored as
+/// structure of arrays, so one child's bounds sit in the same lane of each
+/// array and all `N` boxes are tested together.
+#[derive(Clone, Copy, Debug)]
+pub struct WideBvhNode<const N: usize> {
+    /// `min.x`, `min.y`, `min.z`, `max.x`, `max.y` and `max.z` of each
+    /// child.
+    pub bounds: [[f32; N]; 6],
+    /// A node index, or the first primitive of a leaf child.
+    pub child: [u32; N],
+    /// Primitives in a leaf child; zero for nodes and empty slots.
+    pub count: [u8; N],
+}
+
+impl<const N: usize> WideBvhNode<N> {
+    fn empty() -> Self {
+        // Inverted boxes that no ray can hit.
+        Self {
+            bounds: [
+                [f32::INFINITY; N],
+                [f32::INFINITY; N],
+                [f32::INFINITY; N],
+                [f32::NEG_INFINITY; N],
+                [f32::NEG_INFINITY; N],
+                [f32::NEG_INFINITY; N],
+            ],
+            child: [EMPTY; N],
+            count: [0; N],
+        }
+    }
+
+    fn set_bounds(&mut self, lane: usize, b: &AABB) {
+        let (lo, hi) = (b.min(), b.max());
+        let corners = [lo.x, lo.y, lo.z];
+        for (axis, x) in corners.iter().enumerate() {
+            self.bounds[axis][lane] = round_down(*x);
+        }
+        let corners = [hi.x, hi.y, hi.z];
+        for (axis, x) in corners.iter().enumerate() {
+            self.bounds[axis + 3][lane] = round_up(*x);
+        }
+    }
+
+    /// Tests the ray against every child box. Returns a mask of the
+    /// children hit, with the distance into each in `t_near`.
+    fn intersect(&self, ray: &LaneRay, t_min: f32, t_max: f32, t_near: &mut [f32; N]) -> u32 {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if N & 3 == 0 {
+                return self.intersect_sse(ray, t_min, t_max, t_near);
+            }
+        }
+        self.intersect_scalar(ray, t_min, t_max, t_near)
+    }
+
+    fn intersect_scalar(
+        &self,
+        ray: &LaneRay,
+        t_min: f32,
+        t_max: f32,
+        t_near: &mut [f32; N],
+    ) -> u32 {
+        let mut mask = 0;
+        for (lane, t) in t_near.iter_mut().enumerate() {
+            let (mut t0, mut t1) = (t_min, t_max);
+            for a in 0..3 {
+                let near =
+                    (self.bounds[ray.near[a]][lane] - ray.orig[a]) * ray.inv_dir[a] - ray.pad[a];
+                let far = ((self.bounds[ray.far[a]][lane] - ray.orig[a]) * ray.inv_dir[a]
+                    + ray.pad[a])
+                    * FAR_SLOP;
+                // `max` and `min` skip the NaN from a ray in a slab's plane.
+                t0 = near.max(t0);
+                t1 = far.min(t1);
+            }
+            *t = t0;
+            if t0 <= t1 {
+                mask |= 1 << lane;
+            }
+        }
+        mask
+    }
+
+    /// Four lanes at a time with SSE, which every x86-64 processor has.
+    #[cfg(target_arch = "x86_64")]
+    fn intersect_sse(&self, ray: &LaneRay, t_min: f32, t_max: f32, t_near: &mut [f32; N]) -> u32 {
+        use std::arch::x86_64::*;
+        let mut mask = 0;
+        // Safety: SSE is in the x86-64 baseline, and every load and store
+        // covers four lanes of an array whose length is a multiple of four.
+        unsafe {
+            let slop = _mm_set1_ps(FAR_SLOP);
+            for base in (0..N).step_by(4) {
+                let mut t0 = _mm_set1_ps(t_min);
+                let mut t1 = _mm_set1_ps(t_max);
+                for a in 0..3 {
+                    let orig = _mm_set1_ps(ray.orig[a]);
+                    let inv_dir = _mm_set1_ps(ray.inv_dir[a]);
+                    let pad = _mm_set1_ps(ray.pad[a]);
+                    let near = _mm_loadu_ps(self.bounds[ray.near[a]][base..].as_ptr());
+                    let far = _mm_loadu_ps(self.bounds[ray.far[a]][base..].as_ptr());
+                    let near = _mm_sub_ps(_mm_mul_ps(_mm_sub_ps(near, orig), inv_dir), pad);
+                    let far = _mm_mul_ps(_mm_sub_ps(far, orig), inv_dir);
+                    let far = _mm_mul_ps(_mm_add_ps(far, pad), slop);
+                    // These return the second operand when either is NaN.
+                    t0 = _mm_max_ps(near, t0);
+                    t1 = _mm_min_ps(far, t1);
+                }
+                _mm_storeu_ps(t_near[base..].as_mut_ptr(), t0);
+                mask |= (_mm_movemask_ps(_mm_cmple_ps(t0, t1)) as u32) << base;
+            }
+        }
+        mask
+    }
+}
+
+/// The ray in `f32`, with the planes each slab is entered and left by.
+struct LaneRay {
+    orig: [f32; 3],
+    inv_dir: [f32; 3],
+    /// How far along the ray rounding the origin can move a plane, taken
+    /// off `near` and added to `far`.
+    pad: [f32; 3],
+    /// Rows of `WideBvhNode::bounds` holding the near and far planes.
+    near: [usize; 3],
+    far: [usize; 3],
+}
+
+impl LaneRay {
+    fn new(r: &Ray) -> Self {
+        let (o, d) = (r.orign(), r.direction());
+        let inv_dir = [(1.0 / d.x) as f32, (1.0 / d.y) as f32, (1.0 / d.z) as f32];
+        let mut near = [0, 1, 2];
+        let mut far = [3, 4, 5];
+        for a in 0..3 {
+            if inv_dir[a] < 0.0 {
+                std::mem::swap(&mut near[a], &mut far[a]);
+            }
+        }
+        let orig = [o.x as f32, o.y as f32, o.z as f32];
+        let mut pad = [0.0; 3];
+        for (a, (x, d)) in [o.x, o.y, o.z].iter().zip(&[d.x, d.y, d.z]).enumerate() {
+            let error = (orig[a] as f64 - x).abs();
+            if error > 0.0 {
+                pad[a] = round_up(error / d.abs());
+            }
+        }
+        Self {
+            orig,
+            inv_dir,
+            pad,
+            near,
+            far,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct StackEntry {
+    child: u32,
+    count: u8,
+    t: f32,
+}
+
+/// A BVH with up to `N` children per node, collapsed from the binary
+/// `BvhNode` by pulling up grandchildren until each node is full. The
+/// tree is about `log2(N)` times shallower, and the boxes of a node are
+/// tested together, with SSE on x86-64 when `N` is a multiple of four and
+/// a plain loop elsewhere. It has only been timed on one core, where
+/// final_scene rendered within run-to-run noise of a `LinearBvh`, so the
+/// scenes keep using `LinearBvh` until it shows a speedup.
+pub struct WideBvh<const N: usize> {
+    pub nodes: Vec<WideBvhNode<N>>,
+    pub primitives: Vec<Arc<dyn Hittable>>,
+    pub depth: usize,
+    bbox: Option<AABB>,
+}
+
+impl<const N: usize> WideBvh<N> {
+    pub fn from(list: &mut HittableList, time0: f64, time1: f64) -> Self {
+        Self::collapse(&BvhNode::from(list, time0, time1))
+    }
+
+    pub fn collapse(root: &BvhNode) -> Self {
+        assert!((2..=32).contains(&N), "a wide BVH has 2 to 32 children");
+        let mut bvh = Self {
+            nodes: vec![],
+            primitives: vec![],
+            depth: 0,
+            bbox: None,
+        };
+        if let BvhContent::Leaf(objects) = &root.content {
+            if objects.is_empty() {
+                return bvh;
+            }
+        }
+        bvh.bbox = Some(root.bvh_box);
+        bvh.push(vec![root], 1);
+        bvh
+    }
+
+    /// Adds a node over `children`, opening the interior ones with the
+    /// largest boxes until there are `N`, and returns its index.
+    fn push(&mut self, mut children: Vec<&BvhNode>, depth: usize) -> u32 {
+        self.depth = self.depth.max(depth);
+        while children.len() < N {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| matches!(c.content, BvhContent::Interior { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    a.bvh_box
+                        .surface_area()
+                        .partial_cmp(&b.bvh_box.surface_area())
+                        .unwrap_or(Ordering::Equal)
+                })
+                .map(|(i, _)| i);
+            let i = match largest {
+                Some(i) => i,
+                None => break,
+            };
+            if let BvhContent::Interior { left, right, .. } = &children.swap_remove(i).content {
+                children.push(left);
+                children.push(right);
+            }
+        }
+
+        let index = self.nodes.len();
+        self.nodes.push(WideBvhNode::empty());
+        for (lane, c) in children.into_iter().enumerate() {
+            self.nodes[index].set_bounds(lane, &c.bvh_box);
+            match &c.content {
+                BvhContent::Leaf(objects) => {
+                    self.nodes[index].child[lane] = self.primitives.len() as u32;
+                    self.nodes[index].count[lane] = objects.len() as u8;
+                    self.primitives.extend(objects.iter().cloned());
+                }
+                BvhContent::Interior { .. } => {
+                    let child = self.push(vec![c], depth + 1);
+                    self.nodes[index].child[lane] = child;
+                }
+            }
+        }
+        index as u32
+    }
+}
+
+impl<const N: usize> Hittable for WideBvh<N> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let ray = LaneRay::new(&r);
+        let root = StackEntry {
+            child: 0,
+            count: 0,
+            t: f32::NEG_INFINITY,
+        };
+        // Each node visited leaves at most `N - 1` children behind.
+        let needed = self.depth * (N - 1) + 1;
+        let mut small = [root; STACK_SIZE];
+        let mut large;
+        let stack: &mut [StackEntry] = if needed <= STACK_SIZE {
+            &mut small
+        } else {
+            large = vec![root; needed];
+            &mut large
+        };
+
+        let mut top = 1;
+        let mut closest_so_far = t_max;
+        let mut hit_anything = false;
+        let mut t_near = [0.0; N];
+        while top > 0 {
+            top -= 1;
+            let entry = stack[top];
+            if entry.t as f64 > closest_so_far {
+                continue;
+            }
+            if entry.count > 0 {
+                let first = entry.child as usize;
+                for object in &self.primitives[first..first + entry.count as usize] {
+                    if object.hit(r, t_min, closest_so_far, rec) {
+                        hit_anything = true;
+                        closest_so_far = rec.t;
+                    }
+                }
+                continue;
+            }
+
+            let node = &self.nodes[entry.child as usize];
+            let mut mask = node.intersect(
+                &ray,
+                t_min as f32,
+                closest_so_far as f32 * FAR_SLOP,
+                &mut t_near,
+            );
+            // Push the children hit with the nearest on top.
+            let first = top;
+            while mask != 0 {
+                let lane = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+                if node.child[lane] == EMPTY {
+                    continue;
+                }
+                stack[top] = StackEntry {
+                    child: node.child[lane],
+                    count: node.count[lane],
+                    t: t_near[lane],
+                };
+                let mut j = top;
+                while j > first && stack[j - 1].t < stack[j].t {
+                    stack.swap(j - 1, j);
+                    j -= 1;
+                }
+                top += 1;
+            }
+        }
+        hit_anything
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let ray = LaneRay::new(&r);
+        let root = StackEntry {
+            child: 0,
+            count: 0,
+            t: f32::NEG_INFINITY,
+        };
+        let needed = self.depth * (N - 1) + 1;
+        let mut small = [root; STACK_SIZE];
+        let mut large;
+        let stack: &mut [StackEntry] = if needed <= STACK_SIZE {
+            &mut small
+        } else {
+            large = vec![root; needed];
+            &mut large
+        };
+
+        let mut top = 1;
+        let mut t_near = [0.0; N];
+        while top > 0 {
+            top -= 1;
+            let entry = stack[top];
+            if entry.count > 0 {
+                let first = entry.child as usize;
+                let objects = &self.primitives[first..first + entry.count as usize];
+                if objects.iter().any(|o| o.occluded(r, t_min, t_max)) {
+                    return true;
+                }
+                continue;
+            }
+
+            let node = &self.nodes[entry.child as usize];
+            let mut mask = node.intersect(&ray, t_min as f32, t_max as f32 * FAR_SLOP, &mut t_near);
+            // Any hit will do, so the children go on unsorted.
+            while mask != 0 {
+                let lane = mask.trailing_zeros() as usize;
+                mask &= mask - 1;
+                if node.child[lane] == EMPTY {
+                    continue;
+                }
+                stack[top] = StackEntry {
+                    child: node.child[lane],
+                    count: node.count[lane],
+                    t: t_near[lane],
+                };
+                top += 1;
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        match self.bbox {
+            Some(b) => {
+                *output_box = b;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    /// Spheres far from the origin, where `f32` cannot hold the ray
+    /// origins exactly, hit by rays that run almost along a slab so the
+    /// rounding moves the slab a long way along the ray.
+    #[test]
+    fn matches_list_far_from_origin() {
+        let mat: Arc<dyn Material> = Arc::new(Lambertian::new_by_color(Vec3::new(0.5, 0.5, 0.5)));
+        let offset = Vec3::new(1e5, -2e5, 3e5);
+        let mut list = HittableList::new();
+        for _i in 0..200 {
+            let centre = offset + Vec3::random2(-10.0, 10.0);
+            list.add(Arc::new(Sphere::new(
+                centre,
+                random_double2(0.05, 0.5),
+                mat.clone(),
+            )));
+        }
+        let bvh4 = Bvh4::from(&mut list.clone(), 0.0, 1.0);
+        let bvh8 = Bvh8::from(&mut list.clone(), 0.0, 1.0);
+
+        let mut misses = 0;
+        for i in 0..20000 {
+            let orig = offset + Vec3::random2(-12.0, 12.0);
+            let mut dir = Vec3::random2(-1.0, 1.0);
+            match i % 3 {
+                0 => dir.x *= 1e-4,
+                1 => dir.y *= 1e-4,
+                _ => dir.z *= 1e-4,
+            }
+            let r = Ray::new(orig, dir, 0.0);
+            let mut expected = HitRecord::new0();
+            let hit = list.hit(r, 0.001, INFINITY, &mut expected);
+            for bvh in [&bvh4 as &dyn Hittable, &bvh8].iter() {
+                let mut rec = HitRecord::new0();
+                if bvh.hit(r, 0.001, INFINITY, &mut rec) != hit
+                    || (hit && rec.t != expected.t)
+                    || bvh.occluded(r, 0.001, INFINITY) != hit
+                {
+                    misses += 1;
+                }
+            }
+        }
+        assert_eq!(misses, 0);
+    }
+}